@@ -0,0 +1,177 @@
+//! `cpm build` - run a package action on a source file.
+
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+
+use cpm_core::action::DEFAULT_MAX_T_STATES;
+use cpm_core::{
+    find_action, run_action, ActionResult, CpmEmulator, DriveFS, HeadlessConsole, OverlayDriveFS,
+    PackageDriveFS, TrustedKeys,
+};
+
+use crate::{host_file_name, load_zip, resolve_packages, trusted_keys, warn_conflicts};
+
+/// Arguments for `cpm build`.
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// Source file to build (copied to B:)
+    source: PathBuf,
+
+    /// Tool packages (.zip, mounted on A:) and extra files for B:
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// Action id to run instead of the first one matching the source
+    #[arg(long, value_name = "ID")]
+    action: Option<String>,
+
+    /// Give up after this many T-states
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_T_STATES)]
+    max_t_states: u64,
+
+    /// Run deterministically and write a receipt of the build's inputs
+    /// and outputs, for `cpm verify`
+    #[arg(long, value_name = "FILE")]
+    receipt: Option<PathBuf>,
+
+    /// Only load packages signed by a key in this file
+    #[arg(long, value_name = "FILE")]
+    trusted_keys: Option<PathBuf>,
+}
+
+/// Run the action and write its output files next to the source.
+pub fn run(args: BuildArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = trusted_keys(args.trusted_keys.as_deref())?;
+    let result = build(
+        &args.source,
+        &args.files,
+        args.action.as_deref(),
+        args.max_t_states,
+        args.receipt.is_some(),
+        keys.as_ref(),
+        args.receipt
+            .as_deref()
+            .map(|r| r.parent().unwrap_or_else(|| ".".as_ref())),
+    )?;
+    print!("{}", result.output);
+
+    if let Some(path) = &args.receipt {
+        std::fs::write(path, result.receipt.to_json()?)?;
+        eprintln!("Wrote receipt {}", path.display());
+    }
+
+    if result.timed_out {
+        return Err(format!("Timed out after {} T-states", args.max_t_states).into());
+    }
+    if result.outputs.is_empty() {
+        return Err("No output files produced".into());
+    }
+
+    let dir = args.source.parent().unwrap_or_else(|| ".".as_ref());
+    for (name, data) in &result.outputs {
+        let path = dir.join(name);
+        std::fs::write(&path, data)?;
+        eprintln!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Mount the packages on A: and the source and other files on B:, then run
+/// the action. The receipt names the host files relative to `receipt_dir`
+/// where they lie under it, so `cpm verify` can find them from there.
+pub fn build(
+    source: &Path,
+    files: &[PathBuf],
+    action: Option<&str>,
+    max_t_states: u64,
+    deterministic: bool,
+    keys: Option<&TrustedKeys>,
+    receipt_dir: Option<&Path>,
+) -> Result<ActionResult, Box<dyn std::error::Error>> {
+    let name = |path: &Path| receipt_name(path, receipt_dir);
+    let mut work = OverlayDriveFS::new(PackageDriveFS::new());
+    let mut loaded = Vec::new();
+    let mut packages = Vec::new();
+    let mut extra_files = Vec::new();
+
+    for path in files {
+        let is_zip = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+        if is_zip {
+            let pkg = load_zip(path, keys)?;
+            eprintln!(
+                "Loaded package: {} ({} files)",
+                pkg.manifest.name,
+                pkg.files.len()
+            );
+            loaded.push(pkg);
+            packages.push((name(path), std::fs::read(path)?));
+        } else {
+            work.write_file(&host_file_name(path), &std::fs::read(path)?)?;
+            extra_files.push(name(path));
+        }
+    }
+
+    // Dependencies among the given packages decide the order
+    let tools = PackageDriveFS::from_packages(resolve_packages(loaded, None, keys)?);
+    warn_conflicts(&tools);
+
+    let source_name = host_file_name(source);
+    work.write_file(&source_name, &std::fs::read(source)?)?;
+
+    let actions = tools.get_actions().to_vec();
+    let action = match action {
+        Some(id) => actions.iter().find(|a| a.id.eq_ignore_ascii_case(id)),
+        None => find_action(&actions, &source_name),
+    }
+    .ok_or_else(|| format!("No action for {}", source_name))?;
+    eprintln!("Running {} on {}", action.name, source_name);
+
+    let mut emu = CpmEmulator::new(HeadlessConsole::new());
+    emu.set_deterministic(deterministic);
+    emu.mount(0, OverlayDriveFS::new(tools));
+    emu.mount(1, work);
+
+    let mut result = run_action(&mut emu, action, 1, &source_name, max_t_states)?;
+    let receipt = &mut result.receipt;
+    for (name, data) in &packages {
+        receipt.add_package(name, data);
+    }
+    receipt.config.source = Some(name(source));
+    receipt.config.extra_files = extra_files;
+    Ok(result)
+}
+
+/// How a receipt in `dir` names the host file `path`: relative to `dir`,
+/// so the receipt can move with the files. Without a directory, or if
+/// either cannot be resolved, the path is kept as given.
+fn receipt_name(path: &Path, dir: Option<&Path>) -> String {
+    let resolve = |dir: &Path| {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        Some((dir.canonicalize().ok()?, path.canonicalize().ok()?))
+    };
+    let Some((dir, file)) = dir.and_then(resolve) else {
+        return path.display().to_string();
+    };
+    let common = dir
+        .components()
+        .zip(file.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return file.display().to_string();
+    }
+    let mut relative = PathBuf::new();
+    for _ in dir.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(file.components().skip(common));
+    relative.display().to_string()
+}
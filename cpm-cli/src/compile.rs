@@ -0,0 +1,91 @@
+//! `cpm compile` - build a source file with a package toolchain.
+
+use std::path::PathBuf;
+
+use clap::Args;
+
+use cpm_core::{ManifestToolchain, PackageDriveFS, Source, Toolchain};
+
+use crate::{host_file_name, load_zip, resolve_packages, trusted_keys, warn_conflicts};
+
+/// Arguments for `cpm compile`.
+#[derive(Args, Debug)]
+pub struct CompileArgs {
+    /// Source file
+    source: PathBuf,
+
+    /// Tool packages (.zip)
+    #[arg(required = true)]
+    packages: Vec<PathBuf>,
+
+    /// Language (action id such as bdsc, lasm3, turbo3); defaults to the
+    /// first tool matching the source extension
+    #[arg(long, value_name = "ID")]
+    lang: Option<String>,
+
+    /// Give up after this many T-states
+    #[arg(long, value_name = "N")]
+    max_t_states: Option<u64>,
+
+    /// Only load packages signed by a key in this file
+    #[arg(long, value_name = "FILE")]
+    trusted_keys: Option<PathBuf>,
+}
+
+/// Compile, print diagnostics, and write the binary and listing next to
+/// the source.
+pub fn run(args: CompileArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = trusted_keys(args.trusted_keys.as_deref())?;
+    let mut packages = Vec::new();
+    for path in &args.packages {
+        packages.push(load_zip(path, keys.as_ref())?);
+    }
+    let packages = resolve_packages(packages, None, keys.as_ref())?;
+    warn_conflicts(&PackageDriveFS::from_packages(packages.clone()));
+    let mut toolchain = ManifestToolchain::new(packages);
+    toolchain.max_t_states = args.max_t_states;
+
+    let source_name = host_file_name(&args.source);
+    let text = std::fs::read(&args.source)?;
+    let source = Source::text(&source_name, &String::from_utf8_lossy(&text));
+
+    let lang = match args.lang {
+        Some(lang) => lang,
+        None => toolchain.language_for(&source_name).ok_or_else(|| {
+            format!(
+                "No tool for {} (available: {})",
+                source_name,
+                toolchain.languages().join(", ")
+            )
+        })?,
+    };
+
+    let result = toolchain.compile(&source, &lang)?;
+    print!("{}", result.output);
+    // Point diagnostics at the host path so editors can jump to them
+    let host_path = args.source.display().to_string();
+    for error in &result.errors {
+        let mut error = error.clone();
+        if error
+            .file
+            .as_deref()
+            .is_some_and(|f| f.eq_ignore_ascii_case(&source_name))
+        {
+            error.file = Some(host_path.clone());
+        }
+        eprintln!("{}", error);
+    }
+
+    let dir = args.source.parent().unwrap_or_else(|| ".".as_ref());
+    for (name, data) in result.binary.iter().chain(&result.listing) {
+        let path = dir.join(name);
+        std::fs::write(&path, data)?;
+        eprintln!("Wrote {}", path.display());
+    }
+
+    if result.success() {
+        Ok(())
+    } else {
+        Err(format!("{} failed", source_name).into())
+    }
+}
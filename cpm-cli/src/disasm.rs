@@ -0,0 +1,66 @@
+//! `cpm disasm` - disassemble a CP/M binary.
+
+use std::path::{Path, PathBuf};
+
+use clap::{Args, ValueEnum};
+
+use cpm_core::disasm::{Disassembler, Syntax};
+use cpm_core::SymbolTable;
+
+/// Mnemonic set for `--syntax`.
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum SyntaxArg {
+    Z80,
+    #[value(name = "8080")]
+    I8080,
+}
+
+/// Arguments for `cpm disasm`.
+#[derive(Args, Debug)]
+pub struct DisasmArgs {
+    /// Binary to disassemble (.COM or raw image)
+    file: PathBuf,
+
+    /// Load address of the first byte
+    #[arg(long, default_value = "0x100", value_parser = crate::parse_address)]
+    org: u16,
+
+    /// Mnemonic syntax
+    #[arg(long, value_enum, default_value = "z80")]
+    syntax: SyntaxArg,
+
+    /// Don't annotate BDOS calls
+    #[arg(long)]
+    no_bdos: bool,
+
+    /// Symbol file (.SYM or .PRN); defaults to one next to the binary
+    #[arg(long, value_name = "PATH")]
+    symbols: Option<PathBuf>,
+}
+
+/// Find `NAME.SYM` or `NAME.PRN` next to a binary (either case).
+pub fn symbol_file_for(path: &Path) -> Option<PathBuf> {
+    ["SYM", "sym", "PRN", "prn"]
+        .iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+/// Run the disassembler and print the listing to stdout.
+pub fn run(args: DisasmArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(&args.file)?;
+
+    let mut dis = Disassembler::new(match args.syntax {
+        SyntaxArg::Z80 => Syntax::Z80,
+        SyntaxArg::I8080 => Syntax::I8080,
+    });
+    dis.annotate_bdos = !args.no_bdos;
+
+    if let Some(sym_path) = args.symbols.or_else(|| symbol_file_for(&args.file)) {
+        let data = std::fs::read(&sym_path)?;
+        dis.symbols = SymbolTable::parse(&sym_path.to_string_lossy(), &data);
+    }
+
+    print!("{}", dis.listing(&data, args.org));
+    Ok(())
+}
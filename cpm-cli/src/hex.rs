@@ -0,0 +1,61 @@
+//! `cpm hex2com` / `cpm com2hex` - convert between Intel HEX and .COM.
+
+use std::path::PathBuf;
+
+use clap::Args;
+
+use cpm_core::HexImage;
+
+/// Arguments for `cpm hex2com`.
+#[derive(Args, Debug)]
+pub struct Hex2ComArgs {
+    /// Intel HEX file
+    file: PathBuf,
+
+    /// Output file; defaults to the input with a .COM extension
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+/// Arguments for `cpm com2hex`.
+#[derive(Args, Debug)]
+pub struct Com2HexArgs {
+    /// Binary to convert (.COM or raw image)
+    file: PathBuf,
+
+    /// Output file; defaults to the input with a .HEX extension
+    #[arg(short, long, value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Load address of the first byte
+    #[arg(long, default_value = "0x100", value_parser = crate::parse_address)]
+    org: u16,
+
+    /// Start address written to the end record
+    #[arg(long, value_parser = crate::parse_address)]
+    start: Option<u16>,
+}
+
+/// Convert HEX to a .COM image, as LOAD.COM would.
+pub fn run_hex2com(args: Hex2ComArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let image = HexImage::parse(&std::fs::read(&args.file)?)?;
+    let com = image.to_com()?;
+    let path = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("COM"));
+    std::fs::write(&path, &com)?;
+    eprintln!("Wrote {} ({} bytes)", path.display(), com.len());
+    Ok(())
+}
+
+/// Convert a binary to HEX.
+pub fn run_com2hex(args: Com2HexArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut image = HexImage::from_binary(args.org, &std::fs::read(&args.file)?);
+    image.start = args.start;
+    let path = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("HEX"));
+    std::fs::write(&path, image.to_hex())?;
+    eprintln!("Wrote {}", path.display());
+    Ok(())
+}
@@ -6,33 +6,60 @@
 //! Examples:
 //!   cpm cpm22.zip                    # Load cpm22.zip, find and run shell
 //!   cpm cpm22.zip utilities.zip      # Load multiple packages
+//!   cpm bds-c.zip --package-dir pkgs # Load bds-c and what it depends on
 //!   cpm cpm22.zip -- STAT            # Run STAT command directly
 //!   cpm cpm22.zip hello.com          # Load package + add hello.com to A:
 //!   cpm hello.com                    # Run hello.com directly (no shell)
+//!   cpm hello.hex                    # Run an Intel HEX file like a .COM
+//!   cpm --builtin-ccp a.com b.com    # Built-in command processor
+//!   cpm --gdb 1234 hello.com         # Debug hello.com with a GDB client
+//!   cpm --trace-format json --trace-file trace.jsonl hello.com
+//!   cpm --profile out.folded --profile-format folded hello.com
+//!   cpm --receipt run.json hello.com # Record the run's inputs and outputs
+//!   cpm disasm hello.com --org 0x100 # Disassemble a binary
+//!   cpm build FOO.ASM asm.zip        # Run the package action for FOO.ASM
+//!   cpm build FOO.ASM asm.zip --receipt foo.json
+//!   cpm verify foo.json              # Rebuild and compare with the receipt
+//!   cpm compile hello.c bds-c.zip --lang bdsc
+//!   cpm hex2com hello.hex            # Convert HEX to HELLO.COM
 
 use std::io::Write;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::time::Duration;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use crossterm::{
     event::{self, Event, KeyCode, KeyModifiers},
     terminal::{disable_raw_mode, enable_raw_mode},
 };
 use tokio::sync::mpsc as tokio_mpsc;
 
+mod build;
+mod compile;
+mod disasm;
+mod hex;
+mod verify;
+
+use cpm_core::gdb::{GdbStub, SessionEnd};
 use cpm_core::{
-    load_package_from_path, CpmConsole, CpmEmulator, DriveFS, ExitReason, OverlayDriveFS,
-    PackageDriveFS,
+    load_package_from_path, load_signed_package, Ccp, CpmConsole, CpmEmulator, CpmExitInfo,
+    DriveFS, ExitReason, FileDigest, JsonSink, LoadedPackage, MemoryBank, MemoryMap,
+    OverlayDriveFS, PackageDriveFS, PackageResolver, Receipt, RunConfig, TextSink, Throttle,
+    Timer, TraceSink, TrustedKeys,
 };
+use cpm_core::io::Rtc;
 
 /// CP/M Emulator CLI
 #[derive(Parser, Debug)]
 #[command(name = "cpm")]
 #[command(about = "Run CP/M programs")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Package ZIP files or .COM executables to load
+    #[command(subcommand)]
+    subcommand: Option<Commands>,
+
+    /// Package ZIP files or .COM/.HEX/.PRL executables to load
     #[arg(required = true)]
     files: Vec<PathBuf>,
 
@@ -40,17 +67,250 @@ struct Args {
     #[arg(short, long)]
     trace: bool,
 
+    /// Trace output format (implies --trace)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    trace_format: Option<TraceFormat>,
+
+    /// Write trace output to a file instead of stderr (implies --trace)
+    #[arg(long, value_name = "PATH")]
+    trace_file: Option<PathBuf>,
+
+    /// Profile execution and write the result to this file
+    #[arg(long, value_name = "PATH")]
+    profile: Option<PathBuf>,
+
+    /// Profile output format
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t)]
+    profile_format: ProfileFormat,
+
+    /// Use the built-in command processor instead of a CCP.COM-style shell
+    #[arg(long)]
+    builtin_ccp: bool,
+
+    /// Wait for a GDB remote debugger on this local TCP port
+    #[arg(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Memory size of the emulated system, as in "48K CP/M" (20k-64k);
+    /// defaults to a 62K TPA with the BDOS at 0FE00H
+    #[arg(long, value_name = "SIZE", value_parser = parse_memory_map)]
+    tpa: Option<MemoryMap>,
+
+    /// Raise a timer interrupt every this many T-states (80000 is 50 Hz
+    /// at 4 MHz)
+    #[arg(long, value_name = "T-STATES", value_parser = clap::value_parser!(u64).range(1..))]
+    timer: Option<u64>,
+
+    /// Run at this CPU clock in MHz (e.g. 2 or 4) instead of as fast as
+    /// the host allows
+    #[arg(long, value_name = "MHZ", value_parser = parse_throttle)]
+    mhz: Option<Throttle>,
+
+    /// Run the --rtc clock from the T-state counter instead of the host
+    /// clock and leave host time out of the profile. Keys still arrive
+    /// when they are typed, so console timing stays host-driven
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Attach a real-time clock with its register select at this port and
+    /// its data at the next one
+    #[arg(long, value_name = "PORT", value_parser = clap::value_parser!(u8).range(0..=254))]
+    rtc: Option<u8>,
+
+    /// Bank the memory below C000H into this many banks (2-256), selected
+    /// by writing the bank number to port 0FFH
+    #[arg(long, value_name = "COUNT", value_parser = parse_memory_bank)]
+    banks: Option<MemoryBank>,
+
+    /// Imply --deterministic and write a receipt of the run's inputs and
+    /// outputs to this file
+    #[arg(long, value_name = "FILE")]
+    receipt: Option<PathBuf>,
+
+    /// Only load package ZIPs signed by a key in this file, with the
+    /// signature in NAME.zip.sig
+    #[arg(long, value_name = "FILE")]
+    trusted_keys: Option<PathBuf>,
+
+    /// Load the packages the given ones depend on from this directory
+    #[arg(long, value_name = "DIR")]
+    package_dir: Option<PathBuf>,
+
     /// Command and arguments to run (instead of shell)
     #[arg(last = true)]
     command: Vec<String>,
 }
 
+/// Subcommands (running programs is the default).
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Disassemble a Z80/8080 binary
+    Disasm(disasm::DisasmArgs),
+    /// Run a package action (assemble, compile, ...) on a source file
+    Build(build::BuildArgs),
+    /// Compile a source file and report diagnostics
+    Compile(compile::CompileArgs),
+    /// Convert an Intel HEX file to a .COM file
+    Hex2com(hex::Hex2ComArgs),
+    /// Convert a binary to an Intel HEX file
+    Com2hex(hex::Com2HexArgs),
+    /// Re-run a build from its receipt and check the outputs match
+    Verify(verify::VerifyArgs),
+}
+
+/// Trace output formats.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum TraceFormat {
+    /// One human-readable line per event
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Profile output formats.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+enum ProfileFormat {
+    /// Hot spots, pages, BDOS timings and file statistics
+    #[default]
+    Text,
+    /// Folded stacks for flamegraph tools
+    Folded,
+}
+
+/// Build the trace sink selected by the command line, if tracing is enabled.
+fn trace_sink(args: &Args) -> std::io::Result<Option<Box<dyn TraceSink>>> {
+    if !args.trace && args.trace_format.is_none() && args.trace_file.is_none() {
+        return Ok(None);
+    }
+
+    let out: Box<dyn Write + Send> = match &args.trace_file {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stderr()),
+    };
+    Ok(Some(match args.trace_format.unwrap_or_default() {
+        TraceFormat::Text => Box::new(TextSink::new(out)),
+        TraceFormat::Json => Box::new(JsonSink::new(out)),
+    }))
+}
+
+/// Run the loaded program, under the GDB stub if a port is given.
+fn execute<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+    start_address: u16,
+    gdb_port: Option<u16>,
+) -> cpm_core::CpmResult<CpmExitInfo> {
+    let Some(port) = gdb_port else {
+        return emu.run_from(start_address);
+    };
+
+    // Debug mode: hand control to the GDB stub until it lets go
+    emu.start(start_address);
+    eprintln!("Waiting for GDB on 127.0.0.1:{}...\r", port);
+    let mut stub = GdbStub::listen(("127.0.0.1", port))?;
+    match stub.serve(emu)? {
+        SessionEnd::Exited(info) => Ok(info),
+        SessionEnd::Detached => emu.resume(),
+        SessionEnd::Killed => Ok(CpmExitInfo {
+            reason: ExitReason::Error("Killed by debugger".to_string()),
+            t_states: emu.t_states(),
+            pc: emu.pc(),
+        }),
+    }
+}
+
+/// Upper-case file name of a host path, as it appears on a CP/M drive.
+fn host_file_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("UNKNOWN")
+        .to_uppercase()
+}
+
+/// Keys from a trusted-keys file, if one was given.
+fn trusted_keys(path: Option<&std::path::Path>) -> cpm_core::CpmResult<Option<TrustedKeys>> {
+    path.map(TrustedKeys::load).transpose()
+}
+
+/// Load a package ZIP, requiring a trusted signature when there are keys.
+fn load_zip(
+    path: &std::path::Path,
+    keys: Option<&TrustedKeys>,
+) -> cpm_core::CpmResult<cpm_core::LoadedPackage> {
+    match keys {
+        Some(keys) => load_signed_package(path, keys),
+        None => load_package_from_path(path),
+    }
+}
+
+/// Put packages after their dependencies, loading missing ones from `dir`.
+fn resolve_packages(
+    packages: Vec<LoadedPackage>,
+    dir: Option<&std::path::Path>,
+    keys: Option<&TrustedKeys>,
+) -> cpm_core::CpmResult<Vec<LoadedPackage>> {
+    let resolver = match dir {
+        Some(dir) => PackageResolver::from_dir(dir, keys)?,
+        None => PackageResolver::default(),
+    };
+    let given = packages.len();
+    let resolved = resolver.resolve(packages)?;
+    if resolved.len() > given {
+        let names: Vec<&str> = resolved.iter().map(|p| p.manifest.name.as_str()).collect();
+        eprintln!("Resolved packages: {}", names.join(", "));
+    }
+    Ok(resolved)
+}
+
+/// Report files that one package replaces in another.
+fn warn_conflicts(fs: &PackageDriveFS) {
+    for conflict in fs.conflicts() {
+        eprintln!("Warning: {}", conflict);
+    }
+}
+
+/// Parse a memory size such as `48k`.
+fn parse_memory_map(s: &str) -> Result<MemoryMap, String> {
+    MemoryMap::parse(s).map_err(|e| e.to_string())
+}
+
+/// Parse a bank count for the default banked layout.
+fn parse_memory_bank(s: &str) -> Result<MemoryBank, String> {
+    let count = s
+        .parse()
+        .map_err(|_| format!("invalid bank count: {}", s))?;
+    MemoryBank::with_defaults(count).map_err(|e| e.to_string())
+}
+
+/// Parse a clock speed in MHz.
+fn parse_throttle(s: &str) -> Result<Throttle, String> {
+    let mhz = s
+        .parse()
+        .map_err(|_| format!("invalid clock speed: {}", s))?;
+    Throttle::from_mhz(mhz).map_err(|e| e.to_string())
+}
+
+/// Parse an address such as `0x100`, `100h` or `256`.
+fn parse_address(s: &str) -> Result<u16, String> {
+    let lower = s.trim().to_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        u16::from_str_radix(hex, 16)
+    } else {
+        lower.parse::<u16>()
+    };
+    parsed.map_err(|_| format!("invalid address: {}", s))
+}
+
 /// Channel-based console that communicates via tokio channels.
 struct ChannelConsole {
     /// Receiver for keyboard input
     key_rx: mpsc::Receiver<u8>,
     /// Pending keys (buffered)
     key_buffer: Vec<u8>,
+    /// Every key received, for receipts
+    typed: Vec<u8>,
 }
 
 impl ChannelConsole {
@@ -58,8 +318,25 @@ impl ChannelConsole {
         Self {
             key_rx,
             key_buffer: Vec::new(),
+            typed: Vec::new(),
         }
     }
+
+    /// Non-blocking receive.
+    fn try_recv(&mut self) -> Option<u8> {
+        let ch = self.key_rx.try_recv().ok()?;
+        self.typed.push(ch);
+        Some(ch)
+    }
+
+    /// Blocking receive; a closed channel yields 0.
+    fn recv(&mut self) -> u8 {
+        let Ok(ch) = self.key_rx.recv() else {
+            return 0;
+        };
+        self.typed.push(ch);
+        ch
+    }
 }
 
 impl CpmConsole for ChannelConsole {
@@ -106,9 +383,19 @@ impl CpmConsole for ChannelConsole {
         }
 
         // Try non-blocking receive
-        match self.key_rx.try_recv() {
-            Ok(ch) => Some(ch),
-            Err(_) => None,
+        self.try_recv()
+    }
+
+    fn take_break(&mut self) -> bool {
+        // Pull in type-ahead so the next key can be inspected
+        while let Some(ch) = self.try_recv() {
+            self.key_buffer.push(ch);
+        }
+        if self.key_buffer.first() == Some(&0x03) {
+            self.key_buffer.remove(0);
+            true
+        } else {
+            false
         }
     }
 
@@ -119,10 +406,7 @@ impl CpmConsole for ChannelConsole {
         }
 
         // Blocking receive
-        match self.key_rx.recv() {
-            Ok(ch) => ch,
-            Err(_) => 0, // Channel closed
-        }
+        self.recv()
     }
 }
 
@@ -130,14 +414,11 @@ impl CpmConsole for ChannelConsole {
 fn translate_key(code: KeyCode, modifiers: KeyModifiers) -> Option<u8> {
     // Handle control characters
     if modifiers.contains(KeyModifiers::CONTROL) {
-        match code {
-            KeyCode::Char(c) => {
-                let upper = c.to_ascii_uppercase();
-                if upper.is_ascii_uppercase() {
-                    return Some(upper as u8 - 64); // Ctrl+A=1, Ctrl+C=3, etc.
-                }
+        if let KeyCode::Char(c) = code {
+            let upper = c.to_ascii_uppercase();
+            if upper.is_ascii_uppercase() {
+                return Some(upper as u8 - 64); // Ctrl+A=1, Ctrl+C=3, etc.
             }
-            _ => {}
         }
     }
 
@@ -163,28 +444,34 @@ struct ShellInfo {
 }
 
 /// Find shell file from packages.
-fn find_shell(packages: &[cpm_core::LoadedPackage]) -> Option<ShellInfo> {
+fn find_shell(packages: &[cpm_core::LoadedPackage], map: &MemoryMap) -> Option<ShellInfo> {
     // First, look for shell type in manifest
+    let mut rejected = Vec::new();
     for pkg in packages {
         for file_entry in &pkg.manifest.files {
             if file_entry.file_type.as_deref() == Some("shell") {
                 let filename = cpm_core::to_8_3(&file_entry.src);
                 if let Some(data) = pkg.files.get(&filename) {
                     // Parse load address from manifest (e.g., "0xDC00")
-                    let load_address = file_entry
-                        .load_address
-                        .as_ref()
-                        .and_then(|s| {
-                            let s = s.trim_start_matches("0x").trim_start_matches("0X");
-                            u16::from_str_radix(s, 16).ok()
-                        })
-                        .unwrap_or(0x0100); // Default to TPA
-
-                    return Some(ShellInfo {
-                        name: filename,
-                        data: data.clone(),
-                        load_address,
+                    let load_address = file_entry.load_address.as_ref().and_then(|s| {
+                        let s = s.trim_start_matches("0x").trim_start_matches("0X");
+                        u16::from_str_radix(s, 16).ok()
                     });
+                    // Relocate .PRL shells; others default to TPA and must
+                    // stay clear of the BDOS
+                    match cpm_core::prl::load_shell(&filename, data, load_address, map) {
+                        Ok((load_address, data)) => {
+                            return Some(ShellInfo {
+                                name: filename,
+                                data,
+                                load_address,
+                            })
+                        }
+                        Err(e) => {
+                            eprintln!("Cannot load shell {}: {}", filename, e);
+                            rejected.push(filename);
+                        }
+                    }
                 }
             }
         }
@@ -192,7 +479,7 @@ fn find_shell(packages: &[cpm_core::LoadedPackage]) -> Option<ShellInfo> {
 
     // Fallback: look for known shell names (default to TPA address)
     let shell_names = ["XCCP.COM", "CCP.COM", "ZCCP.COM"];
-    for name in shell_names {
+    for name in shell_names.into_iter().filter(|n| !rejected.iter().any(|r| r == n)) {
         for pkg in packages {
             if let Some(data) = pkg.files.get(name) {
                 return Some(ShellInfo {
@@ -211,9 +498,25 @@ fn find_shell(packages: &[cpm_core::LoadedPackage]) -> Option<ShellInfo> {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
+    if let Some(subcommand) = args.subcommand {
+        return match subcommand {
+            Commands::Disasm(disasm_args) => disasm::run(disasm_args),
+            Commands::Build(build_args) => build::run(build_args),
+            Commands::Compile(compile_args) => compile::run(compile_args),
+            Commands::Hex2com(hex_args) => hex::run_hex2com(hex_args),
+            Commands::Com2hex(hex_args) => hex::run_com2hex(hex_args),
+            Commands::Verify(verify_args) => verify::run(verify_args),
+        };
+    }
+
+    let keys = trusted_keys(args.trusted_keys.as_deref())?;
+
     // Separate packages (.zip) from loose files (.com)
     let mut packages = Vec::new();
     let mut loose_files: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut support_files: Vec<(String, Vec<u8>)> = Vec::new();
+    // Package ZIPs as given, for the receipt
+    let mut package_zips: Vec<(String, Vec<u8>)> = Vec::new();
 
     for path in &args.files {
         let ext = path
@@ -224,7 +527,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         if ext == "ZIP" {
             // Load as package
-            match load_package_from_path(path) {
+            match load_zip(path, keys.as_ref()) {
                 Ok(pkg) => {
                     eprintln!(
                         "Loaded package: {} ({} files)",
@@ -238,6 +541,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     return Err(e.into());
                 }
             }
+            if args.receipt.is_some() {
+                package_zips.push((path.display().to_string(), std::fs::read(path)?));
+            }
         } else if ext == "COM" {
             // Load as loose .COM file
             let filename = path
@@ -255,12 +561,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     return Err(e.into());
                 }
             }
+            // Bring along a symbol file sitting next to it
+            if let Some(sym_path) = disasm::symbol_file_for(path) {
+                support_files.push((host_file_name(&sym_path), std::fs::read(&sym_path)?));
+            }
+        } else if ext == "HEX" {
+            // Convert to a .COM, as LOAD.COM would
+            let image = cpm_core::HexImage::parse(&std::fs::read(path)?)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            let filename = host_file_name(&path.with_extension("COM"));
+            loose_files.push((filename, image.to_com()?));
+            if let Some(sym_path) = disasm::symbol_file_for(path) {
+                support_files.push((host_file_name(&sym_path), std::fs::read(&sym_path)?));
+            }
+        } else if ext == "PRL" {
+            // Relocate to the TPA and run like a .COM
+            let code = cpm_core::PrlImage::parse(&std::fs::read(path)?)?.relocate(0x0100)?;
+            loose_files.push((host_file_name(&path.with_extension("COM")), code));
+        } else if ext == "SYM" || ext == "PRN" {
+            support_files.push((host_file_name(path), std::fs::read(path)?));
         } else {
-            eprintln!("Unknown file type: {} (expected .zip or .com)", path.display());
+            eprintln!(
+                "Unknown file type: {} (expected .zip, .com, .hex, .prl, .sym or .prn)",
+                path.display()
+            );
             return Err(format!("Unknown file type: {}", path.display()).into());
         }
     }
 
+    let packages = resolve_packages(packages, args.package_dir.as_deref(), keys.as_ref())?;
+
     if packages.is_empty() && loose_files.is_empty() {
         eprintln!("No packages or executables loaded");
         return Ok(());
@@ -269,15 +599,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Determine execution mode:
     // 1. If packages with shell: use shell (optionally run command)
     // 2. If only loose .com files: run first one directly at 0x100
-    let shell = find_shell(&packages);
-
-    // Validate we have something to run
-    if shell.is_none() && loose_files.is_empty() {
-        eprintln!("No shell found in packages and no .COM files provided.");
-        return Err("Nothing to run".into());
-    }
+    // 3. Otherwise (or with --builtin-ccp): built-in command processor
+    let memory_map = args.tpa.unwrap_or_default();
+    let shell = if args.builtin_ccp {
+        None
+    } else {
+        find_shell(&packages, &memory_map)
+    };
+    let builtin_ccp = shell.is_none() && (args.builtin_ccp || loose_files.is_empty());
 
-    if let Some(ref s) = shell {
+    if builtin_ccp {
+        eprintln!("Using built-in CCP");
+    } else if let Some(ref s) = shell {
         eprintln!(
             "Using shell: {} (load address: 0x{:04X})",
             s.name, s.load_address
@@ -286,23 +619,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Create filesystem from packages
     let base_fs = PackageDriveFS::from_packages(packages);
+    warn_conflicts(&base_fs);
     let mut overlay_fs = OverlayDriveFS::new(base_fs);
 
     // Add loose .COM files to the overlay (A: drive)
-    for (filename, data) in &loose_files {
+    let mut host_inputs = Vec::new();
+    for (filename, data) in loose_files.iter().chain(&support_files) {
         overlay_fs.write_file(filename, data)?;
+        host_inputs.push(FileDigest::of(format!("A:{}", filename), data));
         //eprintln!("Added {} to A: drive", filename);
     }
 
     // Determine what to run and how
-    let (program_data, start_address, use_shell): (Vec<u8>, u16, bool) = if let Some(s) = shell {
+    let (program_name, program_data, start_address, use_shell) = if builtin_ccp {
+        // Built-in CCP: start at the warm boot vector
+        (String::new(), Vec::new(), 0x0000, false)
+    } else if let Some(s) = shell {
         // Shell mode: load shell, optionally pass command
-        (s.data, s.load_address, true)
+        (s.name, s.data, s.load_address, true)
     } else {
         // Direct mode: run first .com file at TPA (0x100)
         let (first_name, first_data) = loose_files.first().unwrap();
         //eprintln!("Running {} directly", first_name);
-        (first_data.clone(), 0x0100, false)
+        (first_name.clone(), first_data.clone(), 0x0100, false)
     };
 
     // Create channel for keyboard input
@@ -317,31 +656,99 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Enable raw mode (gracefully handle non-TTY)
     let raw_mode_enabled = enable_raw_mode().is_ok();
 
-    let trace = args.trace;
+    let trace_sink = trace_sink(&args)?;
+    let gdb_port = args.gdb;
+    let timer = args.timer.map(Timer::new);
+    let banks = args.banks.clone();
+    let throttle = args.mhz.clone();
+    let receipt_path = args.receipt.clone();
+    let deterministic = args.deterministic || receipt_path.is_some();
+    let rtc_port = args.rtc;
+    let profile = args.profile.clone().map(|path| (path, args.profile_format));
     let command = args.command.clone();
 
     // Spawn emulator in blocking task
     let emu_handle = tokio::task::spawn_blocking(move || {
         let mut emu: CpmEmulator<ChannelConsole, OverlayDriveFS<PackageDriveFS>> =
             CpmEmulator::new(console);
-        emu.trace = trace;
+        emu.set_memory_map(memory_map);
+        emu.set_timer(timer);
+        emu.set_memory_bank(banks);
+        emu.set_throttle(throttle);
+        emu.set_deterministic(deterministic);
+        if let Some(port) = rtc_port {
+            let rtc = Rtc::new(emu.time_source());
+            emu.io_mut().attach(port..=port + 1, Box::new(rtc))?;
+        }
+        if let Some(sink) = trace_sink {
+            emu.set_trace_sink(sink);
+        }
         emu.mount(0, overlay_fs);
 
-        if use_shell {
+        if builtin_ccp {
+            let mut ccp = Ccp::new();
+            if !command.is_empty() {
+                ccp.push_command(&command.join(" "));
+            }
+            emu.set_builtin_shell(ccp);
+        } else if use_shell {
             // Shell mode: set shell for warm boot, pass command as args
             emu.set_shell(&program_data, start_address);
         } else {
             // Direct mode: load program at TPA, no shell for warm boot
             emu.load_at(start_address, &program_data);
+            emu.load_symbols_for(0, &program_name);
         }
 
         // Set command line args (works for both shell and direct mode)
-        if !command.is_empty() {
+        if !command.is_empty() && !builtin_ccp {
             let cmd_line = command.join(" ");
             emu.set_args(&cmd_line);
         }
 
-        emu.run_from(start_address)
+        if profile.is_some() {
+            emu.enable_profiling();
+        }
+        emu.record_files(receipt_path.is_some());
+
+        let result = execute(&mut emu, start_address, gdb_port);
+
+        if let Some(path) = &receipt_path {
+            let mut receipt = Receipt::new(
+                RunConfig::of(&emu, 0),
+                &command.join(" "),
+                &emu.console().typed,
+            );
+            for (name, zip) in &package_zips {
+                receipt.add_package(name, zip);
+            }
+            receipt.record_files(&emu);
+            // Loose files are inputs even when run without opening them
+            for file in host_inputs {
+                if !receipt.inputs.iter().any(|f| f.name == file.name) {
+                    receipt.inputs.push(file);
+                }
+            }
+            receipt.inputs.sort();
+            let written = receipt
+                .to_json()
+                .and_then(|json| Ok(std::fs::write(path, json)?));
+            if let Err(e) = written {
+                eprintln!("Failed to write receipt {}: {}\r", path.display(), e);
+            }
+        }
+
+        if let (Some((path, format)), Some(profiler)) = (&profile, emu.profiler()) {
+            let text = match format {
+                ProfileFormat::Text => profiler.report(20, emu.symbols()),
+                ProfileFormat::Folded => profiler.folded(emu.symbols()),
+            };
+            if let Err(e) = std::fs::write(path, text) {
+                eprintln!("Failed to write profile {}: {}\r", path.display(), e);
+            }
+        }
+
+        result
     });
 
     // Spawn terminal input reader
@@ -0,0 +1,61 @@
+//! `cpm verify` - re-run a build from its receipt and compare.
+
+use std::path::PathBuf;
+
+use clap::Args;
+
+use cpm_core::Receipt;
+
+use crate::{build, trusted_keys};
+
+/// Arguments for `cpm verify`.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// Receipt written by `cpm build --receipt`
+    receipt: PathBuf,
+
+    /// Only load packages signed by a key in this file
+    #[arg(long, value_name = "FILE")]
+    trusted_keys: Option<PathBuf>,
+}
+
+/// Build again from the files named in the receipt, without writing the
+/// outputs, and report every digest that differs. Relative paths in the
+/// receipt are taken from the receipt's directory.
+pub fn run(args: VerifyArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = trusted_keys(args.trusted_keys.as_deref())?;
+    let receipt = Receipt::from_json(&std::fs::read_to_string(&args.receipt)?)?;
+    let dir = args.receipt.parent().unwrap_or_else(|| ".".as_ref());
+    let source = receipt
+        .config
+        .source
+        .as_ref()
+        .ok_or("Receipt does not come from cpm build")?;
+    let files: Vec<PathBuf> = receipt
+        .packages
+        .iter()
+        .map(|p| &p.name)
+        .chain(&receipt.config.extra_files)
+        .map(|name| dir.join(name))
+        .collect();
+
+    let result = build::build(
+        &dir.join(source),
+        &files,
+        receipt.config.action.as_deref(),
+        receipt.config.max_t_states,
+        receipt.config.deterministic,
+        keys.as_ref(),
+        Some(dir),
+    )?;
+
+    let mismatches = receipt.mismatches(&result.receipt);
+    if mismatches.is_empty() {
+        eprintln!("Verified {} outputs", receipt.outputs.len());
+        return Ok(());
+    }
+    for mismatch in &mismatches {
+        eprintln!("{}", mismatch);
+    }
+    Err(format!("{} mismatches", mismatches.len()).into())
+}
@@ -0,0 +1,261 @@
+//! Package action executor.
+//!
+//! Runs a `PackageAction` against a source file: the expanded submit
+//! template is typed at the built-in CCP one command per prompt, each
+//! `InteractiveStep` answers its prompt once the text appears in the console
+//! output, and files with an extension listed in `output_exts` that were
+//! created or changed on the source drive are collected. The result
+//! carries a `Receipt` of the session's inputs and outputs.
+
+use std::collections::HashMap;
+
+use crate::ccp::Ccp;
+use crate::console::HeadlessConsole;
+use crate::emulator::CpmEmulator;
+use crate::error::{CpmError, CpmResult};
+use crate::fs::DriveFS;
+use crate::package::{action_matches_file, PackageAction};
+use crate::receipt::{Receipt, RunConfig};
+use crate::{CpmExitInfo, ExitReason};
+
+/// Default execution budget for an action.
+pub const DEFAULT_MAX_T_STATES: u64 = 1_000_000_000;
+
+/// Outcome of running an action.
+#[derive(Debug, Clone)]
+pub struct ActionResult {
+    pub exit_info: CpmExitInfo,
+    /// Console output.
+    pub output: String,
+    /// Output files created or changed on the source drive, by name.
+    pub outputs: Vec<(String, Vec<u8>)>,
+    /// Interactive steps whose prompt was seen and answered.
+    pub steps_completed: usize,
+    /// The T-state budget ran out before the session ended.
+    pub timed_out: bool,
+    /// Digests of the command, replies, files read and files written.
+    pub receipt: Receipt,
+}
+
+/// First action whose patterns match `filename`.
+pub fn find_action<'a>(actions: &'a [PackageAction], filename: &str) -> Option<&'a PackageAction> {
+    actions
+        .iter()
+        .find(|action| action_matches_file(action, filename))
+}
+
+/// Run `action` on `filename` from `drive` (0 = A:) with the built-in CCP
+/// as the shell. The tools must be reachable from the commands in the
+/// template; the session starts on A:.
+pub fn run_action<D: DriveFS>(
+    emu: &mut CpmEmulator<HeadlessConsole, D>,
+    action: &PackageAction,
+    drive: u8,
+    filename: &str,
+    max_t_states: u64,
+) -> CpmResult<ActionResult> {
+    let letter = (b'A' + drive) as char;
+    let fs = emu.drive(drive).ok_or(CpmError::DriveNotMounted(letter))?;
+    let base_name = filename
+        .rsplit_once('.')
+        .map_or(filename, |(name, _)| name)
+        .to_uppercase();
+    let before: HashMap<String, Vec<u8>> = fs
+        .list_files()
+        .into_iter()
+        .filter_map(|name| fs.read_file(&name).map(|data| (name, data)))
+        .collect();
+
+    let commands = action.submit_lines(&base_name, letter);
+    let mut ccp = Ccp::new();
+    for line in &commands {
+        ccp.push_command(line);
+    }
+    emu.set_builtin_shell(ccp);
+    emu.record_files(true);
+    emu.start(0);
+
+    let steps = action.interactive_script.as_deref().unwrap_or_default();
+    let mut steps_completed = 0;
+    let mut mark = 0;
+    let mut seen = 0;
+    let mut replies = String::new();
+    let deadline = emu.t_states().saturating_add(max_t_states);
+
+    let (exit_info, timed_out) = loop {
+        if let Some(info) = emu.step()? {
+            break (info, false);
+        }
+
+        // Answer the next prompt once its text has been printed
+        let output = emu.console().output();
+        if steps_completed < steps.len() && output.len() != seen {
+            seen = output.len();
+            let step = &steps[steps_completed];
+            if contains(&output[mark..], step.wait.as_bytes()) {
+                let send = step
+                    .send
+                    .replace("{drive}", &letter.to_string())
+                    .replace("{name}", &base_name);
+                mark = output.len();
+                emu.console_mut().queue_string(&send);
+                replies.push_str(&send);
+                steps_completed += 1;
+            }
+        }
+
+        if emu.t_states() >= deadline {
+            let info = CpmExitInfo {
+                reason: ExitReason::Error(format!("Timed out after {} T-states", max_t_states)),
+                t_states: emu.t_states(),
+                pc: emu.pc(),
+            };
+            break (info, true);
+        }
+    };
+
+    let mut outputs = Vec::new();
+    if let Some(fs) = emu.drive(drive) {
+        let mut names = fs.list_files();
+        names.sort();
+        for name in names {
+            let ext = name.rsplit_once('.').map_or("", |(_, ext)| ext);
+            if !action
+                .output_exts
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(ext))
+            {
+                continue;
+            }
+            if let Some(data) = fs.read_file(&name) {
+                if before.get(&name) != Some(&data) {
+                    outputs.push((name, data));
+                }
+            }
+        }
+    }
+
+    let mut config = RunConfig::of(emu, max_t_states);
+    config.action = Some(action.id.clone());
+    let mut receipt = Receipt::new(config, &commands.join("\n"), replies.as_bytes());
+    receipt.record_files(emu);
+    emu.record_files(false);
+
+    Ok(ActionResult {
+        exit_info,
+        output: emu.console().output_string(),
+        outputs,
+        steps_completed,
+        timed_out,
+        receipt,
+    })
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::InteractiveStep;
+    use crate::receipt::{sha256_hex, FileDigest};
+    use crate::MemoryDriveFS;
+
+    /// Print "Make?", read a line with BDOS 10, then create the FCB1 file.
+    const MAKER_COM: [u8; 39] = [
+        0x11, 0x20, 0x01, // 0100: LD DE,0120
+        0x0E, 0x09, // LD C,9
+        0xCD, 0x05, 0x00, // CALL 5
+        0x11, 0x26, 0x01, // LD DE,0126
+        0x0E, 0x0A, // LD C,10
+        0xCD, 0x05, 0x00, // CALL 5
+        0x11, 0x5C, 0x00, // LD DE,005C
+        0x0E, 0x16, // LD C,22 (make file)
+        0xCD, 0x05, 0x00, // CALL 5
+        0x0E, 0x10, // LD C,16 (close)
+        0xCD, 0x05, 0x00, // CALL 5
+        0xC3, 0x00, 0x00, // JP 0
+        b'M', b'a', b'k', b'e', b'?', b'$', // 0120: "Make?$"
+        0x10, // 0126: max 16
+    ];
+
+    fn action(submit: &str, script: Option<Vec<InteractiveStep>>) -> PackageAction {
+        PackageAction {
+            id: "make".to_string(),
+            name: "Make".to_string(),
+            command: "MAKER".to_string(),
+            patterns: vec!["*.SRC".to_string()],
+            output_exts: vec!["OUT".to_string()],
+            submit: Some(submit.to_string()),
+            interactive_script: script,
+            package: None,
+        }
+    }
+
+    fn emulator() -> CpmEmulator<HeadlessConsole, MemoryDriveFS> {
+        let mut tools = MemoryDriveFS::new();
+        tools.add_file("MAKER.COM", MAKER_COM.to_vec());
+        let mut source = MemoryDriveFS::new();
+        source.add_file("HELLO.SRC", b"source".to_vec());
+        source.add_file("OLD.OUT", b"untouched".to_vec());
+
+        let mut emu = CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(0, tools);
+        emu.mount(1, source);
+        emu
+    }
+
+    #[test]
+    fn test_find_action() {
+        let actions = [action("", None)];
+        assert_eq!(find_action(&actions, "HELLO.SRC").unwrap().id, "make");
+        assert!(find_action(&actions, "HELLO.TXT").is_none());
+    }
+
+    #[test]
+    fn test_interactive_action() {
+        let script = vec![InteractiveStep {
+            wait: "Make?".to_string(),
+            send: "{drive}:{name}\r".to_string(),
+        }];
+        let mut emu = emulator();
+        let result = run_action(
+            &mut emu,
+            &action("{drive}:\rA:MAKER {name}.OUT\r", Some(script)),
+            1,
+            "hello.src",
+            DEFAULT_MAX_T_STATES,
+        )
+        .unwrap();
+
+        assert!(!result.timed_out);
+        assert_eq!(result.steps_completed, 1);
+        assert!(
+            result
+                .output
+                .contains("B>A:MAKER HELLO.OUT\r\nMake?B:HELLO\r\n"),
+            "{}",
+            result.output
+        );
+        let names: Vec<&str> = result.outputs.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["HELLO.OUT"]);
+
+        let receipt = &result.receipt;
+        assert_eq!(receipt.command, "B:\nA:MAKER HELLO.OUT");
+        assert_eq!(receipt.input_sha256, sha256_hex(b"B:HELLO\r"));
+        assert_eq!(receipt.outputs, [FileDigest::of("B:HELLO.OUT", b"")]);
+    }
+
+    #[test]
+    fn test_action_timeout() {
+        let mut emu = emulator();
+        emu.drive_mut(0)
+            .unwrap()
+            .add_file("LOOP.COM", vec![0x18, 0xFE]); // JR $
+        let result = run_action(&mut emu, &action("LOOP\r", None), 1, "HELLO.SRC", 10_000).unwrap();
+
+        assert!(result.timed_out);
+        assert!(result.outputs.is_empty());
+    }
+}
@@ -0,0 +1,200 @@
+//! Banked memory for CP/M 3 and MP/M style systems.
+//!
+//! The address space is split at `common_base`: everything from there up
+//! is common to all banks, everything below belongs to the selected bank.
+//! Writing a bank number to the select port switches banks.
+//!
+//! The emulator's 64K array always holds what the CPU sees, so the flat
+//! case, `memory()` and the debugger need no indirection: switching copies
+//! the banked area of the outgoing bank out and the incoming one in.
+//!
+//! Enabled with the `banked` feature.
+
+use crate::error::{CpmError, CpmResult};
+
+/// Start of the common area unless configured otherwise.
+pub const DEFAULT_COMMON_BASE: u16 = 0xC000;
+
+/// Bank select port unless configured otherwise.
+pub const DEFAULT_SELECT_PORT: u8 = 0xFF;
+
+/// Banks below a common area, switched through an output port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryBank {
+    common_base: u16,
+    port: u8,
+    /// Contents of each bank's area. The selected bank's entry is stale;
+    /// its bytes live in the CPU's memory.
+    banks: Vec<Vec<u8>>,
+    current: u8,
+}
+
+impl MemoryBank {
+    /// `count` zeroed banks (2-256) below `common_base`, which must be on
+    /// a page, selected by writes to `port`. Bank 0 starts selected.
+    pub fn new(count: usize, common_base: u16, port: u8) -> CpmResult<Self> {
+        if !(2..=256).contains(&count) {
+            return Err(CpmError::MemoryMap(format!(
+                "{} banks, expected 2-256",
+                count
+            )));
+        }
+        if common_base == 0 || common_base & 0xFF != 0 {
+            return Err(CpmError::MemoryMap(format!(
+                "common area at {:04X}H is not on a page above 0",
+                common_base
+            )));
+        }
+        Ok(Self {
+            common_base,
+            port,
+            banks: vec![vec![0; common_base as usize]; count],
+            current: 0,
+        })
+    }
+
+    /// `count` banks below 0C000H, selected through port 0FFH.
+    pub fn with_defaults(count: usize) -> CpmResult<Self> {
+        Self::new(count, DEFAULT_COMMON_BASE, DEFAULT_SELECT_PORT)
+    }
+
+    /// Number of banks.
+    pub fn count(&self) -> usize {
+        self.banks.len()
+    }
+
+    /// Selected bank.
+    pub fn current(&self) -> u8 {
+        self.current
+    }
+
+    /// First address of the common area.
+    pub fn common_base(&self) -> u16 {
+        self.common_base
+    }
+
+    /// Bank select port.
+    pub fn port(&self) -> u8 {
+        self.port
+    }
+
+    /// Switch `memory` to `bank`. Numbers past the last bank are ignored,
+    /// as on a latch without those lines; returns false for them.
+    pub fn select(&mut self, memory: &mut [u8; 65536], bank: u8) -> bool {
+        if bank as usize >= self.count() {
+            return false;
+        }
+        if bank != self.current {
+            let area = ..self.common_base as usize;
+            self.banks[self.current as usize].copy_from_slice(&memory[area]);
+            memory[area].copy_from_slice(&self.banks[bank as usize]);
+            self.current = bank;
+        }
+        true
+    }
+
+    /// Copy page zero (0000H-00FFH) of `memory` into the other banks, so
+    /// the BDOS and warm boot jumps work whichever bank is selected.
+    pub fn share_page_zero(&mut self, memory: &[u8; 65536]) {
+        for (i, bank) in self.banks.iter_mut().enumerate() {
+            if i != self.current as usize {
+                bank[..0x100].copy_from_slice(&memory[..0x100]);
+            }
+        }
+    }
+
+    /// Byte at `address` as `bank` sees it.
+    pub fn read(&self, memory: &[u8; 65536], bank: u8, address: u16) -> u8 {
+        if address >= self.common_base || bank == self.current {
+            memory[address as usize]
+        } else {
+            self.banks[bank as usize][address as usize]
+        }
+    }
+
+    /// Store a byte at `address` in `bank` without switching.
+    pub fn write(&mut self, memory: &mut [u8; 65536], bank: u8, address: u16, value: u8) {
+        if address >= self.common_base || bank == self.current {
+            memory[address as usize] = value;
+        } else {
+            self.banks[bank as usize][address as usize] = value;
+        }
+    }
+
+    /// Copy of the banks with the selected one taken from `memory`, so
+    /// every bank is complete.
+    pub fn snapshot(&self, memory: &[u8; 65536]) -> Self {
+        let mut copy = self.clone();
+        copy.banks[self.current as usize].copy_from_slice(&memory[..self.common_base as usize]);
+        copy
+    }
+
+    /// Put the selected bank of a snapshot into `memory`.
+    pub fn restore(&self, memory: &mut [u8; 65536]) {
+        memory[..self.common_base as usize].copy_from_slice(&self.banks[self.current as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_swaps_banked_area() {
+        let mut memory = [0u8; 65536];
+        let mut bank = MemoryBank::new(3, 0x8000, 0x40).unwrap();
+        memory[0x100] = 0x11;
+        memory[0x9000] = 0xCC;
+
+        assert!(bank.select(&mut memory, 1));
+        assert_eq!(memory[0x100], 0);
+        memory[0x100] = 0x22;
+        assert_eq!(memory[0x9000], 0xCC);
+
+        bank.write(&mut memory, 2, 0x100, 0x33);
+        assert_eq!(bank.read(&memory, 0, 0x100), 0x11);
+        assert_eq!(bank.read(&memory, 1, 0x100), 0x22);
+        assert_eq!(bank.read(&memory, 2, 0x9000), 0xCC);
+
+        assert!(!bank.select(&mut memory, 3));
+        assert!(bank.select(&mut memory, 2));
+        assert_eq!(memory[0x100], 0x33);
+        assert!(bank.select(&mut memory, 0));
+        assert_eq!(memory[0x100], 0x11);
+    }
+
+    #[test]
+    fn test_share_page_zero() {
+        let mut memory = [0u8; 65536];
+        let mut bank = MemoryBank::new(3, 0x8000, 0x40).unwrap();
+        memory[0x05] = 0xC3;
+        memory[0x100] = 0x11;
+        bank.share_page_zero(&memory);
+
+        for n in 1..3 {
+            assert_eq!(bank.read(&memory, n, 0x05), 0xC3);
+            assert_eq!(bank.read(&memory, n, 0x100), 0);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut memory = [0u8; 65536];
+        let mut bank = MemoryBank::with_defaults(2).unwrap();
+        bank.select(&mut memory, 1);
+        memory[0x200] = 0x55;
+
+        let snapshot = bank.snapshot(&memory);
+        memory[0x200] = 0;
+        snapshot.restore(&mut memory);
+        assert_eq!(memory[0x200], 0x55);
+        assert_eq!(snapshot.current(), 1);
+    }
+
+    #[test]
+    fn test_invalid_layouts() {
+        assert!(MemoryBank::new(1, 0xC000, 0).is_err());
+        assert!(MemoryBank::new(2, 0xC080, 0).is_err());
+        assert!(MemoryBank::new(2, 0, 0).is_err());
+    }
+}
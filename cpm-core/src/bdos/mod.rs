@@ -146,11 +146,13 @@ pub const RECORD_SIZE: usize = 128;
 pub mod addr {
     /// Transient Program Area - where .COM files load
     pub const TPA: u16 = 0x0100;
-    /// Console Command Processor area
+    /// Console Command Processor area (default `MemoryMap`)
     pub const CCP: u16 = 0xDC00;
-    /// BDOS entry point
+    /// Start of the BDOS, where the serial number is (default `MemoryMap`)
     pub const BDOS: u16 = 0xFE00;
-    /// CBIOS entry points
+    /// BDOS entry point, after the serial number (default `MemoryMap`)
+    pub const BDOS_ENTRY: u16 = 0xFE06;
+    /// CBIOS entry points (default `MemoryMap`)
     pub const CBIOS: u16 = 0xFF00;
     /// Default DMA buffer
     pub const DEFAULT_DMA: u16 = 0x0080;
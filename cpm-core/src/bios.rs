@@ -0,0 +1,240 @@
+//! Code images for the system area.
+//!
+//! The BDOS and CBIOS run in Rust, but programs look at their memory: ZASM
+//! follows 0001H to the jump table, terminal installers copy and patch
+//! vectors and copy protection reads the BDOS serial number. `install`
+//! writes what a real system would have there:
+//!
+//! - BDOS: the serial number, a `JP` from the entry to the trap and the
+//!   four error vectors
+//! - CBIOS: 17 `JP`s, each to its own trap byte
+//! - one DPH, DPB, allocation vector and directory buffer shared by all
+//!   drives, after the CBIOS traps or between BDOS and CBIOS if they fit
+//!
+//! The emulator intercepts the trap addresses too, so calls through copied
+//! or patched vectors still reach it.
+
+use crate::memory::{MemoryMap, CBIOS_ENTRIES};
+
+/// BDOS serial number: OEM 0, version 2.2, serial 0.
+pub const SERIAL: [u8; 6] = [0x00, 0x22, 0x00, 0x00, 0x00, 0x00];
+
+const JP: u8 = 0xC3;
+const RET: u8 = 0xC9;
+
+/// CP/M 2.2 disk parameter block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskParameterBlock {
+    /// 128-byte records per track.
+    pub spt: u16,
+    /// Block shift and mask.
+    pub bsh: u8,
+    pub blm: u8,
+    /// Extent mask.
+    pub exm: u8,
+    /// Highest block number.
+    pub dsm: u16,
+    /// Highest directory entry number.
+    pub drm: u16,
+    /// Directory block bitmap.
+    pub al0: u8,
+    pub al1: u8,
+    /// Checked directory entries.
+    pub cks: u16,
+    /// Reserved tracks.
+    pub off: u16,
+}
+
+/// Parameters reported for every drive: 512K in 2K blocks with 128
+/// directory entries.
+pub const DPB: DiskParameterBlock = DiskParameterBlock {
+    spt: 64,
+    bsh: 4,
+    blm: 15,
+    exm: 1,
+    dsm: 255,
+    drm: 127,
+    al0: 0xC0,
+    al1: 0x00,
+    cks: 0,
+    off: 2,
+};
+
+impl DiskParameterBlock {
+    /// The 15 bytes of the block as stored in memory.
+    pub fn to_bytes(&self) -> [u8; 15] {
+        let mut out = [0; 15];
+        out[0..2].copy_from_slice(&self.spt.to_le_bytes());
+        out[2] = self.bsh;
+        out[3] = self.blm;
+        out[4] = self.exm;
+        out[5..7].copy_from_slice(&self.dsm.to_le_bytes());
+        out[7..9].copy_from_slice(&self.drm.to_le_bytes());
+        out[9] = self.al0;
+        out[10] = self.al1;
+        out[11..13].copy_from_slice(&self.cks.to_le_bytes());
+        out[13..15].copy_from_slice(&self.off.to_le_bytes());
+        out
+    }
+
+    /// Bytes per allocation block.
+    pub fn block_size(&self) -> usize {
+        128 << self.bsh
+    }
+
+    /// Bytes in the allocation vector.
+    pub fn alv_size(&self) -> usize {
+        self.dsm as usize / 8 + 1
+    }
+
+    /// Allocation vector for a drive holding files of these sizes. The
+    /// directory blocks are always allocated; a full disk saturates.
+    pub fn allocation(&self, file_sizes: impl IntoIterator<Item = usize>) -> Vec<u8> {
+        let dir_blocks = u16::from_be_bytes([self.al0, self.al1]).count_ones() as usize;
+        let file_blocks: usize = file_sizes
+            .into_iter()
+            .map(|size| size.div_ceil(self.block_size()))
+            .sum();
+        let used = (dir_blocks + file_blocks).min(self.dsm as usize + 1);
+
+        let mut alv = vec![0; self.alv_size()];
+        for block in 0..used {
+            alv[block / 8] |= 0x80 >> (block % 8);
+        }
+        alv
+    }
+}
+
+/// Where the disk tables were placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskTables {
+    /// Disk parameter header returned by SELDSK.
+    pub dph: u16,
+    pub dpb: u16,
+    /// Allocation vector, filled in by BDOS 27.
+    pub alv: u16,
+    pub dirbuf: u16,
+}
+
+impl DiskTables {
+    /// Bytes taken by the tables.
+    pub const SIZE: u16 = 16 + 15 + 32 + 128;
+
+    /// Room after the CBIOS traps, else between the BDOS code and the
+    /// CBIOS, if either has space.
+    pub fn locate(map: &MemoryMap) -> Option<Self> {
+        let after_cbios = map.cbios_end();
+        let after_bdos = map.bdos_trap() as u32 + 4;
+        let start = if after_cbios + Self::SIZE as u32 <= 0x10000 {
+            after_cbios
+        } else if after_bdos < map.cbios as u32
+            && after_bdos + Self::SIZE as u32 <= map.cbios as u32
+        {
+            after_bdos
+        } else {
+            return None;
+        } as u16;
+        Some(Self {
+            dph: start,
+            dpb: start + 16,
+            alv: start + 16 + 15,
+            dirbuf: start + 16 + 15 + 32,
+        })
+    }
+}
+
+/// Write the BDOS and CBIOS images for `map` and the warm boot and BDOS
+/// jumps at 0000H and 0005H. Returns the disk tables, if there was room.
+pub fn install(memory: &mut [u8; 65536], map: &MemoryMap) -> Option<DiskTables> {
+    let mut put = |address: u16, bytes: &[u8]| {
+        let start = address as usize;
+        let end = (start + bytes.len()).min(memory.len());
+        memory[start..end].copy_from_slice(&bytes[..end - start]);
+    };
+    let jp = |target: u16| {
+        let [lo, hi] = target.to_le_bytes();
+        [JP, lo, hi]
+    };
+
+    // JP WBOOT at 0000H and JP BDOS at 0005H
+    put(0x0000, &jp(map.cbios + 3));
+    put(0x0005, &jp(map.bdos));
+
+    // BDOS: serial, entry, error vectors to a warm boot stub, trap
+    let trap = map.bdos_trap();
+    if map.bdos >= map.tpa_end() + SERIAL.len() as u16 {
+        put(map.bdos - SERIAL.len() as u16, &SERIAL);
+    }
+    put(map.bdos, &jp(trap));
+    for i in 0..4 {
+        put(map.bdos + 3 + i * 2, &(trap + 1).to_le_bytes());
+    }
+    put(trap, &[RET]);
+    put(trap + 1, &jp(0x0000));
+
+    // CBIOS: jump table into one trap byte per function
+    for function in 0..CBIOS_ENTRIES as u8 {
+        put(
+            map.cbios + function as u16 * 3,
+            &jp(map.cbios_trap(function)),
+        );
+        put(map.cbios_trap(function), &[RET]);
+    }
+
+    let tables = DiskTables::locate(map)?;
+    let mut dph = [0u8; 16];
+    dph[8..10].copy_from_slice(&tables.dirbuf.to_le_bytes());
+    dph[10..12].copy_from_slice(&tables.dpb.to_le_bytes());
+    dph[14..16].copy_from_slice(&tables.alv.to_le_bytes());
+    put(tables.dph, &dph);
+    put(tables.dpb, &DPB.to_bytes());
+    Some(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_install_default() {
+        let mut memory = [0u8; 65536];
+        let map = MemoryMap::default();
+        let tables = install(&mut memory, &map).unwrap();
+
+        assert_eq!(&memory[0..3], [JP, 0x03, 0xFF]);
+        assert_eq!(&memory[5..8], [JP, 0x06, 0xFE]);
+        assert_eq!(&memory[0xFE00..0xFE06], SERIAL);
+        assert_eq!(&memory[0xFE06..0xFE09], [JP, 0x11, 0xFE]);
+        assert_eq!(&memory[0xFE09..0xFE0B], [0x12, 0xFE]);
+        assert_eq!(&memory[0xFF03..0xFF06], [JP, 0x34, 0xFF]);
+        assert_eq!(memory[0xFF34], RET);
+
+        // No room after the traps in the last page
+        assert_eq!(tables.dph, 0xFE15);
+        assert_eq!(&memory[0xFE1F..0xFE21], [0x25, 0xFE]);
+        assert_eq!(&memory[0xFE25..0xFE34], DPB.to_bytes());
+    }
+
+    #[test]
+    fn test_install_64k() {
+        let mut memory = [0u8; 65536];
+        let map = MemoryMap::for_size(64).unwrap();
+        let tables = install(&mut memory, &map).unwrap();
+
+        assert_eq!(&memory[0..3], [JP, 0x03, 0xFA]);
+        assert_eq!(&memory[0xEC00..0xEC06], SERIAL);
+        assert_eq!(tables.dph, 0xFA44);
+        assert!(tables.dirbuf as u32 + 128 <= 0x10000);
+    }
+
+    #[test]
+    fn test_allocation() {
+        assert_eq!(DPB.block_size(), 2048);
+        let alv = DPB.allocation([100, 4096, 0]);
+        assert_eq!(alv.len(), 32);
+        // Two directory blocks, then one and two blocks of data
+        assert_eq!(alv[0], 0b1111_1000);
+        assert!(alv[1..].iter().all(|&b| b == 0));
+        assert!(DPB.allocation([1 << 20]).iter().all(|&b| b == 0xFF));
+    }
+}
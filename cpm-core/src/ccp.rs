@@ -0,0 +1,820 @@
+//! Built-in console command processor.
+//!
+//! A Rust implementation of the CP/M 2.2 CCP for sessions without a
+//! CCP.COM-style shell binary. It runs on the host side of each warm boot:
+//! it prompts, takes a command line from the startup queue, `$$$.SUB` on A:
+//! (see `CpmEmulator::submit`) or the console, runs the built-ins (DIR, ERA, REN, TYPE, SAVE, USER and
+//! `d:`) directly against the drives, and loads transient .COM programs into
+//! the TPA with the command tail and default FCBs set up.
+
+use std::collections::VecDeque;
+
+use crate::bdos::{addr, Fcb, RECORD_SIZE};
+use crate::console::CpmConsole;
+use crate::emulator::CpmEmulator;
+use crate::fs::DriveFS;
+
+/// Batch file consumed at the prompt, one command per record.
+pub const SUBMIT_FILE: &str = "$$$.SUB";
+
+/// Longest command line accepted at the prompt.
+const MAX_LINE: usize = 127;
+
+/// Directory entries per DIR line.
+const DIR_COLUMNS: usize = 4;
+
+/// A file name parsed the way the CCP fills an FCB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileSpec {
+    /// Drive: 0 = default, 1 = A:, 2 = B:, ...
+    pub drive: u8,
+    /// Name, space-padded; `*` is expanded to `?`.
+    pub name: [u8; 8],
+    /// Extension, space-padded; `*` is expanded to `?`.
+    pub ext: [u8; 3],
+}
+
+impl FileSpec {
+    /// Parse one file name from the start of `text`, skipping leading
+    /// blanks. Returns the name and the text from the delimiter on.
+    pub fn parse(text: &str) -> (FileSpec, &str) {
+        let bytes = text.as_bytes();
+        let mut pos = 0;
+        while pos < bytes.len() && (bytes[pos] == b' ' || bytes[pos] == b'\t') {
+            pos += 1;
+        }
+
+        let mut spec = FileSpec {
+            drive: 0,
+            name: [b' '; 8],
+            ext: [b' '; 3],
+        };
+
+        if pos + 1 < bytes.len() && bytes[pos + 1] == b':' && bytes[pos].is_ascii_alphabetic() {
+            spec.drive = bytes[pos].to_ascii_uppercase() - b'A' + 1;
+            pos += 2;
+        }
+
+        pos = fill_field(bytes, pos, &mut spec.name);
+        if pos < bytes.len() && bytes[pos] == b'.' {
+            pos = fill_field(bytes, pos + 1, &mut spec.ext);
+        }
+
+        (spec, &text[pos..])
+    }
+
+    /// True if no name was given (a bare `d:` or nothing at all).
+    pub fn is_empty(&self) -> bool {
+        self.name[0] == b' ' && self.ext[0] == b' '
+    }
+
+    /// True if the name contains wildcards.
+    pub fn is_ambiguous(&self) -> bool {
+        self.name.contains(&b'?') || self.ext.contains(&b'?')
+    }
+
+    /// True if every position is a wildcard (`*.*`).
+    pub fn is_all(&self) -> bool {
+        self.name.iter().chain(&self.ext).all(|&b| b == b'?')
+    }
+
+    /// The name as `NAME.EXT` (trailing blanks and empty extension removed).
+    pub fn filename(&self) -> String {
+        let name = String::from_utf8_lossy(&self.name).trim_end().to_string();
+        let ext = String::from_utf8_lossy(&self.ext).trim_end().to_string();
+        if ext.is_empty() {
+            name
+        } else {
+            format!("{}.{}", name, ext)
+        }
+    }
+
+    /// Whether a directory entry matches this name (with `?` wildcards).
+    pub fn matches(&self, filename: &str) -> bool {
+        let mut mem = [0u8; 36];
+        let mut fcb = Fcb::new(&mut mem);
+        fcb.parse_filename(filename);
+        fcb.matches_pattern(&self.name, &self.ext)
+    }
+
+    /// The first 16 bytes of an FCB: drive, name, extension and zeroed
+    /// EX/S1/S2/RC.
+    pub fn to_fcb(&self) -> [u8; 16] {
+        let mut fcb = [0u8; 16];
+        fcb[0] = self.drive;
+        fcb[1..9].copy_from_slice(&self.name);
+        fcb[9..12].copy_from_slice(&self.ext);
+        fcb
+    }
+}
+
+/// CCP field delimiters.
+fn is_delimiter(b: u8) -> bool {
+    b <= b' ' || b"=_.:;<>".contains(&b)
+}
+
+/// Copy name characters into `field` until a delimiter; excess characters
+/// are skipped and `*` fills the rest of the field with `?`.
+fn fill_field(bytes: &[u8], mut pos: usize, field: &mut [u8]) -> usize {
+    let mut i = 0;
+    while pos < bytes.len() && !is_delimiter(bytes[pos]) {
+        let b = bytes[pos].to_ascii_uppercase();
+        if b == b'*' {
+            while i < field.len() {
+                field[i] = b'?';
+                i += 1;
+            }
+        } else if i < field.len() {
+            field[i] = b;
+            i += 1;
+        }
+        pos += 1;
+    }
+    pos
+}
+
+/// Built-in command processor state, kept across warm boots.
+#[derive(Debug, Default)]
+pub struct Ccp {
+    /// Command lines to run before reading the console.
+    pending: VecDeque<String>,
+}
+
+impl Ccp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue a command line to run at the next prompt, ahead of `$$$.SUB`
+    /// and console input.
+    pub fn push_command(&mut self, line: &str) {
+        self.pending.push_back(line.to_string());
+    }
+
+    /// Prompt and run commands until a transient program has been loaded
+    /// (returns true, with the CPU ready at the TPA) or the console has no
+    /// more input (returns false).
+    pub(crate) fn run<C: CpmConsole, D: DriveFS>(&mut self, emu: &mut CpmEmulator<C, D>) -> bool {
+        if emu.drive(emu.current_drive()).is_none() {
+            emu.select_drive(0);
+        }
+
+        loop {
+            let prompt = format!("\r\n{}>", (b'A' + emu.current_drive()) as char);
+            print(emu, &prompt);
+
+            let line = match self
+                .pending
+                .pop_front()
+                .or_else(|| emu.next_batch_command())
+            {
+                Some(line) => {
+                    print(emu, &line);
+                    print(emu, "\r\n");
+                    line
+                }
+                None => match read_line(emu) {
+                    Some(line) => line,
+                    None => return false,
+                },
+            };
+
+            let line = line.trim().to_uppercase();
+            if !line.is_empty() && execute(emu, &line) {
+                return true;
+            }
+        }
+    }
+}
+
+/// Take the next command from `$$$.SUB` on A:. Commands are stored in
+/// reverse order, so the last record is removed and returned; the file is
+/// deleted once empty.
+pub(crate) fn take_submit_line<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+) -> Option<String> {
+    let fs = emu.drive_mut(0)?;
+    let mut data = fs.read_file(SUBMIT_FILE)?;
+    let records = data.len() / RECORD_SIZE;
+    if records == 0 {
+        fs.delete_file(SUBMIT_FILE);
+        return None;
+    }
+
+    let start = (records - 1) * RECORD_SIZE;
+    let record = data[start..start + RECORD_SIZE].to_vec();
+    data.truncate(start);
+    if data.is_empty() {
+        fs.delete_file(SUBMIT_FILE);
+    } else {
+        let _ = fs.write_file(SUBMIT_FILE, &data);
+    }
+
+    let len = (record[0] as usize).min(RECORD_SIZE - 1);
+    Some(String::from_utf8_lossy(&record[1..1 + len]).into_owned())
+}
+
+/// Build `$$$.SUB` contents for a batch: one record per line (length byte,
+/// text, zero fill), last line first.
+pub fn submit_records<S: AsRef<str>>(lines: &[S]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(lines.len() * RECORD_SIZE);
+    for line in lines.iter().rev() {
+        let text = line.as_ref().as_bytes();
+        let len = text.len().min(RECORD_SIZE - 1);
+        let mut record = [0u8; RECORD_SIZE];
+        record[0] = len as u8;
+        record[1..1 + len].copy_from_slice(&text[..len]);
+        data.extend_from_slice(&record);
+    }
+    data
+}
+
+/// Read a command line from the console with CP/M line editing. Returns
+/// None if the console input is exhausted before anything was typed.
+fn read_line<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>) -> Option<String> {
+    let console = emu.console_mut();
+    let mut line = String::new();
+
+    loop {
+        if console.input_closed() {
+            return if line.is_empty() { None } else { Some(line) };
+        }
+
+        match console.wait_for_key() {
+            b'\r' | b'\n' => {
+                console.write(b'\r');
+                console.write(b'\n');
+                return Some(line);
+            }
+            // Backspace / DEL
+            8 | 127 if line.pop().is_some() => {
+                console.write(8);
+                console.write(b' ');
+                console.write(8);
+            }
+            // Ctrl-C on an empty line: reboot to a fresh prompt
+            3 if line.is_empty() => {
+                console.write(b'^');
+                console.write(b'C');
+                return Some(line);
+            }
+            // Ctrl-U / Ctrl-X: discard the line
+            0x15 | 0x18 => {
+                for _ in 0..line.len() {
+                    console.write(8);
+                    console.write(b' ');
+                    console.write(8);
+                }
+                line.clear();
+            }
+            ch if (b' '..127).contains(&ch) && line.len() < MAX_LINE => {
+                line.push(ch as char);
+                console.write(ch);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Run one command line. Returns true if a transient program was loaded.
+fn execute<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, line: &str) -> bool {
+    let (command, tail) = FileSpec::parse(line);
+
+    if command.is_empty() {
+        // `d:` changes the default drive
+        if command.drive != 0 && tail.trim().is_empty() {
+            let drive = command.drive - 1;
+            if !emu.select_drive(drive) {
+                select_error(emu, drive);
+            }
+        } else {
+            command_error(emu, line);
+        }
+        return false;
+    }
+
+    if command.drive == 0 && command.ext == [b' '; 3] {
+        let done = match command.filename().as_str() {
+            "DIR" => dir(emu, tail),
+            "ERA" => era(emu, tail),
+            "REN" => ren(emu, tail),
+            "TYPE" => type_file(emu, tail),
+            "SAVE" => save(emu, tail),
+            "USER" => user(emu, tail),
+            _ => None,
+        };
+        match done {
+            Some(true) => return false,
+            Some(false) => {
+                command_error(emu, line);
+                return false;
+            }
+            None => {}
+        }
+    }
+
+    transient(emu, line, &command, tail)
+}
+
+/// DIR [d:][afn]
+fn dir<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    let (mut spec, rest) = FileSpec::parse(tail);
+    if !rest.trim().is_empty() {
+        return Some(false);
+    }
+    if spec.is_empty() {
+        spec.name = [b'?'; 8];
+        spec.ext = [b'?'; 3];
+    }
+
+    let Some(files) = matching_files(emu, &spec) else {
+        return Some(true);
+    };
+    if files.is_empty() {
+        print(emu, "NO FILE");
+        return Some(true);
+    }
+
+    let drive = (b'A' + drive_of(emu, &spec)) as char;
+    for (i, filename) in files.iter().enumerate() {
+        if i % DIR_COLUMNS == 0 {
+            if i > 0 {
+                print(emu, "\r\n");
+            }
+            print(emu, &drive.to_string());
+        } else {
+            print(emu, " ");
+        }
+        let (name, ext) = filename.split_once('.').unwrap_or((filename, ""));
+        print(emu, &format!(": {:<8} {:<3}", name, ext));
+    }
+    Some(true)
+}
+
+/// ERA [d:]afn
+fn era<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    let (spec, rest) = FileSpec::parse(tail);
+    if spec.is_empty() || !rest.trim().is_empty() {
+        return Some(false);
+    }
+
+    if spec.is_all() {
+        print(emu, "ALL (Y/N)?");
+        if read_line(emu).unwrap_or_default().trim().to_uppercase() != "Y" {
+            return Some(true);
+        }
+    }
+
+    let Some(files) = matching_files(emu, &spec) else {
+        return Some(true);
+    };
+    if files.is_empty() {
+        print(emu, "NO FILE");
+        return Some(true);
+    }
+
+    let drive = drive_of(emu, &spec);
+    if let Some(fs) = emu.drive_mut(drive) {
+        for filename in &files {
+            fs.delete_file(filename);
+        }
+    }
+    Some(true)
+}
+
+/// REN [d:]newname=oldname
+fn ren<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    let (new, rest) = FileSpec::parse(tail);
+    let rest = rest.trim_start();
+    let Some(rest) = rest.strip_prefix('=').or_else(|| rest.strip_prefix('_')) else {
+        return Some(false);
+    };
+    let (old, rest) = FileSpec::parse(rest);
+
+    if new.is_empty()
+        || old.is_empty()
+        || new.is_ambiguous()
+        || old.is_ambiguous()
+        || !rest.trim().is_empty()
+        || (new.drive != 0 && old.drive != 0 && new.drive != old.drive)
+    {
+        return Some(false);
+    }
+
+    let drive = if new.drive != 0 {
+        new.drive - 1
+    } else {
+        drive_of(emu, &old)
+    };
+    let Some(fs) = emu.drive_mut(drive) else {
+        select_error(emu, drive);
+        return Some(true);
+    };
+
+    let (new_name, old_name) = (new.filename(), old.filename());
+    if fs.exists(&new_name) {
+        print(emu, "FILE EXISTS");
+        return Some(true);
+    }
+    match fs.read_file(&old_name) {
+        Some(data) => {
+            if fs.write_file(&new_name, &data).is_ok() {
+                fs.delete_file(&old_name);
+            }
+        }
+        None => print(emu, "NO FILE"),
+    }
+    Some(true)
+}
+
+/// TYPE [d:]ufn
+fn type_file<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    let (spec, rest) = FileSpec::parse(tail);
+    if spec.is_empty() || spec.is_ambiguous() || !rest.trim().is_empty() {
+        return Some(false);
+    }
+
+    let drive = drive_of(emu, &spec);
+    let Some(fs) = emu.drive(drive) else {
+        select_error(emu, drive);
+        return Some(true);
+    };
+    let Some(data) = fs.read_file(&spec.filename()) else {
+        print(emu, "NO FILE");
+        return Some(true);
+    };
+
+    let console = emu.console_mut();
+    for &b in data.iter().take_while(|&&b| b != 0x1A) {
+        console.write(b);
+    }
+    Some(true)
+}
+
+/// SAVE n [d:]ufn - write n pages from the TPA.
+fn save<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    let tail = tail.trim_start();
+    let (count, rest) = tail.split_at(tail.find(' ').unwrap_or(tail.len()));
+    let Ok(pages) = count.parse::<u8>() else {
+        return Some(false);
+    };
+    let (spec, rest) = FileSpec::parse(rest);
+    if spec.is_empty() || spec.is_ambiguous() || !rest.trim().is_empty() {
+        return Some(false);
+    }
+
+    let start = addr::TPA as usize;
+    let data = emu.memory()[start..start + pages as usize * 256].to_vec();
+    let drive = drive_of(emu, &spec);
+    let saved = match emu.drive_mut(drive) {
+        Some(fs) => fs.write_file(&spec.filename(), &data).is_ok(),
+        None => false,
+    };
+    if !saved {
+        print(emu, "NO SPACE");
+    }
+    Some(true)
+}
+
+/// USER n (0-15)
+fn user<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, tail: &str) -> Option<bool> {
+    match tail.trim().parse::<u8>() {
+        Ok(n) if n < 16 => {
+            emu.set_current_user(n);
+            Some(true)
+        }
+        _ => Some(false),
+    }
+}
+
+/// Load `[d:]NAME.COM` at the TPA with the command tail and default FCBs
+/// set up, and prepare the CPU to run it.
+fn transient<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+    line: &str,
+    command: &FileSpec,
+    tail: &str,
+) -> bool {
+    if command.is_ambiguous() || command.ext != [b' '; 3] {
+        command_error(emu, line);
+        return false;
+    }
+
+    let drive = drive_of(emu, command);
+    let com_name = format!("{}.COM", command.filename());
+    let Some(data) = emu.drive(drive).and_then(|fs| fs.read_file(&com_name)) else {
+        command_error(emu, line);
+        return false;
+    };
+    if data.len() > emu.memory_map().tpa_size() as usize {
+        print(emu, "BAD LOAD");
+        return false;
+    }
+
+    emu.load_com(&data);
+    emu.load_symbols_for(drive, &com_name);
+    set_command_tail(emu.memory_mut(), tail);
+    emu.start(addr::TPA);
+
+    // A final RET returns to the warm boot vector
+    let sp = emu.registers().sp as usize;
+    emu.memory_mut()[sp..sp + 2].copy_from_slice(&[0, 0]);
+    true
+}
+
+/// Store a command tail at 0x0080 and parse its first two names into the
+/// default FCBs at 0x005C and 0x006C, as the CCP does before running a
+/// program. The tail is stored as given, so options such as `[X]` or `$A`
+/// reach the program intact.
+pub fn set_command_tail(memory: &mut [u8], tail: &str) {
+    let bytes = &tail.as_bytes()[..tail.len().min(MAX_LINE)];
+    let cmdline = addr::CMDLINE as usize;
+    memory[cmdline] = bytes.len() as u8;
+    memory[cmdline + 1..cmdline + 1 + bytes.len()].copy_from_slice(bytes);
+    if bytes.len() < MAX_LINE {
+        memory[cmdline + 1 + bytes.len()] = 0;
+    }
+
+    let (first, rest) = FileSpec::parse(tail);
+    let rest = match rest.as_bytes().first() {
+        Some(&b) if b != b' ' && is_delimiter(b) => &rest[1..],
+        _ => rest,
+    };
+    let (second, _) = FileSpec::parse(rest);
+
+    let fcb1 = addr::FCB1 as usize;
+    let fcb2 = addr::FCB2 as usize;
+    memory[fcb1..fcb1 + 16].copy_from_slice(&first.to_fcb());
+    memory[fcb2..fcb2 + 16].copy_from_slice(&second.to_fcb());
+    // Current and random record of FCB1
+    memory[fcb2 + 16..fcb2 + 20].fill(0);
+}
+
+/// Files on the spec's drive matching it, sorted; None (after reporting a
+/// select error) if the drive is not mounted.
+fn matching_files<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+    spec: &FileSpec,
+) -> Option<Vec<String>> {
+    let drive = drive_of(emu, spec);
+    let Some(fs) = emu.drive(drive) else {
+        select_error(emu, drive);
+        return None;
+    };
+    let mut files: Vec<String> = fs
+        .list_files()
+        .into_iter()
+        .filter(|f| spec.matches(f))
+        .collect();
+    files.sort();
+    Some(files)
+}
+
+/// Drive index (0 = A:) a spec refers to.
+fn drive_of<C: CpmConsole, D: DriveFS>(emu: &CpmEmulator<C, D>, spec: &FileSpec) -> u8 {
+    if spec.drive == 0 {
+        emu.current_drive()
+    } else {
+        spec.drive - 1
+    }
+}
+
+/// Echo the offending command word followed by `?`.
+fn command_error<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, line: &str) {
+    let word = line.split_whitespace().next().unwrap_or(line);
+    print(emu, &format!("{}?", word));
+}
+
+fn select_error<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, drive: u8) {
+    print(
+        emu,
+        &format!("Bdos Err On {}: Select", (b'A' + drive) as char),
+    );
+}
+
+fn print<C: CpmConsole, D: DriveFS>(emu: &mut CpmEmulator<C, D>, text: &str) {
+    let console = emu.console_mut();
+    for b in text.bytes() {
+        console.write(b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ExitReason, HeadlessConsole, MemoryDriveFS};
+
+    type Emu = CpmEmulator<HeadlessConsole, MemoryDriveFS>;
+
+    /// Print the command tail, then the FCB1 and FCB2 names.
+    const ECHO_COM: [u8; 52] = [
+        0x21, 0x80, 0x00, // 0100: LD HL,0080
+        0x46, // LD B,(HL)
+        0x23, // 0104: INC HL
+        0x78, // LD A,B
+        0xB7, // OR A
+        0x28, 0x0D, // JR Z,0116
+        0x5E, // LD E,(HL)
+        0x0E, 0x02, // LD C,2
+        0xE5, // PUSH HL
+        0xC5, // PUSH BC
+        0xCD, 0x05, 0x00, // CALL 5
+        0xC1, // POP BC
+        0xE1, // POP HL
+        0x05, // DEC B
+        0x18, 0xEE, // JR 0104
+        0x21, 0x5D, 0x00, // 0116: LD HL,005D
+        0xCD, 0x24, 0x01, // CALL 0124
+        0x21, 0x6D, 0x00, // LD HL,006D
+        0xCD, 0x24, 0x01, // CALL 0124
+        0xC9, // RET
+        0x00, // NOP
+        0x06, 0x08, // 0124: LD B,8
+        0x5E, // 0126: LD E,(HL)
+        0x0E, 0x02, // LD C,2
+        0xE5, // PUSH HL
+        0xC5, // PUSH BC
+        0xCD, 0x05, 0x00, // CALL 5
+        0xC1, // POP BC
+        0xE1, // POP HL
+        0x23, // INC HL
+        0x10, 0xF3, // DJNZ 0126
+        0xC9, // RET
+    ];
+
+    fn session(input: &str, files: &[(&str, &[u8])]) -> Emu {
+        let mut fs = MemoryDriveFS::new();
+        for (name, data) in files {
+            fs.add_file(name, data.to_vec());
+        }
+        let mut emu: Emu = CpmEmulator::new(HeadlessConsole::with_input(input.as_bytes()));
+        emu.mount(0, fs);
+        emu.set_builtin_shell(Ccp::new());
+        let info = emu.run_from(0).unwrap();
+        assert_eq!(info.reason, ExitReason::WarmBoot);
+        emu
+    }
+
+    #[test]
+    fn test_parse_file_spec() {
+        let (spec, rest) = FileSpec::parse("  b:foo.txt bar");
+        assert_eq!(spec.drive, 2);
+        assert_eq!(spec.filename(), "FOO.TXT");
+        assert_eq!(rest, " bar");
+
+        let (spec, rest) = FileSpec::parse("*.C=X");
+        assert_eq!(&spec.name, b"????????");
+        assert_eq!(&spec.ext, b"C  ");
+        assert!(spec.is_ambiguous());
+        assert_eq!(rest, "=X");
+
+        let (spec, _) = FileSpec::parse("VERYLONGNAME.TEXT");
+        assert_eq!(spec.filename(), "VERYLONG.TEX");
+
+        let (spec, _) = FileSpec::parse("A:");
+        assert!(spec.is_empty());
+        assert_eq!(spec.drive, 1);
+
+        let (spec, _) = FileSpec::parse("F?O.*");
+        assert!(spec.matches("FOO.COM"));
+        assert!(!spec.matches("FOOD.COM"));
+    }
+
+    #[test]
+    fn test_builtins() {
+        let emu = session(
+            "DIR\rTYPE HELLO.TXT\rREN GREET.TXT=HELLO.TXT\rDIR *.TXT\rERA GREET.TXT\rDIR *.TXT\rSAVE 1 PAGE.BIN\rUSER 3\rFOO\r",
+            &[("HELLO.TXT", b"Hello, world\x1Agarbage"), ("A.COM", b"")],
+        );
+        let out = emu.console().output_string();
+        assert!(
+            out.contains("A: A        COM : HELLO    TXT\r\n"),
+            "{}",
+            out
+        );
+        assert!(out.contains("Hello, world\r\n"));
+        assert!(!out.contains("garbage"));
+        assert!(out.contains("A: GREET    TXT\r\n"));
+        assert!(out.contains("NO FILE"));
+        assert!(out.contains("FOO?\r\n"));
+        assert!(out.ends_with("\r\nA>"));
+
+        let fs = emu.drive(0).unwrap();
+        assert!(!fs.exists("HELLO.TXT"));
+        assert!(!fs.exists("GREET.TXT"));
+        assert_eq!(fs.read_file("PAGE.BIN").unwrap().len(), 256);
+        assert_eq!(emu.current_user(), 3);
+    }
+
+    #[test]
+    fn test_drive_switch() {
+        let mut b = MemoryDriveFS::new();
+        b.add_file("ON_B.TXT", b"B".to_vec());
+        let mut emu: Emu = CpmEmulator::new(HeadlessConsole::with_input(b"B:\rDIR\rC:\r"));
+        emu.mount(0, MemoryDriveFS::new());
+        emu.mount(1, b);
+        emu.set_builtin_shell(Ccp::new());
+        emu.run_from(0).unwrap();
+
+        let out = emu.console().output_string();
+        assert!(out.contains("B>DIR\r\nB: ON_B     TXT"), "{}", out);
+        assert!(out.contains("Bdos Err On C: Select"));
+        assert_eq!(emu.current_drive(), 1);
+        assert_eq!(emu.memory()[0x0004], 1);
+    }
+
+    #[test]
+    fn test_transient_runs_repeatedly() {
+        let emu = session("ECHO b:one.txt two\rECHO\r", &[("ECHO.COM", &ECHO_COM)]);
+        let out = emu.console().output_string();
+        assert!(
+            out.contains("A>ECHO b:one.txt two\r\n B:ONE.TXT TWOONE     TWO     \r\nA>"),
+            "{}",
+            out
+        );
+        assert_eq!(out.matches("A>ECHO").count(), 2);
+        assert!(out.ends_with("\r\nA>"));
+    }
+
+    #[test]
+    fn test_command_tail_and_fcbs() {
+        let mut memory = [0xAAu8; 256];
+        set_command_tail(&mut memory, " B:FOO.* BAR.TXT");
+        assert_eq!(memory[0x80], 16);
+        assert_eq!(&memory[0x81..0x91], b" B:FOO.* BAR.TXT");
+        assert_eq!(memory[0x91], 0);
+        assert_eq!(&memory[0x5C..0x68], b"\x02FOO     ???");
+        assert_eq!(&memory[0x6C..0x78], b"\x00BAR     TXT");
+        assert_eq!(&memory[0x7C..0x80], &[0, 0, 0, 0]);
+
+        set_command_tail(&mut memory, "");
+        assert_eq!(memory[0x80], 0);
+        assert_eq!(&memory[0x5C..0x68], b"\x00           ");
+    }
+
+    #[test]
+    fn test_xsub_feeds_console_input() {
+        // Read a line with BDOS 10 into 0120 and print it with BDOS 9
+        let reader = [
+            0x11, 0x20, 0x01, // LD DE,0120
+            0x0E, 0x0A, // LD C,10
+            0xCD, 0x05, 0x00, // CALL 5
+            0x3A, 0x21, 0x01, // LD A,(0121)
+            0x6F, // LD L,A
+            0x26, 0x00, // LD H,0
+            0x11, 0x22, 0x01, // LD DE,0122
+            0x19, // ADD HL,DE
+            0x36, b'$', // LD (HL),'$'
+            0x0E, 0x09, // LD C,9
+            0xCD, 0x05, 0x00, // CALL 5
+            0xC3, 0x00, 0x00, // JP 0
+            0x00, 0x00, 0x00, 0x00, // pad to 0120
+            0x40, // buffer: max 64
+        ];
+        let mut fs = MemoryDriveFS::new();
+        fs.add_file("READER.COM", reader.to_vec());
+        let mut emu: Emu = CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(0, fs);
+        emu.set_builtin_shell(Ccp::new());
+        emu.submit(&["XSUB", "READER", "from batch", "DIR"])
+            .unwrap();
+        emu.run_from(0).unwrap();
+
+        let out = emu.console().output_string();
+        assert!(
+            out.contains("A>READER\r\nfrom batch\r\nfrom batch\r\nA>DIR\r\nA: READER"),
+            "{}",
+            out
+        );
+        assert!(!emu.drive(0).unwrap().exists(SUBMIT_FILE));
+        assert!(!emu.xsub_active());
+    }
+
+    #[test]
+    fn test_ctrl_c_aborts_batch() {
+        let mut emu: Emu = CpmEmulator::new(HeadlessConsole::with_input(b"\x03"));
+        emu.mount(0, MemoryDriveFS::new());
+        emu.set_builtin_shell(Ccp::new());
+        emu.submit(&["DIR", "DIR"]).unwrap();
+        emu.run_from(0).unwrap();
+
+        assert_eq!(emu.console().output_string(), "\r\nA>");
+        assert!(!emu.drive(0).unwrap().exists(SUBMIT_FILE));
+    }
+
+    #[test]
+    fn test_submit_file() {
+        // Records are stored last command first
+        let mut sub = vec![0u8; 2 * RECORD_SIZE];
+        sub[0] = 4;
+        sub[1..5].copy_from_slice(b"TYPE");
+        sub[RECORD_SIZE] = 3;
+        sub[RECORD_SIZE + 1..RECORD_SIZE + 4].copy_from_slice(b"DIR");
+
+        let emu = session("", &[("$$$.SUB", &sub)]);
+        let out = emu.console().output_string();
+        assert!(out.contains("A>DIR\r\nA: $$$      SUB"), "{}", out);
+        assert!(out.contains("A>TYPE\r\nTYPE?"));
+        assert!(!emu.drive(0).unwrap().exists(SUBMIT_FILE));
+    }
+}
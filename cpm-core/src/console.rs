@@ -28,6 +28,28 @@ pub trait CpmConsole: Send {
             std::thread::sleep(std::time::Duration::from_millis(1));
         }
     }
+
+    /// True once no further input can arrive (e.g. a headless script has
+    /// been consumed). The built-in CCP ends the session at its prompt then.
+    fn input_closed(&self) -> bool {
+        false
+    }
+
+    /// Consume a pending Ctrl-C if it is the next key, leaving any other
+    /// type-ahead alone. Used to abort SUBMIT batches.
+    fn take_break(&mut self) -> bool {
+        false
+    }
+
+    /// Called with the T-state count before each BDOS or CBIOS call, for
+    /// consoles whose input arrives in emulated time.
+    fn set_time(&mut self, _t_states: u64) {}
+
+    /// T-state at which the next input will arrive, if the console knows.
+    /// A program blocked on input skips ahead to it.
+    fn next_input_at(&self) -> Option<u64> {
+        None
+    }
 }
 
 /// Headless console for testing - captures output, provides queued input.
@@ -93,6 +115,19 @@ impl CpmConsole for HeadlessConsole {
         // For headless, just return from queue or 0 if empty
         self.input.pop_front().unwrap_or(0)
     }
+
+    fn input_closed(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn take_break(&mut self) -> bool {
+        if self.input.front() == Some(&0x03) {
+            self.input.pop_front();
+            true
+        } else {
+            false
+        }
+    }
 }
 
 #[cfg(test)]
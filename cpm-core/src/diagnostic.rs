@@ -0,0 +1,386 @@
+//! Compiler and assembler diagnostics.
+//!
+//! Tools report problems in their own formats: ASM-family assemblers flag
+//! lines of the `.PRN` listing with a letter in column 1, BDS C prints
+//! `FOO.C: 12: message`, Pascal MT+ `Error # 101 at line 12` and Turbo
+//! Pascal `Error 85` after a running line count. Each tool profile picks an
+//! `ErrorParser` that turns console output and listing into `Diagnostic`s,
+//! which display in the `file:line:col: error: message` form editors and
+//! IDEs understand.
+
+use std::fmt;
+
+use regex::Regex;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// One problem reported by a tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Source file, when the tool names it.
+    pub file: Option<String>,
+    /// 1-based line number, when known.
+    pub line: Option<u32>,
+    /// 1-based column, when known.
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Error without a location.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            file: None,
+            line: None,
+            column: None,
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    /// Error in `file`, at `line` when known.
+    pub fn at(file: &str, line: Option<u32>, message: impl Into<String>) -> Self {
+        Self {
+            file: Some(file.to_string()),
+            line,
+            ..Self::error(message)
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    /// `file:line:col: severity: message`, leaving out unknown parts.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file)?;
+            if let Some(line) = self.line {
+                write!(f, "{}:", line)?;
+                if let Some(column) = self.column {
+                    write!(f, "{}:", column)?;
+                }
+            }
+            f.write_str(" ")?;
+        }
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// Error letters of CP/M ASM and LASM3.
+pub const ASM_CODES: &[(char, &str)] = &[
+    ('D', "data error"),
+    ('E', "expression error"),
+    ('L', "label error"),
+    ('N', "not implemented"),
+    ('O', "overflow"),
+    ('P', "phase error"),
+    ('R', "register error"),
+    ('S', "syntax error"),
+    ('U', "undefined symbol"),
+    ('V', "value error"),
+];
+
+/// Error letters of Z80MR and Z1.
+pub const Z80MR_CODES: &[(char, &str)] = &[('O', "bad opcode"), ('U', "undefined symbol")];
+
+/// How a tool's output is scanned for problems.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorParser {
+    /// Any line containing one of these words (case-insensitive) is an
+    /// error, except summaries such as "0 ERRORS" or "NO ERRORS".
+    Keywords(&'static [&'static str]),
+    /// Letter codes in column 1 of the listing, one listing line per source
+    /// line, or after the line number (LASM3). Falls back to the console,
+    /// which shows the same lines.
+    ListingCodes(&'static [(char, &'static str)]),
+    /// BDS C: `B:FOO.C: 12: message`.
+    BdsC,
+    /// Pascal MT+: `Error # 101 at line 12`.
+    MtPlus,
+    /// Turbo Pascal: `Error 85: ";" expected.` after the `N lines` count.
+    Turbo,
+}
+
+impl ErrorParser {
+    /// Diagnostics found in console `output` and the `listing`, for a
+    /// build of `file`.
+    pub fn parse(&self, file: &str, output: &str, listing: Option<&str>) -> Vec<Diagnostic> {
+        match self {
+            ErrorParser::Keywords(words) => parse_keywords(output, words),
+            ErrorParser::ListingCodes(codes) => match listing {
+                Some(listing) => parse_listing(file, listing, codes, true),
+                None => parse_listing(file, output, codes, false),
+            },
+            ErrorParser::BdsC => parse_bdsc(output),
+            ErrorParser::MtPlus => parse_mtplus(file, output),
+            ErrorParser::Turbo => parse_turbo(file, output),
+        }
+    }
+
+    /// True for parsers that guess from wording rather than a known
+    /// message format.
+    pub fn is_heuristic(&self) -> bool {
+        matches!(self, ErrorParser::Keywords(_))
+    }
+}
+
+fn parse_keywords(output: &str, words: &[&str]) -> Vec<Diagnostic> {
+    output
+        .split(['\r', '\n'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !is_summary(line))
+        .filter(|line| {
+            let lower = line.to_lowercase();
+            words.iter().any(|w| lower.contains(&w.to_lowercase()))
+        })
+        .map(Diagnostic::error)
+        .collect()
+}
+
+fn parse_listing(
+    file: &str,
+    text: &str,
+    codes: &[(char, &str)],
+    by_index: bool,
+) -> Vec<Diagnostic> {
+    let numbered = Regex::new(r"^\s*(\d+)  ([A-Z])[0-9A-F ]").expect("valid pattern");
+    // Error code, then a hex address or line number, or blanks up to the
+    // statement
+    let flagged = Regex::new(r"^([A-Z])(?:[0-9A-F]{4}(?:\s|$)| *\t)").expect("valid pattern");
+    let text = text.split('\x1A').next().unwrap_or_default();
+    // ASM starts the listing with empty lines; source blank lines are
+    // listed as spaces
+    let text = text.trim_start_matches(['\r', '\n']);
+    let mut diagnostics = Vec::new();
+
+    for (idx, line) in text.split('\n').enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (number, code) = if let Some(caps) = numbered.captures(line) {
+            (caps[1].parse().ok(), caps[2].chars().next())
+        } else if let Some(caps) = flagged.captures(line) {
+            (by_index.then_some(idx as u32 + 1), caps[1].chars().next())
+        } else {
+            continue;
+        };
+        let Some(code) = code else {
+            continue;
+        };
+        let meaning = codes
+            .iter()
+            .find(|(c, _)| *c == code)
+            .map_or_else(|| format!("error {}", code), |(_, m)| m.to_string());
+        let statement = line.split_once('\t').map_or("", |(_, s)| s.trim());
+        let message = if statement.is_empty() {
+            meaning
+        } else {
+            format!("{}: {}", meaning, statement)
+        };
+        diagnostics.push(Diagnostic::at(file, number, message));
+    }
+    diagnostics
+}
+
+fn parse_bdsc(output: &str) -> Vec<Diagnostic> {
+    let re =
+        Regex::new(r"^(?:[A-P]:)?([^\s:]+\.[A-Za-z]+): *(\d+): *(.+)$").expect("valid pattern");
+    output
+        .split(['\r', '\n'])
+        .filter_map(|line| re.captures(line.trim()))
+        .map(|caps| Diagnostic::at(&caps[1], caps[2].parse().ok(), caps[3].trim()))
+        .collect()
+}
+
+fn parse_mtplus(file: &str, output: &str) -> Vec<Diagnostic> {
+    let re = Regex::new(r"Error #\s*(\d+)\s+at line\s+(\d+)").expect("valid pattern");
+    re.captures_iter(output)
+        .map(|caps| Diagnostic::at(file, caps[2].parse().ok(), format!("error #{}", &caps[1])))
+        .collect()
+}
+
+fn parse_turbo(file: &str, output: &str) -> Vec<Diagnostic> {
+    let count = Regex::new(r"^(\d+) lines").expect("valid pattern");
+    let error =
+        Regex::new(r"Error (\d+)(?:[:.] *(.*?))?\.? *(?:Press <ESC>)?$").expect("valid pattern");
+    let mut line = None;
+    let mut diagnostics = Vec::new();
+
+    // The line count is redrawn with CR while compiling
+    for part in output.split(['\r', '\n']).map(str::trim) {
+        if let Some(caps) = count.captures(part) {
+            line = caps[1].parse().ok();
+        } else if let Some(caps) = error.captures(part) {
+            let message = match caps.get(2).map(|m| m.as_str()) {
+                Some(text) if !text.is_empty() => format!("Error {}: {}", &caps[1], text),
+                _ => format!("Error {}", &caps[1]),
+            };
+            diagnostics.push(Diagnostic::at(file, line, message));
+        }
+    }
+    diagnostics
+}
+
+/// "0 ERRORS", "No errors", "Errors: 0" and the like.
+fn is_summary(line: &str) -> bool {
+    let lower = line.to_lowercase();
+    let Some(idx) = lower.find("error") else {
+        return false;
+    };
+    let is_zero = |word: &str| word.bytes().all(|b| b == b'0');
+    let before = lower[..idx].split_whitespace().last();
+    let after = lower[idx..]
+        .split_once(':')
+        .and_then(|(_, rest)| rest.split_whitespace().next());
+    match (before, after) {
+        (Some("no"), _) => true,
+        (Some(count), _) if is_zero(count) => true,
+        (None, Some(count)) => is_zero(count),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyword_parser() {
+        let parser = ErrorParser::Keywords(&["error", "undefined"]);
+        let output = "Pass 1\r\nUNDEFINED SYMBOL: FOO\r\n0 ERRORS\r\nNo errors\r\n1 ERROR\r\n";
+        let errors = parser.parse("FOO.ASM", output, None);
+
+        assert_eq!(
+            errors,
+            [
+                Diagnostic::error("UNDEFINED SYMBOL: FOO"),
+                Diagnostic::error("1 ERROR")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asm_listing() {
+        let parser = ErrorParser::ListingCodes(ASM_CODES);
+        let listing = "\r\n\r\n 0100          \tORG 100H\r\n\
+                       U0102 110000    \tLXI D,MSGX\r\n\
+                       S               \tFOO A\r\n\
+                        0106           \tEND\r\n\x1A\x1A";
+        let errors = parser.parse("BAD.ASM", "", Some(listing));
+        assert_eq!(
+            errors,
+            [
+                Diagnostic::at("BAD.ASM", Some(2), "undefined symbol: LXI D,MSGX"),
+                Diagnostic::at("BAD.ASM", Some(3), "syntax error: FOO A"),
+            ]
+        );
+
+        // Console lines carry no position
+        let errors = parser.parse(
+            "BAD.ASM",
+            "CP/M ASSEMBLER\r\nS               \tFOO A\r\n",
+            None,
+        );
+        assert_eq!(
+            errors,
+            [Diagnostic::at("BAD.ASM", None, "syntax error: FOO A")]
+        );
+
+        // Other console messages are not listing lines
+        let output = "Bdos Err on B: R/O\r\nDeleting BAD.PRN\r\nEND OF ASSEMBLY\r\n";
+        assert!(parser.parse("BAD.ASM", output, None).is_empty());
+    }
+
+    #[test]
+    fn test_numbered_listing() {
+        let parser = ErrorParser::ListingCodes(ASM_CODES);
+        let listing = "\x0cLASM3  Page 001\r\n\r\n     1   0100\t\tORG 100H\r\n     \
+                       3  U0102 110000    \tLXI D,MSGX\r\n";
+        let errors = parser.parse("BAD.ASM", "", Some(listing));
+        assert_eq!(
+            errors,
+            [Diagnostic::at(
+                "BAD.ASM",
+                Some(3),
+                "undefined symbol: LXI D,MSGX"
+            )]
+        );
+
+        let parser = ErrorParser::ListingCodes(Z80MR_CODES);
+        let listing = " Z80MR v12\t\tORG 100H\r\nQ0002\t\tFOO\r\nUNDEFINED SYMBOLS\r\n";
+        let errors = parser.parse("BAD.AZM", "", Some(listing));
+        assert_eq!(errors, [Diagnostic::at("BAD.AZM", Some(2), "error Q: FOO")]);
+    }
+
+    #[test]
+    fn test_compiler_formats() {
+        let output = "BD Software C Compiler\r\nB:BAD.C: 3: Missing semicolon\r\n\
+                      Can't find 0/B:BAD.CRL\r\n";
+        assert_eq!(
+            ErrorParser::BdsC.parse("BAD.C", output, None),
+            [Diagnostic::at("BAD.C", Some(3), "Missing semicolon")]
+        );
+
+        let output = "Pascal/MT+\r\nError # 101 at line 1\r\nLast ID: HI\r\n";
+        assert_eq!(
+            ErrorParser::MtPlus.parse("ADD.PAS", output, None),
+            [Diagnostic::at("ADD.PAS", Some(1), "error #101")]
+        );
+
+        let output = "Compiling\r\n10 lines\r20 lines\r23 lines\r\n\r\n\
+                      Error 85: \";\" expected. Press <ESC>";
+        assert_eq!(
+            ErrorParser::Turbo.parse("HELLO.PAS", output, None),
+            [Diagnostic::at(
+                "HELLO.PAS",
+                Some(23),
+                "Error 85: \";\" expected"
+            )]
+        );
+        let output = "Error 2. Press <ESC>";
+        assert_eq!(
+            ErrorParser::Turbo.parse("HELLO.PAS", output, None),
+            [Diagnostic::at("HELLO.PAS", None, "Error 2")]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let mut diag = Diagnostic::at("src/bad.c", Some(3), "Missing semicolon");
+        assert_eq!(diag.to_string(), "src/bad.c:3: error: Missing semicolon");
+        diag.column = Some(7);
+        diag.severity = Severity::Warning;
+        assert_eq!(
+            diag.to_string(),
+            "src/bad.c:3:7: warning: Missing semicolon"
+        );
+        assert_eq!(Diagnostic::error("Timeout").to_string(), "error: Timeout");
+        assert_eq!(
+            Diagnostic::at("BAD.ASM", None, "syntax error").to_string(),
+            "BAD.ASM: error: syntax error"
+        );
+    }
+
+    #[test]
+    fn test_summary_lines() {
+        assert!(is_summary("0 ERRORS"));
+        assert!(is_summary("000 error(s)"));
+        assert!(is_summary("No errors detected"));
+        assert!(is_summary("Errors:       0"));
+        assert!(!is_summary("Errors: 2"));
+        assert!(!is_summary("10 ERRORS"));
+        assert!(!is_summary("Error 85"));
+    }
+}
@@ -0,0 +1,937 @@
+//! Z80/8080 disassembler.
+//!
+//! Decodes the full Z80 instruction set, including the undocumented
+//! IXH/IXL/IYH/IYL forms, SLL, the DDCB/FDCB register-copy variants and the
+//! duplicate ED opcodes. Output can use Zilog mnemonics or Intel 8080
+//! mnemonics (as printed by ASM and DDT); in 8080 mode any Z80-only opcode is
+//! shown as a `DB` byte.
+//!
+//! The `Disassembler` performs a linear sweep and can annotate BDOS calls
+//! with the function loaded into C, e.g. `CALL 0005H ; C=15 OpenFile`.
+//! With a symbol table, labels are listed and address operands that match a
+//! symbol are shown by name.
+
+use crate::bdos::BdosFunction;
+use crate::symbols::SymbolTable;
+
+/// Mnemonic syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Zilog Z80 mnemonics (`LD A,(HL)`).
+    #[default]
+    Z80,
+    /// Intel 8080 mnemonics (`MOV A,M`).
+    I8080,
+}
+
+/// Control flow effect of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    /// Falls through to the next instruction.
+    Next,
+    /// Unconditional jump (JP, JR).
+    Jump(u16),
+    /// Conditional jump (JP cc, JR cc, DJNZ).
+    CondJump(u16),
+    /// Jump through a register (JP (HL), JP (IX), JP (IY)).
+    IndirectJump,
+    /// Unconditional call (CALL, RST).
+    Call(u16),
+    /// Conditional call.
+    CondCall(u16),
+    /// Unconditional return (RET, RETI, RETN).
+    Return,
+    /// Conditional return.
+    CondReturn,
+    /// HALT.
+    Halt,
+}
+
+/// A decoded instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// Address of the first byte.
+    pub address: u16,
+    /// Raw instruction bytes.
+    pub bytes: Vec<u8>,
+    /// Mnemonic and operands, e.g. `LD C,0FH`.
+    pub text: String,
+    /// Control flow effect.
+    pub flow: Flow,
+    /// Optional annotation (BDOS function, etc.).
+    pub comment: Option<String>,
+}
+
+impl Instruction {
+    /// Instruction length in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Always false; decoded instructions are at least one byte.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Branch target address, if the instruction has a fixed one.
+    pub fn target(&self) -> Option<u16> {
+        match self.flow {
+            Flow::Jump(t) | Flow::CondJump(t) | Flow::Call(t) | Flow::CondCall(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Format as a listing line: address, bytes, mnemonic and comment.
+    pub fn to_line(&self) -> String {
+        let hex: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let mut line = format!("{:04X}  {:<12} {}", self.address, hex.join(" "), self.text);
+        if let Some(ref comment) = self.comment {
+            let pad = 40usize.saturating_sub(line.len());
+            line.push_str(&" ".repeat(pad.max(1)));
+            line.push_str("; ");
+            line.push_str(comment);
+        }
+        line
+    }
+}
+
+/// Decode a single instruction at `pc`, reading bytes through `fetch`.
+pub fn decode<F: Fn(u16) -> u8>(fetch: F, pc: u16, syntax: Syntax) -> Instruction {
+    let mut d = Decoder::new(&fetch, pc);
+    let (text, flow) = match syntax {
+        Syntax::Z80 => d.z80(),
+        Syntax::I8080 => d.i8080(),
+    };
+    let bytes = (0..d.len).map(|i| fetch(pc.wrapping_add(i))).collect();
+    Instruction {
+        address: pc,
+        bytes,
+        text,
+        flow,
+        comment: None,
+    }
+}
+
+/// Linear-sweep disassembler with optional BDOS call annotation.
+#[derive(Debug, Clone)]
+pub struct Disassembler {
+    /// Mnemonic syntax.
+    pub syntax: Syntax,
+    /// Annotate `CALL 5` / `JP 5` with the BDOS function in C.
+    pub annotate_bdos: bool,
+    /// Symbols for labels and address operands.
+    pub symbols: SymbolTable,
+}
+
+impl Default for Disassembler {
+    fn default() -> Self {
+        Self::new(Syntax::Z80)
+    }
+}
+
+impl Disassembler {
+    /// Create a disassembler with BDOS annotations enabled.
+    pub fn new(syntax: Syntax) -> Self {
+        Self {
+            syntax,
+            annotate_bdos: true,
+            symbols: SymbolTable::new(),
+        }
+    }
+
+    /// Disassemble `code` loaded at `org`.
+    ///
+    /// An instruction that would run past the end of `code` is emitted as
+    /// `DB` bytes instead.
+    pub fn disassemble(&self, code: &[u8], org: u16) -> Vec<Instruction> {
+        let end = org as usize + code.len();
+        let fetch = |a: u16| {
+            (a as usize)
+                .checked_sub(org as usize)
+                .and_then(|i| code.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+
+        let mut out = Vec::new();
+        let mut pc = org as usize;
+        let mut reg_c: Option<u8> = None;
+
+        while pc < end {
+            let mut insn = decode(fetch, pc as u16, self.syntax);
+            if pc + insn.len() > end {
+                insn = data_byte(pc as u16, code[pc - org as usize]);
+            }
+
+            if self.annotate_bdos {
+                reg_c = self.track_bdos(&mut insn, reg_c);
+            }
+            self.apply_symbols(&mut insn);
+
+            pc += insn.len();
+            out.push(insn);
+        }
+        out
+    }
+
+    /// Disassemble `code` and return the listing as text.
+    pub fn listing(&self, code: &[u8], org: u16) -> String {
+        let mut text = String::new();
+        for insn in self.disassemble(code, org) {
+            if let Some(label) = self.symbols.name_at(insn.address) {
+                text.push_str(label);
+                text.push_str(":\n");
+            }
+            text.push_str(&insn.to_line());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Replace a branch target or 16-bit operand with its symbol name.
+    fn apply_symbols(&self, insn: &mut Instruction) {
+        if self.symbols.is_empty() {
+            return;
+        }
+        let operand = match insn.target() {
+            Some(target) => Some(target),
+            None if insn.len() >= 3 => {
+                let n = insn.len();
+                Some(u16::from_le_bytes([insn.bytes[n - 2], insn.bytes[n - 1]]))
+            }
+            None => None,
+        };
+        if let Some(name) = operand.and_then(|a| self.symbols.name_at(a).map(|n| (a, n))) {
+            insn.text = insn.text.replace(&hex16(name.0), name.1);
+        }
+    }
+
+    /// Update the known value of C and annotate BDOS entry calls.
+    fn track_bdos(&self, insn: &mut Instruction, reg_c: Option<u8>) -> Option<u8> {
+        let b = &insn.bytes;
+        match (b[0], insn.flow) {
+            // LD C,n / MVI C,n and LD BC,nn / LXI B,nn; a truncated one
+            // leaves C unknown
+            (0x0E, _) | (0x01, _) => b.get(1).copied(),
+            (_, Flow::Call(0x0005)) | (_, Flow::Jump(0x0005)) => {
+                insn.comment = Some(bdos_comment(reg_c));
+                None
+            }
+            (_, Flow::Next) if !writes_c(b) => reg_c,
+            _ => None,
+        }
+    }
+}
+
+/// Describe a BDOS call given the (possibly unknown) function number in C.
+fn bdos_comment(reg_c: Option<u8>) -> String {
+    match reg_c {
+        Some(c) => match BdosFunction::try_from(c) {
+            Ok(func) => format!("C={} {:?}", c, func),
+            Err(_) => format!("C={}", c),
+        },
+        None => "BDOS".to_string(),
+    }
+}
+
+/// Whether an instruction (other than an immediate load) may change C.
+/// A lone prefix byte, as left by 8080 syntax or a truncated instruction,
+/// counts as a change.
+fn writes_c(b: &[u8]) -> bool {
+    match (b[0], b.get(1).copied()) {
+        (0x03 | 0x0B | 0x0C | 0x0D | 0x48..=0x4F | 0xC1 | 0xD9, _) => true,
+        (0xCB | 0xED | 0xDD | 0xFD, None) => true,
+        // CB rotates/RES/SET on C (BIT only reads)
+        (0xCB, Some(op)) => op & 0x07 == 1 && op >> 6 != 1,
+        // DDCB/FDCB ops that also copy the result to C
+        (0xDD | 0xFD, Some(0xCB)) => b.get(3).is_none_or(|&op| op & 0x07 == 1 && op >> 6 != 1),
+        // LD C,(IX+d), LD C,IXH and the rest follow the unprefixed opcode
+        (0xDD | 0xFD, Some(_)) => writes_c(&b[1..]),
+        // IN C,(C), LD BC,(nn) and the block moves/compares that count in BC
+        (0xED, Some(op)) => matches!(
+            op,
+            0x48 | 0x4B | 0xA0 | 0xA1 | 0xA8 | 0xA9 | 0xB0 | 0xB1 | 0xB8 | 0xB9
+        ),
+        _ => false,
+    }
+}
+
+/// A single `DB` byte.
+fn data_byte(address: u16, byte: u8) -> Instruction {
+    Instruction {
+        address,
+        bytes: vec![byte],
+        text: format!("DB {}", hex8(byte)),
+        flow: Flow::Next,
+        comment: None,
+    }
+}
+
+/// Format an 8-bit value in assembler hex notation (`0FH`).
+fn hex8(n: u8) -> String {
+    asm_hex(format!("{:02X}", n))
+}
+
+/// Format a 16-bit value in assembler hex notation (`0FE00H`).
+fn hex16(n: u16) -> String {
+    asm_hex(format!("{:04X}", n))
+}
+
+fn asm_hex(digits: String) -> String {
+    if digits.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{}H", digits)
+    } else {
+        format!("{}H", digits)
+    }
+}
+
+const R: [&str; 8] = ["B", "C", "D", "E", "H", "L", "(HL)", "A"];
+const CC: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU: [&str; 8] = [
+    "ADD A,", "ADC A,", "SUB ", "SBC A,", "AND ", "XOR ", "OR ", "CP ",
+];
+const ROT: [&str; 8] = ["RLC", "RRC", "RL", "RR", "SLA", "SRA", "SLL", "SRL"];
+const IM: [&str; 8] = ["0", "0/1", "1", "2", "0", "0/1", "1", "2"];
+const BLI: [[&str; 4]; 4] = [
+    ["LDI", "CPI", "INI", "OUTI"],
+    ["LDD", "CPD", "IND", "OUTD"],
+    ["LDIR", "CPIR", "INIR", "OTIR"],
+    ["LDDR", "CPDR", "INDR", "OTDR"],
+];
+
+const R_8080: [&str; 8] = ["B", "C", "D", "E", "H", "L", "M", "A"];
+const RP_8080: [&str; 4] = ["B", "D", "H", "SP"];
+const RP2_8080: [&str; 4] = ["B", "D", "H", "PSW"];
+const CC_8080: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+const ALU_8080: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALUI_8080: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+const ROT_8080: [&str; 8] = ["RLC", "RRC", "RAL", "RAR", "DAA", "CMA", "STC", "CMC"];
+
+/// Instruction decoder state for one instruction.
+struct Decoder<'a, F: Fn(u16) -> u8> {
+    fetch: &'a F,
+    pc: u16,
+    len: u16,
+    /// Active index register prefix ("IX" or "IY").
+    index: Option<&'static str>,
+    /// Whether the index prefix affected the decoded instruction.
+    index_used: bool,
+}
+
+impl<'a, F: Fn(u16) -> u8> Decoder<'a, F> {
+    fn new(fetch: &'a F, pc: u16) -> Self {
+        Self {
+            fetch,
+            pc,
+            len: 0,
+            index: None,
+            index_used: false,
+        }
+    }
+
+    fn byte(&mut self) -> u8 {
+        let b = (self.fetch)(self.pc.wrapping_add(self.len));
+        self.len += 1;
+        b
+    }
+
+    fn word(&mut self) -> u16 {
+        let lo = self.byte();
+        let hi = self.byte();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    fn imm8(&mut self) -> String {
+        let n = self.byte();
+        hex8(n)
+    }
+
+    fn imm16(&mut self) -> String {
+        let n = self.word();
+        hex16(n)
+    }
+
+    /// Relative jump target (displacement is the next byte).
+    fn rel(&mut self) -> u16 {
+        let d = self.byte() as i8;
+        self.pc.wrapping_add(self.len).wrapping_add(d as u16)
+    }
+
+    /// `(IX+d)` operand text for a displacement byte.
+    fn indexed(&self, index: &str, d: u8) -> String {
+        let d = d as i8;
+        if d < 0 {
+            format!("({}-{})", index, hex8(d.unsigned_abs()))
+        } else {
+            format!("({}+{})", index, hex8(d as u8))
+        }
+    }
+
+    /// 8-bit register operand. With an index prefix, (HL) becomes (IX+d)
+    /// and, when `halves` is set, H/L become IXH/IXL.
+    fn r(&mut self, i: u8, halves: bool) -> String {
+        match (i, self.index) {
+            (6, Some(ix)) => {
+                self.index_used = true;
+                let d = self.byte();
+                self.indexed(ix, d)
+            }
+            (4 | 5, Some(ix)) if halves => {
+                self.index_used = true;
+                format!("{}{}", ix, if i == 4 { "H" } else { "L" })
+            }
+            _ => R[i as usize].to_string(),
+        }
+    }
+
+    /// HL, or the active index register.
+    fn hl(&mut self) -> &'static str {
+        match self.index {
+            Some(ix) => {
+                self.index_used = true;
+                ix
+            }
+            None => "HL",
+        }
+    }
+
+    fn rp(&mut self, p: u8) -> &'static str {
+        match p {
+            0 => "BC",
+            1 => "DE",
+            2 => self.hl(),
+            _ => "SP",
+        }
+    }
+
+    fn rp2(&mut self, p: u8) -> &'static str {
+        match p {
+            0 => "BC",
+            1 => "DE",
+            2 => self.hl(),
+            _ => "AF",
+        }
+    }
+
+    /// Decode using Zilog mnemonics.
+    fn z80(&mut self) -> (String, Flow) {
+        let op = self.byte();
+        match op {
+            0xCB => self.z80_cb(),
+            0xED => self.z80_ed(),
+            0xDD | 0xFD => {
+                let next = (self.fetch)(self.pc.wrapping_add(1));
+                if matches!(next, 0xDD | 0xED | 0xFD) {
+                    // A prefix followed by another prefix acts as a NOP
+                    return (format!("DB {}", hex8(op)), Flow::Next);
+                }
+                self.index = Some(if op == 0xDD { "IX" } else { "IY" });
+                let op = self.byte();
+                if op == 0xCB {
+                    return self.z80_index_cb();
+                }
+                let result = self.z80_main(op);
+                if !self.index_used {
+                    // Prefix has no effect on this opcode
+                    self.len = 1;
+                    return (
+                        format!(
+                            "DB {}",
+                            hex8(if self.index == Some("IX") { 0xDD } else { 0xFD })
+                        ),
+                        Flow::Next,
+                    );
+                }
+                result
+            }
+            _ => self.z80_main(op),
+        }
+    }
+
+    fn z80_main(&mut self, op: u8) -> (String, Flow) {
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        let next = Flow::Next;
+
+        match x {
+            0 => match z {
+                0 => match y {
+                    0 => ("NOP".into(), next),
+                    1 => ("EX AF,AF'".into(), next),
+                    2 => {
+                        let t = self.rel();
+                        (format!("DJNZ {}", hex16(t)), Flow::CondJump(t))
+                    }
+                    3 => {
+                        let t = self.rel();
+                        (format!("JR {}", hex16(t)), Flow::Jump(t))
+                    }
+                    _ => {
+                        let t = self.rel();
+                        (
+                            format!("JR {},{}", CC[(y - 4) as usize], hex16(t)),
+                            Flow::CondJump(t),
+                        )
+                    }
+                },
+                1 => {
+                    if q == 0 {
+                        let rp = self.rp(p);
+                        (format!("LD {},{}", rp, self.imm16()), next)
+                    } else {
+                        let hl = self.hl();
+                        (format!("ADD {},{}", hl, self.rp(p)), next)
+                    }
+                }
+                2 => {
+                    let text = match (q, p) {
+                        (0, 0) => "LD (BC),A".to_string(),
+                        (0, 1) => "LD (DE),A".to_string(),
+                        (0, 2) => {
+                            let hl = self.hl();
+                            format!("LD ({}),{}", self.imm16(), hl)
+                        }
+                        (0, _) => format!("LD ({}),A", self.imm16()),
+                        (_, 0) => "LD A,(BC)".to_string(),
+                        (_, 1) => "LD A,(DE)".to_string(),
+                        (_, 2) => {
+                            let hl = self.hl();
+                            format!("LD {},({})", hl, self.imm16())
+                        }
+                        _ => format!("LD A,({})", self.imm16()),
+                    };
+                    (text, next)
+                }
+                3 => {
+                    let name = if q == 0 { "INC" } else { "DEC" };
+                    (format!("{} {}", name, self.rp(p)), next)
+                }
+                4 => (format!("INC {}", self.r(y, true)), next),
+                5 => (format!("DEC {}", self.r(y, true)), next),
+                6 => {
+                    let r = self.r(y, true);
+                    (format!("LD {},{}", r, self.imm8()), next)
+                }
+                _ => {
+                    let names = ["RLCA", "RRCA", "RLA", "RRA", "DAA", "CPL", "SCF", "CCF"];
+                    (names[y as usize].into(), next)
+                }
+            },
+            1 => {
+                if y == 6 && z == 6 {
+                    ("HALT".into(), Flow::Halt)
+                } else {
+                    // H/L stay plain when the other operand is (IX+d)
+                    let halves = y != 6 && z != 6;
+                    let dst = self.r(y, halves);
+                    let src = self.r(z, halves);
+                    (format!("LD {},{}", dst, src), next)
+                }
+            }
+            2 => (format!("{}{}", ALU[y as usize], self.r(z, true)), next),
+            _ => match z {
+                0 => (format!("RET {}", CC[y as usize]), Flow::CondReturn),
+                1 => {
+                    if q == 0 {
+                        (format!("POP {}", self.rp2(p)), next)
+                    } else {
+                        match p {
+                            0 => ("RET".into(), Flow::Return),
+                            1 => ("EXX".into(), next),
+                            2 => (format!("JP ({})", self.hl()), Flow::IndirectJump),
+                            _ => (format!("LD SP,{}", self.hl()), next),
+                        }
+                    }
+                }
+                2 => {
+                    let t = self.word();
+                    (
+                        format!("JP {},{}", CC[y as usize], hex16(t)),
+                        Flow::CondJump(t),
+                    )
+                }
+                3 => match y {
+                    0 => {
+                        let t = self.word();
+                        (format!("JP {}", hex16(t)), Flow::Jump(t))
+                    }
+                    // CB is handled as a prefix before reaching here
+                    1 => ("DB 0CBH".into(), next),
+                    2 => (format!("OUT ({}),A", self.imm8()), next),
+                    3 => (format!("IN A,({})", self.imm8()), next),
+                    4 => (format!("EX (SP),{}", self.hl()), next),
+                    5 => ("EX DE,HL".into(), next),
+                    6 => ("DI".into(), next),
+                    _ => ("EI".into(), next),
+                },
+                4 => {
+                    let t = self.word();
+                    (
+                        format!("CALL {},{}", CC[y as usize], hex16(t)),
+                        Flow::CondCall(t),
+                    )
+                }
+                5 => {
+                    if q == 0 {
+                        (format!("PUSH {}", self.rp2(p)), next)
+                    } else {
+                        // p != 0 are prefixes, handled before reaching here
+                        let t = self.word();
+                        (format!("CALL {}", hex16(t)), Flow::Call(t))
+                    }
+                }
+                6 => (format!("{}{}", ALU[y as usize], self.imm8()), next),
+                _ => {
+                    let t = (y as u16) * 8;
+                    (format!("RST {}", hex8(t as u8)), Flow::Call(t))
+                }
+            },
+        }
+    }
+
+    fn z80_cb(&mut self) -> (String, Flow) {
+        let op = self.byte();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let r = R[(op & 7) as usize];
+        let text = match x {
+            0 => format!("{} {}", ROT[y as usize], r),
+            1 => format!("BIT {},{}", y, r),
+            2 => format!("RES {},{}", y, r),
+            _ => format!("SET {},{}", y, r),
+        };
+        (text, Flow::Next)
+    }
+
+    /// DDCB/FDCB: displacement precedes the opcode. Undocumented forms with
+    /// z != 6 also copy the result into a register.
+    fn z80_index_cb(&mut self) -> (String, Flow) {
+        let ix = self.index.unwrap_or("IX");
+        let d = self.byte();
+        let op = self.byte();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let mem = self.indexed(ix, d);
+        let copy = if z == 6 {
+            String::new()
+        } else {
+            format!(",{}", R[z as usize])
+        };
+        let text = match x {
+            0 => format!("{} {}{}", ROT[y as usize], mem, copy),
+            1 => format!("BIT {},{}", y, mem),
+            2 => format!("RES {},{}{}", y, mem, copy),
+            _ => format!("SET {},{}{}", y, mem, copy),
+        };
+        (text, Flow::Next)
+    }
+
+    fn z80_ed(&mut self) -> (String, Flow) {
+        let op = self.byte();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = y >> 1;
+        let q = y & 1;
+        let next = Flow::Next;
+
+        match x {
+            1 => match z {
+                0 if y == 6 => ("IN (C)".into(), next),
+                0 => (format!("IN {},(C)", R[y as usize]), next),
+                1 if y == 6 => ("OUT (C),0".into(), next),
+                1 => (format!("OUT (C),{}", R[y as usize]), next),
+                2 => {
+                    let name = if q == 0 { "SBC" } else { "ADC" };
+                    (format!("{} HL,{}", name, self.rp(p)), next)
+                }
+                3 => {
+                    let rp = self.rp(p);
+                    if q == 0 {
+                        (format!("LD ({}),{}", self.imm16(), rp), next)
+                    } else {
+                        (format!("LD {},({})", rp, self.imm16()), next)
+                    }
+                }
+                4 => ("NEG".into(), next),
+                5 if y == 1 => ("RETI".into(), Flow::Return),
+                5 => ("RETN".into(), Flow::Return),
+                6 => (format!("IM {}", IM[y as usize]), next),
+                _ => {
+                    let names = ["LD I,A", "LD R,A", "LD A,I", "LD A,R", "RRD", "RLD"];
+                    match names.get(y as usize) {
+                        Some(name) => (name.to_string(), next),
+                        None => (format!("DB 0EDH,{}", hex8(op)), next),
+                    }
+                }
+            },
+            2 if z <= 3 && y >= 4 => (BLI[(y - 4) as usize][z as usize].into(), next),
+            // Undefined ED opcodes execute as a two-byte NOP
+            _ => (format!("DB 0EDH,{}", hex8(op)), next),
+        }
+    }
+
+    /// Decode using Intel 8080 mnemonics.
+    fn i8080(&mut self) -> (String, Flow) {
+        let op = self.byte();
+        let x = op >> 6;
+        let y = (op >> 3) & 7;
+        let z = op & 7;
+        let p = (y >> 1) as usize;
+        let q = y & 1;
+        let next = Flow::Next;
+        let db = || (format!("DB {}", hex8(op)), Flow::Next);
+
+        match x {
+            0 => match z {
+                0 if y == 0 => ("NOP".into(), next),
+                0 => db(),
+                1 if q == 0 => (format!("LXI {},{}", RP_8080[p], self.imm16()), next),
+                1 => (format!("DAD {}", RP_8080[p]), next),
+                2 => {
+                    let text = match (q, p) {
+                        (0, 0) => "STAX B".to_string(),
+                        (0, 1) => "STAX D".to_string(),
+                        (0, 2) => format!("SHLD {}", self.imm16()),
+                        (0, _) => format!("STA {}", self.imm16()),
+                        (_, 0) => "LDAX B".to_string(),
+                        (_, 1) => "LDAX D".to_string(),
+                        (_, 2) => format!("LHLD {}", self.imm16()),
+                        _ => format!("LDA {}", self.imm16()),
+                    };
+                    (text, next)
+                }
+                3 => {
+                    let name = if q == 0 { "INX" } else { "DCX" };
+                    (format!("{} {}", name, RP_8080[p]), next)
+                }
+                4 => (format!("INR {}", R_8080[y as usize]), next),
+                5 => (format!("DCR {}", R_8080[y as usize]), next),
+                6 => (format!("MVI {},{}", R_8080[y as usize], self.imm8()), next),
+                _ => (ROT_8080[y as usize].into(), next),
+            },
+            1 if y == 6 && z == 6 => ("HLT".into(), Flow::Halt),
+            1 => (
+                format!("MOV {},{}", R_8080[y as usize], R_8080[z as usize]),
+                next,
+            ),
+            2 => (
+                format!("{} {}", ALU_8080[y as usize], R_8080[z as usize]),
+                next,
+            ),
+            _ => match z {
+                0 => (format!("R{}", CC_8080[y as usize]), Flow::CondReturn),
+                1 if q == 0 => (format!("POP {}", RP2_8080[p]), next),
+                1 => match p {
+                    0 => ("RET".into(), Flow::Return),
+                    2 => ("PCHL".into(), Flow::IndirectJump),
+                    3 => ("SPHL".into(), next),
+                    _ => db(),
+                },
+                2 => {
+                    let t = self.word();
+                    (
+                        format!("J{} {}", CC_8080[y as usize], hex16(t)),
+                        Flow::CondJump(t),
+                    )
+                }
+                3 => match y {
+                    0 => {
+                        let t = self.word();
+                        (format!("JMP {}", hex16(t)), Flow::Jump(t))
+                    }
+                    2 => (format!("OUT {}", self.imm8()), next),
+                    3 => (format!("IN {}", self.imm8()), next),
+                    4 => ("XTHL".into(), next),
+                    5 => ("XCHG".into(), next),
+                    6 => ("DI".into(), next),
+                    7 => ("EI".into(), next),
+                    _ => db(),
+                },
+                4 => {
+                    let t = self.word();
+                    (
+                        format!("C{} {}", CC_8080[y as usize], hex16(t)),
+                        Flow::CondCall(t),
+                    )
+                }
+                5 if q == 0 => (format!("PUSH {}", RP2_8080[p]), next),
+                5 if p == 0 => {
+                    let t = self.word();
+                    (format!("CALL {}", hex16(t)), Flow::Call(t))
+                }
+                5 => db(),
+                6 => (format!("{} {}", ALUI_8080[y as usize], self.imm8()), next),
+                _ => (format!("RST {}", y), Flow::Call(y as u16 * 8)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn z80(bytes: &[u8]) -> String {
+        decode(
+            |a| bytes.get(a as usize).copied().unwrap_or(0),
+            0,
+            Syntax::Z80,
+        )
+        .text
+    }
+
+    fn i8080(bytes: &[u8]) -> String {
+        decode(
+            |a| bytes.get(a as usize).copied().unwrap_or(0),
+            0,
+            Syntax::I8080,
+        )
+        .text
+    }
+
+    #[test]
+    fn test_documented_z80() {
+        assert_eq!(z80(&[0x0E, 0x0F]), "LD C,0FH");
+        assert_eq!(z80(&[0x21, 0x00, 0xFE]), "LD HL,0FE00H");
+        assert_eq!(z80(&[0x7E]), "LD A,(HL)");
+        assert_eq!(z80(&[0x18, 0xFE]), "JR 0000H");
+        assert_eq!(z80(&[0xDD, 0x7E, 0xFD]), "LD A,(IX-03H)");
+        assert_eq!(z80(&[0xFD, 0x36, 0x05, 0x42]), "LD (IY+05H),42H");
+        assert_eq!(z80(&[0xED, 0xB0]), "LDIR");
+        assert_eq!(z80(&[0xED, 0x4B, 0x34, 0x12]), "LD BC,(1234H)");
+        assert_eq!(z80(&[0xCB, 0x7E]), "BIT 7,(HL)");
+        assert_eq!(z80(&[0xDD, 0xE9]), "JP (IX)");
+    }
+
+    #[test]
+    fn test_undocumented_z80() {
+        assert_eq!(z80(&[0xDD, 0x7C]), "LD A,IXH");
+        assert_eq!(z80(&[0xFD, 0x65]), "LD IYH,IYL");
+        assert_eq!(z80(&[0xDD, 0x66, 0x01]), "LD H,(IX+01H)");
+        assert_eq!(z80(&[0xCB, 0x37]), "SLL A");
+        assert_eq!(z80(&[0xDD, 0xCB, 0x02, 0x00]), "RLC (IX+02H),B");
+        assert_eq!(z80(&[0xFD, 0xCB, 0x02, 0xC6]), "SET 0,(IY+02H)");
+        assert_eq!(z80(&[0xED, 0x70]), "IN (C)");
+        assert_eq!(z80(&[0xED, 0x71]), "OUT (C),0");
+        assert_eq!(z80(&[0xED, 0x4C]), "NEG");
+        assert_eq!(z80(&[0xED, 0x4E]), "IM 0/1");
+        // Prefix without effect
+        assert_eq!(z80(&[0xDD, 0x00]), "DB 0DDH");
+    }
+
+    #[test]
+    fn test_8080_mnemonics() {
+        assert_eq!(i8080(&[0x0E, 0x0F]), "MVI C,0FH");
+        assert_eq!(i8080(&[0x7E]), "MOV A,M");
+        assert_eq!(i8080(&[0x11, 0x80, 0x00]), "LXI D,0080H");
+        assert_eq!(i8080(&[0xCD, 0x05, 0x00]), "CALL 0005H");
+        assert_eq!(i8080(&[0xC8]), "RZ");
+        assert_eq!(i8080(&[0xE9]), "PCHL");
+        assert_eq!(i8080(&[0xFE, 0x1A]), "CPI 1AH");
+        // Z80-only opcodes
+        assert_eq!(i8080(&[0x10, 0x00]), "DB 10H");
+        assert_eq!(i8080(&[0xED, 0xB0]), "DB 0EDH");
+    }
+
+    #[test]
+    fn test_instruction_lengths() {
+        let dis = Disassembler::new(Syntax::Z80);
+        let code = [
+            0x00, // NOP
+            0xDD, 0x21, 0x00, 0x10, // LD IX,1000H
+            0xDD, 0xCB, 0x01, 0x46, // BIT 0,(IX+01H)
+            0xC9, // RET
+        ];
+        let lens: Vec<usize> = dis
+            .disassemble(&code, 0x100)
+            .iter()
+            .map(|i| i.len())
+            .collect();
+        assert_eq!(lens, vec![1, 4, 4, 1]);
+    }
+
+    #[test]
+    fn test_bdos_annotation() {
+        let code = [
+            0x0E, 0x0F, // LD C,15
+            0x11, 0x5C, 0x00, // LD DE,005CH
+            0xCD, 0x05, 0x00, // CALL 5
+            0xCD, 0x05, 0x00, // CALL 5 (C unknown after call)
+            0xC3, 0x00, 0x00, // JP 0
+        ];
+        let insns = Disassembler::new(Syntax::I8080).disassemble(&code, 0x100);
+        assert_eq!(insns[2].text, "CALL 0005H");
+        assert_eq!(insns[2].comment.as_deref(), Some("C=15 OpenFile"));
+        assert_eq!(insns[3].comment.as_deref(), Some("BDOS"));
+        assert_eq!(insns[4].flow, Flow::Jump(0));
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let insns = Disassembler::default().disassemble(&[0xC3, 0x00], 0x100);
+        assert_eq!(insns.len(), 2);
+        assert_eq!(insns[0].text, "DB 0C3H");
+    }
+
+    #[test]
+    fn test_index_prefixed_writes_c() {
+        for insn in [
+            &[0xDD, 0x4E, 0x01][..],   // LD C,(IX+01H)
+            &[0xFD, 0x4D],             // LD C,IYL
+            &[0xDD, 0xCB, 0x02, 0x01], // RLC (IX+02H),C
+            &[0xFD, 0xCB, 0x02, 0x89], // RES 1,(IY+02H),C
+        ] {
+            let mut code = vec![0x0E, 0x0F];
+            code.extend_from_slice(insn);
+            code.extend_from_slice(&[0xCD, 0x05, 0x00]);
+            let insns = Disassembler::default().disassemble(&code, 0x100);
+            assert_eq!(insns[2].comment.as_deref(), Some("BDOS"), "{:02X?}", insn);
+        }
+
+        // LD (IX+01H),C and BIT 0,(IX+02H) only read C
+        let code = [
+            0x0E, 0x0F, 0xDD, 0x71, 0x01, 0xDD, 0xCB, 0x02, 0x46, 0xCD, 0x05, 0x00,
+        ];
+        let insns = Disassembler::default().disassemble(&code, 0x100);
+        assert_eq!(insns[3].comment.as_deref(), Some("C=15 OpenFile"));
+    }
+
+    #[test]
+    fn test_8080_prefix_bytes() {
+        let code = [
+            0x0E, 0x0F, // MVI C,15
+            0xED, // Z80 prefix, a lone DB byte in 8080 syntax
+            0xCD, 0x05, 0x00, // CALL 5
+            0x0E, // truncated MVI C
+        ];
+        let insns = Disassembler::new(Syntax::I8080).disassemble(&code, 0x100);
+        assert_eq!(insns[1].text, "DB 0EDH");
+        assert_eq!(insns[2].comment.as_deref(), Some("BDOS"));
+        assert_eq!(insns[3].text, "DB 0EH");
+
+        for tail in [0x01, 0x0E, 0xCB, 0xED, 0xDD, 0xFD] {
+            let insns = Disassembler::default().disassemble(&[0x00, tail], 0x100);
+            assert_eq!(insns.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_symbols() {
+        // START: LD DE,MSG; CALL PRINT; RET
+        let code = [0x11, 0x08, 0x01, 0xCD, 0x07, 0x01, 0xC9, 0xC9, b'$'];
+        let mut dis = Disassembler::new(Syntax::Z80);
+        dis.symbols.insert("START", 0x0100);
+        dis.symbols.insert("PRINT", 0x0107);
+        dis.symbols.insert("MSG", 0x0108);
+
+        let insns = dis.disassemble(&code, 0x100);
+        assert_eq!(insns[0].text, "LD DE,MSG");
+        assert_eq!(insns[1].text, "CALL PRINT");
+        assert!(dis.listing(&code, 0x100).starts_with("START:\n0100 "));
+    }
+}
@@ -1,26 +1,53 @@
 //! CP/M Emulator - integrates Z80 CPU with BDOS handling.
 
-use std::num::NonZeroU16;
+use std::num::{NonZeroU16, Wrapping};
+use std::time::{Duration, Instant};
 
 use z80emu::host::TsCounter;
-use z80emu::{Clock, Cpu, Io, Memory, Reg8, StkReg16, Z80NMOS};
+use z80emu::{Clock, Cpu, Io, Memory, Prefix, Reg8, StkReg16, Z80NMOS};
 
+#[cfg(feature = "banked")]
+use crate::bank::MemoryBank;
 use crate::bdos::{addr, BdosFunction, Fcb, RECORD_SIZE};
+use crate::bios::{self, DiskTables, DPB};
+use crate::ccp::{self, Ccp};
 use crate::console::CpmConsole;
-use crate::error::CpmResult;
+use crate::error::{CpmError, CpmResult};
 use crate::fs::DriveFS;
+use crate::hex::HexImage;
+use crate::interrupt::{InterruptController, Timer};
+use crate::io::{IoBus, RtcClock};
+use crate::memory::MemoryMap;
+use crate::prl::PrlImage;
+use crate::profile::Profiler;
+use crate::receipt::FileLog;
+use crate::symbols::SymbolTable;
+use crate::throttle::Throttle;
+use crate::trace::{TraceEvent, TraceSink};
 use crate::{CpmExitInfo, ExitReason};
 
 /// Type alias for the clock.
-type TsClock = TsCounter<i32>;
+type TsClock = TsCounter<i64>;
+
+/// CBIOS jump table entry names, in table order.
+const CBIOS_NAMES: [&str; 17] = [
+    "BOOT", "WBOOT", "CONST", "CONIN", "CONOUT", "LIST", "PUNCH", "READER", "HOME", "SELDSK",
+    "SETTRK", "SETSEC", "SETDMA", "READ", "WRITE", "LISTST", "SECTRAN",
+];
 
 /// CP/M Emulator bus - memory + I/O for z80emu.
 struct Bus<'a> {
     memory: &'a mut [u8; 65536],
+    io: &'a mut IoBus,
+    /// Data bus byte for an interrupt being accepted.
+    irq_vector: u8,
+    /// Bank switching, when enabled.
+    #[cfg(feature = "banked")]
+    bank: Option<&'a mut MemoryBank>,
 }
 
 impl Memory for Bus<'_> {
-    type Timestamp = i32;
+    type Timestamp = i64;
 
     fn read_debug(&self, addr: u16) -> u8 {
         self.memory[addr as usize]
@@ -36,22 +63,51 @@ impl Memory for Bus<'_> {
 }
 
 impl Io for Bus<'_> {
-    type Timestamp = i32;
+    type Timestamp = i64;
     type WrIoBreak = ();
     type RetiBreak = ();
 
-    fn read_io(&mut self, _port: u16, _ts: Self::Timestamp) -> (u8, Option<NonZeroU16>) {
-        (0xFF, None)
+    fn read_io(&mut self, port: u16, ts: Self::Timestamp) -> (u8, Option<NonZeroU16>) {
+        (self.io.read(port, ts as u64), None)
     }
 
     fn write_io(
         &mut self,
-        _port: u16,
-        _value: u8,
-        _ts: Self::Timestamp,
+        port: u16,
+        value: u8,
+        ts: Self::Timestamp,
     ) -> (Option<Self::WrIoBreak>, Option<NonZeroU16>) {
+        #[cfg(feature = "banked")]
+        if let Some(bank) = self.bank.as_deref_mut().filter(|b| b.port() == port as u8) {
+            bank.select(self.memory, value);
+            return (None, None);
+        }
+        self.io.write(port, value, ts as u64);
         (None, None)
     }
+
+    fn irq_data(&mut self, _pc: u16, _ts: Self::Timestamp) -> (u8, Option<NonZeroU16>) {
+        (self.irq_vector, None)
+    }
+}
+
+/// Z80 register file snapshot.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub ix: u16,
+    pub iy: u16,
+    pub af_alt: u16,
+    pub bc_alt: u16,
+    pub de_alt: u16,
+    pub hl_alt: u16,
+    pub i: u8,
+    pub r: u8,
 }
 
 /// CP/M Emulator state.
@@ -62,6 +118,21 @@ pub struct CpmEmulator<C: CpmConsole, D: DriveFS> {
     clock: TsClock,
     /// 64KB memory.
     memory: [u8; 65536],
+    /// Where the CCP, BDOS and CBIOS sit.
+    memory_map: MemoryMap,
+    /// DPH/DPB tables in the system area, if they fit.
+    disk_tables: Option<DiskTables>,
+    /// Devices answering IN and OUT.
+    io: IoBus,
+    /// Timer and device interrupt requests.
+    interrupts: InterruptController,
+    /// Pacing to a target clock; unlimited speed when `None`.
+    throttle: Option<Throttle>,
+    /// Wall clock for devices; T-state based in deterministic mode.
+    time_source: RtcClock,
+    /// Banks below the common area, when banking is enabled.
+    #[cfg(feature = "banked")]
+    bank: Option<MemoryBank>,
     /// Console for I/O.
     console: C,
     /// Drives (A-P).
@@ -84,8 +155,22 @@ pub struct CpmEmulator<C: CpmConsole, D: DriveFS> {
     shell_binary: Option<Vec<u8>>,
     /// Shell load address.
     shell_address: u16,
-    /// Enable syscall tracing.
+    /// Built-in command processor, used on warm boot when no shell binary is set.
+    builtin_ccp: Option<Ccp>,
+    /// `$$$.SUB` command taken at warm boot, for the shell's next BDOS 10.
+    batch_line: Option<String>,
+    /// XSUB active: BDOS 10 reads further lines from `$$$.SUB`.
+    xsub: bool,
+    /// Enable syscall tracing to stderr (used when no sink is set).
     pub trace: bool,
+    /// Receiver for structured trace events.
+    trace_sink: Option<Box<dyn TraceSink>>,
+    /// Execution profiler, when profiling is enabled.
+    profiler: Option<Profiler>,
+    /// Symbols of the running program.
+    symbols: SymbolTable,
+    /// Files opened and written, when recording for a receipt.
+    file_log: Option<FileLog>,
 }
 
 impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
@@ -95,6 +180,14 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             cpu: Z80NMOS::default(),
             clock: TsClock::default(),
             memory: [0; 65536],
+            memory_map: MemoryMap::default(),
+            disk_tables: None,
+            io: IoBus::new(),
+            interrupts: InterruptController::new(),
+            throttle: None,
+            time_source: RtcClock::System,
+            #[cfg(feature = "banked")]
+            bank: None,
             console,
             // Initialize array of None values without requiring Default
             drives: [
@@ -112,7 +205,14 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             open_files: Vec::new(),
             shell_binary: None,
             shell_address: addr::TPA,
+            builtin_ccp: None,
+            batch_line: None,
+            xsub: false,
             trace: false,
+            trace_sink: None,
+            profiler: None,
+            symbols: SymbolTable::new(),
+            file_log: None,
         };
         emu.init_memory();
         emu
@@ -120,30 +220,119 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
 
     /// Initialize memory with CP/M system vectors.
     fn init_memory(&mut self) {
-        // JP 0 at warm boot vector
-        self.memory[0x0000] = 0xC3; // JP
-        self.memory[0x0001] = 0x00;
-        self.memory[0x0002] = 0x00;
+        // JP WBOOT at 0x0000, JP BDOS at 0x0005, BDOS and CBIOS images
+        self.disk_tables = bios::install(&mut self.memory, &self.memory_map);
 
         // IOBYTE at 0x0003
         self.memory[0x0003] = 0x00;
 
         // Current drive at 0x0004
         self.memory[0x0004] = 0x00;
+    }
 
-        // JP BDOS at 0x0005
-        self.memory[0x0005] = 0xC3; // JP
-        self.memory[0x0006] = (addr::BDOS & 0xFF) as u8;
-        self.memory[0x0007] = (addr::BDOS >> 8) as u8;
+    /// Current memory layout.
+    pub fn memory_map(&self) -> MemoryMap {
+        self.memory_map
+    }
 
-        // BDOS entry - RET (we intercept before this)
-        self.memory[addr::BDOS as usize] = 0xC9; // RET
+    /// Devices on the I/O ports.
+    pub fn io(&self) -> &IoBus {
+        &self.io
+    }
+
+    /// Attach devices to the I/O ports.
+    pub fn io_mut(&mut self) -> &mut IoBus {
+        &mut self.io
+    }
 
-        // CBIOS entry points - all RET
-        for i in 0..17 {
-            let addr = addr::CBIOS as usize + i * 3;
-            self.memory[addr] = 0xC9; // RET
+    /// Snapshot of the banked memory with every bank complete, or `None`
+    /// if banking is off.
+    #[cfg(feature = "banked")]
+    pub fn memory_bank(&self) -> Option<MemoryBank> {
+        self.bank.as_ref().map(|b| b.snapshot(&self.memory))
+    }
+
+    /// Enable banking, with the current memory as the selected bank and
+    /// its page zero copied to the others, or (with `None`) go back to
+    /// flat memory keeping the selected bank.
+    #[cfg(feature = "banked")]
+    pub fn set_memory_bank(&mut self, mut bank: Option<MemoryBank>) {
+        if let Some(bank) = &mut bank {
+            bank.share_page_zero(&self.memory);
         }
+        self.bank = bank;
+    }
+
+    /// Go back to a snapshot from `memory_bank`, including the contents
+    /// of its selected bank.
+    #[cfg(feature = "banked")]
+    pub fn restore_memory_bank(&mut self, snapshot: MemoryBank) {
+        snapshot.restore(&mut self.memory);
+        self.bank = Some(snapshot);
+    }
+
+    /// Switch banks as a write to the select port would. Returns false if
+    /// banking is off or there is no such bank.
+    #[cfg(feature = "banked")]
+    pub fn select_bank(&mut self, bank: u8) -> bool {
+        match &mut self.bank {
+            Some(banks) => banks.select(&mut self.memory, bank),
+            None => false,
+        }
+    }
+
+    /// Run at a target clock speed, or (with `None`) as fast as possible.
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    /// Clock pacing, if set.
+    pub fn throttle(&self) -> Option<&Throttle> {
+        self.throttle.as_ref()
+    }
+
+    /// Deterministic mode: the clock from `time_source` counts from the
+    /// CP/M epoch in T-states of a 4 MHz CPU and the profiler records no
+    /// host timings. With input from a script and the T-state timer, every
+    /// run of a program then produces the same output and trace.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.time_source = if deterministic {
+            RtcClock::virtual_time()
+        } else {
+            RtcClock::System
+        };
+    }
+
+    /// True if time comes from the T-state counter.
+    pub fn is_deterministic(&self) -> bool {
+        self.time_source != RtcClock::System
+    }
+
+    /// The clock to give an `Rtc` device so it follows deterministic mode.
+    pub fn time_source(&self) -> RtcClock {
+        self.time_source
+    }
+
+    /// Pending interrupts and the timer.
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    /// Start, replace or (with `None`) stop the periodic timer interrupt.
+    pub fn set_timer(&mut self, timer: Option<Timer>) {
+        self.interrupts.set_timer(timer);
+    }
+
+    /// Request an interrupt with the given data bus byte.
+    pub fn raise_interrupt(&mut self, vector: u8) {
+        self.interrupts.raise(vector);
+    }
+
+    /// Move the BDOS and CBIOS and rewrite the system vectors. Takes effect
+    /// for the next `start`.
+    pub fn set_memory_map(&mut self, map: MemoryMap) {
+        self.memory_map = map;
+        self.init_memory();
     }
 
     /// Mount a drive.
@@ -192,36 +381,259 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         self.memory[start..end].copy_from_slice(&data[..end - start]);
     }
 
+    /// Load an Intel HEX file. Returns the entry point: the file's start
+    /// address, else its lowest address.
+    pub fn load_hex(&mut self, data: &[u8]) -> CpmResult<u16> {
+        let image = HexImage::parse(data)?;
+        for (address, bytes) in &image.segments {
+            self.load_at(*address, bytes);
+        }
+        Ok(image.start.or(image.low_address()).unwrap_or(addr::TPA))
+    }
+
+    /// Load a .PRL file relocated to `address` (page-aligned).
+    pub fn load_prl(&mut self, data: &[u8], address: u16) -> CpmResult<()> {
+        let code = PrlImage::parse(data)?.relocate(address)?;
+        self.load_at(address, &code);
+        Ok(())
+    }
+
     /// Set the shell binary for warm boot reload.
     /// When a program exits via warm boot, the shell will be reloaded and execution continues.
     pub fn set_shell(&mut self, data: &[u8], address: u16) {
         self.shell_binary = Some(data.to_vec());
         self.shell_address = address;
+        self.builtin_ccp = None;
         self.load_at(address, data);
     }
 
+    /// Use the built-in command processor as the shell.
+    /// Start the session with `run_from(0x0000)`; it ends when the console
+    /// runs out of input at the prompt.
+    pub fn set_builtin_shell(&mut self, ccp: Ccp) {
+        self.shell_binary = None;
+        self.builtin_ccp = Some(ccp);
+    }
+
+    /// Get the built-in command processor, if it is the shell.
+    pub fn builtin_shell_mut(&mut self) -> Option<&mut Ccp> {
+        self.builtin_ccp.as_mut()
+    }
+
+    /// Queue a batch of command lines as `$$$.SUB` on A:, as SUBMIT does.
+    /// One line runs per warm boot; a line `XSUB` makes the lines after it
+    /// feed BDOS 10 input as well. Ctrl-C at a warm boot aborts the batch.
+    pub fn submit<S: AsRef<str>>(&mut self, lines: &[S]) -> CpmResult<()> {
+        let data = ccp::submit_records(lines);
+        let fs = self.drive_mut(0).ok_or(CpmError::DriveNotMounted('A'))?;
+        fs.write_file(ccp::SUBMIT_FILE, &data)
+    }
+
+    /// Whether XSUB is feeding BDOS 10 input from `$$$.SUB`.
+    pub fn xsub_active(&self) -> bool {
+        self.xsub
+    }
+
+    /// Take the next command from `$$$.SUB` at warm boot. An `XSUB` line
+    /// turns on BDOS 10 interception and is not run itself.
+    pub(crate) fn next_batch_command(&mut self) -> Option<String> {
+        if !self.batch_pending() {
+            self.xsub = false;
+            return None;
+        }
+        if self.console.take_break() {
+            self.abort_batch();
+            return None;
+        }
+        loop {
+            let line = ccp::take_submit_line(self)?;
+            if line.trim().eq_ignore_ascii_case("XSUB") {
+                self.xsub = true;
+            } else {
+                return Some(line);
+            }
+        }
+    }
+
+    /// Input for BDOS 10 from the batch: the command taken at warm boot for
+    /// the shell, then further `$$$.SUB` lines while XSUB is active.
+    fn batch_input(&mut self) -> Option<String> {
+        if let Some(line) = self.batch_line.take() {
+            return Some(line);
+        }
+        if !self.xsub || !self.batch_pending() {
+            return None;
+        }
+        if self.console.take_break() {
+            self.abort_batch();
+            return None;
+        }
+        ccp::take_submit_line(self)
+    }
+
+    fn batch_pending(&self) -> bool {
+        self.drive(0).is_some_and(|fs| fs.exists(ccp::SUBMIT_FILE))
+    }
+
+    /// Ctrl-C: delete `$$$.SUB` and drop XSUB.
+    fn abort_batch(&mut self) {
+        if let Some(fs) = self.drive_mut(0) {
+            fs.delete_file(ccp::SUBMIT_FILE);
+        }
+        self.xsub = false;
+        self.batch_line = None;
+    }
+
+    /// Current default drive (0 = A:).
+    pub fn current_drive(&self) -> u8 {
+        self.current_drive
+    }
+
+    /// Select the default drive, as BDOS 14 does.
+    /// Returns false (leaving the selection unchanged) if it is not mounted.
+    pub fn select_drive(&mut self, drive: u8) -> bool {
+        if self.drive(drive).is_none() {
+            return false;
+        }
+        self.current_drive = drive;
+        self.update_drive_byte();
+        true
+    }
+
+    /// Current user number (0-15).
+    pub fn current_user(&self) -> u8 {
+        self.current_user
+    }
+
+    /// Set the current user number, as BDOS 32 does.
+    pub fn set_current_user(&mut self, user: u8) {
+        self.current_user = user & 0x0F;
+        self.update_drive_byte();
+    }
+
+    /// Mirror user and drive into 0x0004 (user in the high nibble).
+    fn update_drive_byte(&mut self) {
+        self.memory[0x0004] = (self.current_user << 4) | self.current_drive;
+    }
+
+    /// Send trace events to a sink instead of stderr.
+    pub fn set_trace_sink(&mut self, sink: Box<dyn TraceSink>) {
+        self.trace_sink = Some(sink);
+    }
+
+    /// Remove and return the trace sink, if any.
+    pub fn take_trace_sink(&mut self) -> Option<Box<dyn TraceSink>> {
+        self.trace_sink.take()
+    }
+
+    /// Start collecting an execution profile.
+    pub fn enable_profiling(&mut self) {
+        let mut profiler = Profiler::new();
+        profiler.enter_root(self.cpu.get_pc());
+        self.profiler = Some(profiler);
+    }
+
+    /// Get the execution profile, if profiling is enabled.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stop profiling and return the collected profile.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Start (afresh) or stop recording the files programs open and write,
+    /// for build receipts.
+    pub fn record_files(&mut self, enable: bool) {
+        self.file_log = enable.then(FileLog::new);
+    }
+
+    /// Files recorded since `record_files(true)`.
+    pub fn file_log(&self) -> Option<&FileLog> {
+        self.file_log.as_ref()
+    }
+
+    /// Symbols used to label addresses in traces and the debugger.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Replace the symbol table.
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    /// Load symbols for a program from `NAME.SYM` (or `NAME.PRN`) next to
+    /// `NAME.COM` on a drive. Returns false if neither exists; the current
+    /// symbols are cleared either way.
+    pub fn load_symbols_for(&mut self, drive: u8, com_name: &str) -> bool {
+        self.symbols = SymbolTable::new();
+        let Some(fs) = self.drives.get(drive as usize).and_then(|d| d.as_ref()) else {
+            return false;
+        };
+        let stem = com_name.rsplit_once('.').map_or(com_name, |(s, _)| s);
+        for ext in ["SYM", "PRN"] {
+            let name = format!("{}.{}", stem, ext);
+            if let Some(data) = fs.read_file(&name) {
+                self.symbols = SymbolTable::parse(&name, &data);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Symbolic name for an address, if one is near.
+    fn symbol_for(&self, addr: u16) -> Option<String> {
+        self.symbols
+            .lookup(addr)
+            .map(|_| self.symbols.format_addr(addr))
+    }
+
+    /// Whether trace events are being collected.
+    fn tracing(&self) -> bool {
+        self.trace || self.trace_sink.is_some() || self.profiler.is_some()
+    }
+
+    /// Deliver a trace event to the profiler and the sink, or to stderr if
+    /// only `trace` is set.
+    fn emit(&mut self, event: TraceEvent) {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_event(&event);
+        }
+        if let Some(sink) = &mut self.trace_sink {
+            sink.event(&event);
+        } else if self.trace {
+            eprintln!("{}", event);
+        }
+    }
+
+    /// Emit a trace event about an open file.
+    fn trace_file(&mut self, idx: usize, event: impl FnOnce(char, String) -> TraceEvent) {
+        if self.tracing() {
+            let (drive, filename, _, _) = &self.open_files[idx];
+            let event = event((b'A' + drive) as char, filename.clone());
+            self.emit(event);
+        }
+    }
+
     /// Set the program counter (where execution starts).
     pub fn set_pc(&mut self, address: u16) {
         self.cpu.set_pc(address);
     }
 
     /// Set command line arguments.
-    /// Args are stored at 0x0080 (length byte + text).
+    /// Args are stored at 0x0080 (length byte + text) and the first two file
+    /// names are parsed into FCB1/FCB2, as the CCP would.
     /// CP/M convention: command tail starts with a space, e.g. " ARG1 ARG2"
     pub fn set_args(&mut self, args: &str) {
-        if args.is_empty() {
-            self.memory[addr::CMDLINE as usize] = 0;
-            return;
-        }
-
-        // CP/M command tail has a leading space
-        let args_upper = format!(" {}", args.to_uppercase());
-        let bytes = args_upper.as_bytes();
-        let len = bytes.len().min(127);
-
-        self.memory[addr::CMDLINE as usize] = len as u8;
-        self.memory[addr::CMDLINE as usize + 1..addr::CMDLINE as usize + 1 + len]
-            .copy_from_slice(&bytes[..len]);
+        let tail = if args.is_empty() {
+            String::new()
+        } else {
+            // CP/M command tail has a leading space
+            format!(" {}", args.to_uppercase())
+        };
+        ccp::set_command_tail(&mut self.memory, &tail);
     }
 
     /// Run until program exits, starting at TPA (0x0100).
@@ -233,118 +645,332 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
     /// If a shell is set, warm boot reloads the shell and continues.
     /// Otherwise, warm boot exits.
     pub fn run_from(&mut self, start_address: u16) -> CpmResult<CpmExitInfo> {
+        self.start(start_address);
+        self.resume()
+    }
+
+    /// Reset the CPU and prepare to execute from the specified address.
+    pub fn start(&mut self, start_address: u16) {
         // Set PC to start address
         self.cpu.reset();
         self.cpu.set_pc(start_address);
 
         // Set SP to just below BDOS
-        self.cpu.set_sp(addr::BDOS - 2);
+        self.cpu.set_sp(self.memory_map.stack());
+        self.interrupts.reset();
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter_root(start_address);
+        }
+    }
 
+    /// Continue execution from the current CPU state until the program exits.
+    pub fn resume(&mut self) -> CpmResult<CpmExitInfo> {
+        if let Some(throttle) = &mut self.throttle {
+            throttle.resync(self.clock.as_timestamp() as u64);
+        }
         loop {
-            let pc = self.cpu.get_pc();
-
-            // Check for BDOS/CBIOS intercept BEFORE executing
-            match pc {
-                _ if pc == addr::BDOS => {
-                    let exit = self.handle_bdos()?;
-                    if let Some(info) = exit {
-                        // Check if we should reload shell on warm boot
-                        if info.reason == ExitReason::WarmBoot {
-                            if let Some(ref shell) = self.shell_binary {
-                                self.warm_boot_reload(shell.clone());
-                                continue;
-                            }
-                        }
-                        return Ok(info);
-                    }
-                    // Return from BDOS call
-                    let ret_addr = self.pop16();
-                    self.cpu.set_pc(ret_addr);
-                    continue;
+            if let Some(info) = self.step()? {
+                return Ok(info);
+            }
+            if let Some(throttle) = &mut self.throttle {
+                throttle.pace(self.clock.as_timestamp() as u64);
+            }
+        }
+    }
+
+    /// Execute a single instruction, or a complete BDOS/CBIOS call when the
+    /// PC is at a trap address. Returns Some(exit_info) if the program exited.
+    pub fn step(&mut self) -> CpmResult<Option<CpmExitInfo>> {
+        if self.deliver_interrupt() {
+            return Ok(None);
+        }
+        let pc = self.cpu.get_pc();
+
+        // Check for BDOS/CBIOS intercept BEFORE executing
+        match pc {
+            _ if self.memory_map.is_bdos(pc) => {
+                if let Some(info) = self.handle_bdos()? {
+                    return Ok(self.exit_or_reload(info));
                 }
-                _ if pc >= addr::CBIOS => {
-                    let exit = self.handle_cbios()?;
-                    if let Some(info) = exit {
-                        // Check if we should reload shell on warm boot
-                        if info.reason == ExitReason::WarmBoot {
-                            if let Some(ref shell) = self.shell_binary {
-                                self.warm_boot_reload(shell.clone());
-                                continue;
-                            }
-                        }
-                        return Ok(info);
-                    }
-                    // Return from CBIOS call
-                    let ret_addr = self.pop16();
-                    self.cpu.set_pc(ret_addr);
-                    continue;
+                // Return from BDOS call
+                let ret_addr = self.pop16();
+                self.cpu.set_pc(ret_addr);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_return();
                 }
-                0x0000 => {
-                    // Warm boot - reload shell if available
-                    if let Some(ref shell) = self.shell_binary {
-                        self.warm_boot_reload(shell.clone());
-                        continue;
-                    }
-                    return Ok(CpmExitInfo {
-                        reason: ExitReason::WarmBoot,
-                        t_states: self.clock.as_timestamp() as u64,
-                        pc: 0,
-                    });
+                return Ok(None);
+            }
+            _ if self.memory_map.cbios_function(pc).is_some() => {
+                if let Some(info) = self.handle_cbios()? {
+                    return Ok(self.exit_or_reload(info));
                 }
-                _ => {}
+                // Return from CBIOS call
+                let ret_addr = self.pop16();
+                self.cpu.set_pc(ret_addr);
+                if let Some(profiler) = &mut self.profiler {
+                    profiler.record_return();
+                }
+                return Ok(None);
+            }
+            0x0000 => {
+                return Ok(self.exit_or_reload(CpmExitInfo {
+                    reason: ExitReason::WarmBoot,
+                    t_states: self.clock.as_timestamp() as u64,
+                    pc: 0,
+                }));
             }
+            _ => {}
+        }
 
-            // Execute instruction
-            let mut bus = Bus {
-                memory: &mut self.memory,
-            };
+        // Opcode bytes for CALL/RET tracking, read before execution
+        let opcode = self.profiler.is_some().then(|| {
+            [
+                self.memory[pc as usize],
+                self.memory[pc.wrapping_add(1) as usize],
+            ]
+        });
+        let t_start = self.clock.as_timestamp();
+
+        // Execute instruction
+        let mut bus = Bus {
+            memory: &mut self.memory,
+            io: &mut self.io,
+            irq_vector: 0xFF,
+            #[cfg(feature = "banked")]
+            bank: self.bank.as_mut(),
+        };
 
-            let _result =
-                self.cpu
-                    .execute_next(&mut bus, &mut self.clock, None::<fn(z80emu::CpuDebug)>);
+        let _result =
+            self.cpu
+                .execute_next(&mut bus, &mut self.clock, None::<fn(z80emu::CpuDebug)>);
 
-            // Check for HALT instruction
-            if self.cpu.is_halt() {
-                self.flush_open_files();
-                return Ok(CpmExitInfo {
-                    reason: ExitReason::Halt,
-                    t_states: self.clock.as_timestamp() as u64,
-                    pc: self.cpu.get_pc(),
-                });
+        if let (Some(profiler), Some(opcode)) = (&mut self.profiler, opcode) {
+            let elapsed = self.clock.as_timestamp().wrapping_sub(t_start) as u64;
+            profiler.record_instruction(pc, opcode, elapsed, self.cpu.get_pc());
+        }
+
+        // HALT waits for an interrupt; it ends the program only if none
+        // can come
+        if self.cpu.is_halt() && !self.can_interrupt() {
+            self.flush_open_files();
+            return Ok(self.exit_or_reload(CpmExitInfo {
+                reason: ExitReason::Halt,
+                t_states: self.clock.as_timestamp() as u64,
+                pc: self.cpu.get_pc(),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    /// Block for a key. Input the console schedules for a later T-state
+    /// is waited for by moving the clock there, as if the CPU had been
+    /// polling until then.
+    fn wait_for_key(&mut self) -> u8 {
+        if !self.console.has_key() {
+            let now = self.t_states();
+            if let Some(at) = self.console.next_input_at().filter(|&at| at > now) {
+                self.clock.0 += Wrapping((at - now) as i64);
+                self.console.set_time(at);
+            }
+        }
+        self.console.wait_for_key()
+    }
+
+    /// Accept a pending interrupt if the CPU allows it. Returns true if one
+    /// was taken; the PC is then at the handler.
+    fn deliver_interrupt(&mut self) -> bool {
+        let now = self.t_states();
+        self.interrupts.poll(now);
+        let interrupts = &mut self.interrupts;
+        self.io
+            .poll_interrupts(now, |vector| interrupts.raise(vector));
+
+        let Some(vector) = self.interrupts.peek() else {
+            // Halted until the next tick: skip the idle cycles
+            if let (true, Some(tick)) = (self.cpu.is_halt(), self.interrupts.next_tick()) {
+                if self.cpu.get_iffs().0 && tick > now {
+                    self.clock.0 += Wrapping((tick - now) as i64);
+                }
             }
+            return false;
+        };
+        if !self.cpu.is_irq_allowed() {
+            return false;
+        }
+        let mut bus = Bus {
+            memory: &mut self.memory,
+            io: &mut self.io,
+            irq_vector: vector,
+            #[cfg(feature = "banked")]
+            bank: self.bank.as_mut(),
+        };
+        self.cpu
+            .irq(&mut bus, &mut self.clock, None::<fn(z80emu::CpuDebug)>);
+        self.interrupts.acknowledge();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record_call(self.cpu.get_pc());
+        }
+        true
+    }
+
+    /// True if interrupts are enabled and something can raise one.
+    fn can_interrupt(&self) -> bool {
+        self.cpu.get_iffs().0
+            && (self.interrupts.timer().is_some()
+                || self.interrupts.is_pending()
+                || self.io.can_interrupt())
+    }
+
+    /// On warm boot, reload the shell if one is set, or hand over to the
+    /// built-in CCP until it loads a program; otherwise report the exit.
+    fn exit_or_reload(&mut self, info: CpmExitInfo) -> Option<CpmExitInfo> {
+        if info.reason == ExitReason::WarmBoot {
+            if let Some(ref shell) = self.shell_binary {
+                self.warm_boot_reload(shell.clone());
+                return None;
+            }
+            if let Some(mut ccp) = self.builtin_ccp.take() {
+                self.warm_boot_reset();
+                let loaded = ccp.run(self);
+                self.builtin_ccp = Some(ccp);
+                if loaded {
+                    return None;
+                }
+            }
+        }
+        if self.tracing() {
+            self.emit(TraceEvent::Exit {
+                t_states: info.t_states,
+                pc: info.pc,
+                pc_symbol: self.symbol_for(info.pc),
+                reason: format!("{:?}", info.reason),
+            });
+        }
+        Some(info)
+    }
+
+    /// Get a snapshot of the CPU registers.
+    pub fn registers(&self) -> Registers {
+        Registers {
+            af: self.cpu.get_reg16(StkReg16::AF),
+            bc: self.cpu.get_reg16(StkReg16::BC),
+            de: self.cpu.get_reg16(StkReg16::DE),
+            hl: self.cpu.get_reg16(StkReg16::HL),
+            sp: self.cpu.get_sp(),
+            pc: self.cpu.get_pc(),
+            ix: self.cpu.get_index16(Prefix::Xdd),
+            iy: self.cpu.get_index16(Prefix::Yfd),
+            af_alt: self.cpu.get_alt_reg16(StkReg16::AF),
+            bc_alt: self.cpu.get_alt_reg16(StkReg16::BC),
+            de_alt: self.cpu.get_alt_reg16(StkReg16::DE),
+            hl_alt: self.cpu.get_alt_reg16(StkReg16::HL),
+            i: self.cpu.get_i(),
+            r: self.cpu.get_r(),
         }
     }
 
+    /// Load all CPU registers from a snapshot.
+    pub fn set_registers(&mut self, regs: &Registers) {
+        // Alternate set first, via EXX / EX AF,AF'
+        self.cpu.exx();
+        self.cpu.ex_af_af();
+        self.cpu.set_reg16(StkReg16::AF, regs.af_alt);
+        self.cpu.set_reg16(StkReg16::BC, regs.bc_alt);
+        self.cpu.set_reg16(StkReg16::DE, regs.de_alt);
+        self.cpu.set_reg16(StkReg16::HL, regs.hl_alt);
+        self.cpu.exx();
+        self.cpu.ex_af_af();
+
+        self.cpu.set_reg16(StkReg16::AF, regs.af);
+        self.cpu.set_reg16(StkReg16::BC, regs.bc);
+        self.cpu.set_reg16(StkReg16::DE, regs.de);
+        self.cpu.set_reg16(StkReg16::HL, regs.hl);
+        self.cpu.set_sp(regs.sp);
+        self.cpu.set_pc(regs.pc);
+        self.cpu.set_index16(Prefix::Xdd, regs.ix);
+        self.cpu.set_index16(Prefix::Yfd, regs.iy);
+        self.cpu.set_i(regs.i);
+        self.cpu.set_r(regs.r);
+    }
+
+    /// Current program counter.
+    pub fn pc(&self) -> u16 {
+        self.cpu.get_pc()
+    }
+
+    /// Get the 64KB address space.
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Get the 64KB address space for modification.
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    /// T-states executed so far.
+    pub fn t_states(&self) -> u64 {
+        self.clock.as_timestamp() as u64
+    }
+
     /// Reload shell after warm boot.
     fn warm_boot_reload(&mut self, shell: Vec<u8>) {
-        // Close all open files (flush writes)
-        self.flush_open_files();
+        self.warm_boot_reset();
 
         // Reload shell at its address
         self.load_at(self.shell_address, &shell);
 
-        // Re-initialize memory vectors
-        self.init_memory();
-
-        // Reset DMA to default
-        self.dma = addr::DEFAULT_DMA;
+        // Next batch command goes to the shell's first BDOS 10
+        self.batch_line = self.next_batch_command();
 
         // Reset CPU and set PC to shell
         self.cpu.reset();
         self.cpu.set_pc(self.shell_address);
-        self.cpu.set_sp(addr::BDOS - 2);
+        self.cpu.set_sp(self.memory_map.stack());
+
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter_root(self.shell_address);
+        }
 
         // Clear command line
         self.memory[addr::CMDLINE as usize] = 0;
     }
 
+    /// System state reset common to every warm boot.
+    fn warm_boot_reset(&mut self) {
+        if self.tracing() {
+            self.emit(TraceEvent::WarmBoot {
+                t_states: self.t_states(),
+            });
+        }
+
+        // Close all open files (flush writes)
+        self.flush_open_files();
+
+        // Re-initialize memory vectors, keeping the selected drive and user
+        self.init_memory();
+        self.update_drive_byte();
+
+        // Reset DMA to default
+        self.dma = addr::DEFAULT_DMA;
+
+        // The program's interrupt handlers are gone
+        self.cpu.disable_interrupts();
+        self.interrupts.reset();
+    }
+
     /// Flush and close all open files.
     fn flush_open_files(&mut self) {
         for (drive, filename, data, modified) in self.open_files.drain(..) {
             if modified {
-                if let Some(fs) = &mut self.drives[drive as usize] {
+                if let Some(fs) = self.drives.get_mut(drive as usize).and_then(|d| d.as_mut()) {
                     let _ = fs.write_file(&filename, &data);
                 }
+                if let Some(log) = &mut self.file_log {
+                    log.record_write(drive, &filename);
+                }
             }
         }
     }
@@ -363,20 +989,84 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let c = self.cpu.get_reg(Reg8::C, None);
         let e = self.cpu.get_reg(Reg8::E, None);
         let de = self.cpu.get_reg16(StkReg16::DE);
+        let func = BdosFunction::try_from(c).ok();
+
+        // Capture arguments before the call can change them
+        let traced = self.tracing().then(|| self.trace_bdos_args(func, de));
+
+        self.console.set_time(self.t_states());
+        let started = Instant::now();
+        let result = match func {
+            Some(func) => self.dispatch_bdos(func, e, de)?,
+            None => None,
+        };
+        if let Some(profiler) = &mut self.profiler {
+            let time = if self.time_source == RtcClock::System {
+                started.elapsed()
+            } else {
+                Duration::ZERO
+            };
+            profiler.record_bdos(c, time);
+        }
 
-        if self.trace {
-            eprintln!("[BDOS] Function {} (C={:#04X}, DE={:#06X})", c, c, de);
+        if let Some((caller, fcb, dma)) = traced {
+            self.emit(TraceEvent::BdosCall {
+                t_states: self.t_states(),
+                caller,
+                caller_symbol: self.symbol_for(caller),
+                function: c,
+                name: func.map(|f| format!("{:?}", f)),
+                e,
+                de,
+                fcb,
+                dma,
+                a: self.cpu.get_reg(Reg8::A, None),
+                hl: self.cpu.get_reg16(StkReg16::HL),
+            });
         }
 
-        match BdosFunction::try_from(c) {
-            Ok(func) => self.dispatch_bdos(func, e, de),
-            Err(_) => {
-                if self.trace {
-                    eprintln!("[BDOS] Unknown function: {}", c);
-                }
-                Ok(None)
+        Ok(result)
+    }
+
+    /// Decode BDOS arguments for tracing: caller, FCB filename and DMA address.
+    fn trace_bdos_args(
+        &self,
+        func: Option<BdosFunction>,
+        de: u16,
+    ) -> (u16, Option<String>, Option<u16>) {
+        use BdosFunction::*;
+
+        let sp = self.cpu.get_sp() as usize;
+        let caller = u16::from_le_bytes([self.memory[sp], self.memory[(sp + 1) & 0xFFFF]]);
+
+        let Some(func) = func else {
+            return (caller, None, None);
+        };
+        let fcb = match func {
+            OpenFile | CloseFile | SearchFirst | DeleteFile | ReadSequential | WriteSequential
+            | MakeFile | RenameFile | SetFileAttributes | ReadRandom | WriteRandom
+            | ComputeFileSize | SetRandomRecord | WriteRandomZeroFill => {
+                Some(self.fcb_display_name(de))
             }
+            _ => None,
+        };
+        let dma = match func {
+            SearchFirst | SearchNext | ReadSequential | WriteSequential | ReadRandom
+            | WriteRandom | WriteRandomZeroFill => Some(self.dma),
+            _ => None,
+        };
+        (caller, fcb, dma)
+    }
+
+    /// Format the FCB at an address as `D:NAME.EXT`.
+    fn fcb_display_name(&self, fcb_addr: u16) -> String {
+        let mut fcb_mem = [0u8; 36];
+        for (i, b) in fcb_mem.iter_mut().enumerate() {
+            *b = self.memory[(fcb_addr as usize + i) & 0xFFFF];
         }
+        let fcb = Fcb::new(&mut fcb_mem);
+        let drive = self.effective_drive(fcb.drive());
+        format!("{}:{}", (b'A' + drive) as char, fcb.filename())
     }
 
     /// Dispatch BDOS function.
@@ -398,7 +1088,7 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             }
 
             ConsoleInput => {
-                let ch = self.console.wait_for_key();
+                let ch = self.wait_for_key();
                 self.cpu.set_reg(Reg8::A, None, ch);
             }
 
@@ -420,7 +1110,7 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
                     self.cpu.set_reg(Reg8::A, None, status);
                 } else if e == 0xFD {
                     // Input (wait)
-                    let ch = self.console.wait_for_key();
+                    let ch = self.wait_for_key();
                     self.cpu.set_reg(Reg8::A, None, ch);
                 } else {
                     // Output
@@ -451,28 +1141,44 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
                 let max_len = self.memory[de as usize] as usize;
                 let mut pos = 0;
 
-                loop {
-                    let ch = self.console.wait_for_key();
-
-                    if ch == 13 {
-                        // Enter - end input
-                        self.console.write(13);
-                        self.console.write(10);
-                        break;
-                    } else if ch == 8 || ch == 127 {
-                        // Backspace
-                        if pos > 0 {
-                            pos -= 1;
-                            self.console.write(8);
-                            self.console.write(b' ');
-                            self.console.write(8);
-                        }
-                    } else if ch >= 32 && pos < max_len {
-                        // Printable character
+                if let Some(line) = self.batch_input() {
+                    // Batch line, echoed as if typed
+                    for &ch in line.as_bytes().iter().take(max_len) {
                         self.memory[de as usize + 2 + pos] = ch;
                         pos += 1;
                         self.console.write(ch);
                     }
+                    self.console.write(13);
+                    self.console.write(10);
+                } else {
+                    loop {
+                        // End of scripted input acts as Enter
+                        let ch = if self.console.input_closed() {
+                            13
+                        } else {
+                            self.wait_for_key()
+                        };
+
+                        if ch == 13 {
+                            // Enter - end input
+                            self.console.write(13);
+                            self.console.write(10);
+                            break;
+                        } else if ch == 8 || ch == 127 {
+                            // Backspace
+                            if pos > 0 {
+                                pos -= 1;
+                                self.console.write(8);
+                                self.console.write(b' ');
+                                self.console.write(8);
+                            }
+                        } else if ch >= 32 && pos < max_len {
+                            // Printable character
+                            self.memory[de as usize + 2 + pos] = ch;
+                            pos += 1;
+                            self.console.write(ch);
+                        }
+                    }
                 }
 
                 // Store actual length
@@ -496,14 +1202,8 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             }
 
             SelectDisk => {
-                self.current_drive = e;
-                self.memory[0x0004] = e;
                 // Return 0 if drive exists, 0xFF otherwise
-                let result = if self.drives[e as usize].is_some() {
-                    0
-                } else {
-                    0xFF
-                };
+                let result = if self.select_drive(e) { 0 } else { 0xFF };
                 self.cpu.set_reg(Reg8::A, None, result);
             }
 
@@ -515,6 +1215,31 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
                 self.dma = de;
             }
 
+            GetAllocationVector => {
+                let alv = self.disk_tables.map_or(0, |tables| {
+                    let sizes: Vec<usize> = self
+                        .drive(self.current_drive)
+                        .map(|fs| {
+                            fs.list_files()
+                                .iter()
+                                .filter_map(|name| fs.read_file(name))
+                                .map(|data| data.len())
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    let start = tables.alv as usize;
+                    let alv = DPB.allocation(sizes);
+                    self.memory[start..start + alv.len()].copy_from_slice(&alv);
+                    tables.alv
+                });
+                self.cpu.set_reg16(StkReg16::HL, alv);
+            }
+
+            GetDiskParameters => {
+                let dpb = self.disk_tables.map_or(0, |tables| tables.dpb);
+                self.cpu.set_reg16(StkReg16::HL, dpb);
+            }
+
             ReturnLoginVector => {
                 // Return bitmap of available drives
                 let mut vector: u16 = 0;
@@ -532,7 +1257,7 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
                     self.cpu.set_reg(Reg8::A, None, self.current_user);
                 } else {
                     // Set user
-                    self.current_user = e & 0x0F;
+                    self.set_current_user(e);
                 }
             }
 
@@ -590,9 +1315,6 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
 
             // Unimplemented functions - just return success
             _ => {
-                if self.trace {
-                    eprintln!("[BDOS] Unimplemented: {:?}", func);
-                }
                 self.cpu.set_reg(Reg8::A, None, 0);
             }
         }
@@ -603,10 +1325,23 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
     /// Handle CBIOS call.
     fn handle_cbios(&mut self) -> CpmResult<Option<CpmExitInfo>> {
         let pc = self.cpu.get_pc();
-        let func = ((pc - addr::CBIOS) / 3) as u8;
-
-        if self.trace {
-            eprintln!("[CBIOS] Function {}", func);
+        let func = self.memory_map.cbios_function(pc).unwrap_or(0xFF);
+        self.console.set_time(self.t_states());
+
+        if self.tracing() {
+            let sp = self.cpu.get_sp() as usize;
+            let caller = u16::from_le_bytes([self.memory[sp], self.memory[(sp + 1) & 0xFFFF]]);
+            self.emit(TraceEvent::CbiosCall {
+                t_states: self.t_states(),
+                caller,
+                caller_symbol: self.symbol_for(caller),
+                function: func,
+                name: CBIOS_NAMES
+                    .get(func as usize)
+                    .copied()
+                    .unwrap_or("UNKNOWN")
+                    .to_string(),
+            });
         }
 
         match func {
@@ -625,7 +1360,7 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             }
             3 => {
                 // CONIN - console input
-                let ch = self.console.wait_for_key();
+                let ch = self.wait_for_key();
                 self.cpu.set_reg(Reg8::A, None, ch);
             }
             4 => {
@@ -633,6 +1368,15 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
                 let c = self.cpu.get_reg(Reg8::C, None);
                 self.console.write(c);
             }
+            9 => {
+                // SELDSK - DPH of a mounted drive, else 0
+                let drive = self.cpu.get_reg(Reg8::C, None);
+                let dph = match self.disk_tables {
+                    Some(tables) if self.drive(drive).is_some() => tables.dph,
+                    _ => 0,
+                };
+                self.cpu.set_reg16(StkReg16::HL, dph);
+            }
             _ => {}
         }
 
@@ -659,11 +1403,23 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let drive = self.effective_drive(fcb.drive());
         let filename = fcb.filename();
 
-        if let Some(fs) = &self.drives[drive as usize] {
+        if let Some(fs) = self.drives.get(drive as usize).and_then(|d| d.as_ref()) {
             if let Some(data) = fs.read_file(&filename) {
                 // Store file in open_files
                 let handle = self.open_files.len() as u32 + 1;
+                let size = data.len();
+                if let Some(log) = &mut self.file_log {
+                    log.record_read(drive, &filename, &data);
+                }
                 self.open_files.push((drive, filename.clone(), data, false));
+                self.trace_file(handle as usize - 1, |drive, filename| {
+                    TraceEvent::FileOpen {
+                        drive,
+                        filename,
+                        size,
+                        created: false,
+                    }
+                });
 
                 // Store handle in FCB
                 fcb.init();
@@ -694,11 +1450,24 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             if idx < self.open_files.len() {
                 // Write back if modified
                 let (drive, filename, data, modified) = &self.open_files[idx];
-                if *modified {
-                    if let Some(fs) = &mut self.drives[*drive as usize] {
+                let modified = *modified;
+                if modified {
+                    if let Some(fs) = self
+                        .drives
+                        .get_mut(*drive as usize)
+                        .and_then(|d| d.as_mut())
+                    {
                         let _ = fs.write_file(filename, data);
                     }
+                    if let Some(log) = &mut self.file_log {
+                        log.record_write(*drive, filename);
+                    }
                 }
+                self.trace_file(idx, |drive, filename| TraceEvent::FileClose {
+                    drive,
+                    filename,
+                    modified,
+                });
             }
             fcb.clear_fd();
             self.memory[fcb_addr as usize..fcb_addr as usize + 36].copy_from_slice(&fcb_mem);
@@ -751,6 +1520,11 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             fcb.set_current_record(record + 1);
             self.memory[fcb_addr as usize..fcb_addr as usize + 36].copy_from_slice(&fcb_mem);
 
+            self.trace_file(idx, |drive, filename| TraceEvent::FileRead {
+                drive,
+                filename,
+                record,
+            });
             self.cpu.set_reg(Reg8::A, None, 0x00);
         }
 
@@ -794,12 +1568,24 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         fcb.set_current_record(record + 1);
         self.memory[fcb_addr as usize..fcb_addr as usize + 36].copy_from_slice(&fcb_mem);
 
+        self.trace_file(idx, |drive, filename| TraceEvent::FileWrite {
+            drive,
+            filename,
+            record,
+        });
+
         self.cpu.set_reg(Reg8::A, None, 0x00);
 
         Ok(())
     }
 
     /// BDOS 22: Make (create) file.
+    ///
+    /// The empty file is written to the drive at once, as CP/M makes the
+    /// directory entry, rather than when the file is closed or flushed.
+    /// It can be opened or found before then, stays on the drive even if
+    /// the run stops before a flush, and a make on a drive that refuses
+    /// writes returns 0FFH.
     fn bdos_make_file(&mut self, fcb_addr: u16) -> CpmResult<()> {
         let mut fcb_mem = [0u8; 36];
         fcb_mem.copy_from_slice(&self.memory[fcb_addr as usize..fcb_addr as usize + 36]);
@@ -808,10 +1594,32 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let drive = self.effective_drive(fcb.drive());
         let filename = fcb.filename();
 
-        // Create empty file in open_files
+        // Create the directory entry now so the file can be reopened or
+        // found before it is closed (LOAD makes, then opens its output)
+        let created = self
+            .drives
+            .get_mut(drive as usize)
+            .and_then(|d| d.as_mut())
+            .is_some_and(|fs| fs.write_file(&filename, &[]).is_ok());
+        if !created {
+            self.cpu.set_reg(Reg8::A, None, 0xFF);
+            return Ok(());
+        }
+
+        if let Some(log) = &mut self.file_log {
+            log.record_write(drive, &filename);
+        }
         let handle = self.open_files.len() as u32 + 1;
         self.open_files
-            .push((drive, filename.clone(), Vec::new(), true));
+            .push((drive, filename.clone(), Vec::new(), false));
+        self.trace_file(handle as usize - 1, |drive, filename| {
+            TraceEvent::FileOpen {
+                drive,
+                filename,
+                size: 0,
+                created: true,
+            }
+        });
 
         // Store handle in FCB
         fcb.init();
@@ -834,8 +1642,14 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let drive = self.effective_drive(fcb.drive());
         let filename = fcb.filename();
 
-        if let Some(fs) = &mut self.drives[drive as usize] {
+        if let Some(fs) = self.drives.get_mut(drive as usize).and_then(|d| d.as_mut()) {
             if fs.delete_file(&filename) {
+                if self.tracing() {
+                    self.emit(TraceEvent::FileDelete {
+                        drive: (b'A' + drive) as char,
+                        filename,
+                    });
+                }
                 self.cpu.set_reg(Reg8::A, None, 0x00);
             } else {
                 self.cpu.set_reg(Reg8::A, None, 0xFF);
@@ -858,7 +1672,7 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         self.search_pattern_name.copy_from_slice(fcb.raw_name());
         self.search_pattern_ext.copy_from_slice(fcb.raw_ext());
 
-        if let Some(fs) = &self.drives[drive as usize] {
+        if let Some(fs) = self.drives.get(drive as usize).and_then(|d| d.as_ref()) {
             self.dir_entries = fs.list_files();
             self.dir_entries.sort();
             self.dir_index = 0;
@@ -931,13 +1745,23 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let new_fcb = Fcb::new(&mut new_fcb_mem);
         let new_name = new_fcb.filename();
 
-        if let Some(fs) = &mut self.drives[drive as usize] {
+        if let Some(fs) = self.drives.get_mut(drive as usize).and_then(|d| d.as_mut()) {
             // Read old file
             if let Some(data) = fs.read_file(&old_name) {
                 // Write to new name
                 if fs.write_file(&new_name, &data).is_ok() {
                     // Delete old
                     fs.delete_file(&old_name);
+                    if let Some(log) = &mut self.file_log {
+                        log.record_write(drive, &new_name);
+                    }
+                    if self.tracing() {
+                        self.emit(TraceEvent::FileRename {
+                            drive: (b'A' + drive) as char,
+                            old_name,
+                            new_name,
+                        });
+                    }
                     self.cpu.set_reg(Reg8::A, None, 0x00);
                 } else {
                     self.cpu.set_reg(Reg8::A, None, 0xFF);
@@ -987,6 +1811,11 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
             let len = end - offset;
             self.memory[dma..dma + len].copy_from_slice(&data[offset..end]);
 
+            self.trace_file(idx, |drive, filename| TraceEvent::FileRead {
+                drive,
+                filename,
+                record,
+            });
             self.cpu.set_reg(Reg8::A, None, 0x00);
         }
 
@@ -1023,6 +1852,11 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         data[offset..offset + RECORD_SIZE].copy_from_slice(&self.memory[dma..dma + RECORD_SIZE]);
 
         self.open_files[idx].3 = true;
+        self.trace_file(idx, |drive, filename| TraceEvent::FileWrite {
+            drive,
+            filename,
+            record,
+        });
         self.cpu.set_reg(Reg8::A, None, 0x00);
 
         Ok(())
@@ -1037,9 +1871,9 @@ impl<C: CpmConsole, D: DriveFS> CpmEmulator<C, D> {
         let drive = self.effective_drive(fcb.drive());
         let filename = fcb.filename();
 
-        if let Some(fs) = &self.drives[drive as usize] {
+        if let Some(fs) = self.drives.get(drive as usize).and_then(|d| d.as_ref()) {
             if let Some(data) = fs.read_file(&filename) {
-                let records = (data.len() + RECORD_SIZE - 1) / RECORD_SIZE;
+                let records = data.len().div_ceil(RECORD_SIZE);
                 fcb.set_random_record(records as u32);
                 self.memory[fcb_addr as usize..fcb_addr as usize + 36].copy_from_slice(&fcb_mem);
                 self.cpu.set_reg(Reg8::A, None, 0x00);
@@ -1081,7 +1915,7 @@ mod tests {
 
         // Check BDOS vector at 0x0005
         assert_eq!(emu.memory[0x0005], 0xC3); // JP
-        assert_eq!(emu.memory[0x0006], 0x00); // Low byte of BDOS
+        assert_eq!(emu.memory[0x0006], 0x06); // Low byte of BDOS
         assert_eq!(emu.memory[0x0007], 0xFE); // High byte of BDOS
     }
 
@@ -1106,4 +1940,516 @@ mod tests {
         assert_eq!(result.reason, ExitReason::WarmBoot);
         assert_eq!(emu.console().output_string(), "Hi");
     }
+
+    #[test]
+    fn test_load_hex() {
+        // test_hello_world as HEX, with the second CALL in its own record
+        let hex = ":090100000E021E48CD05001E6927\r\n\
+                   :06010900CD0500C300005B\r\n\
+                   :0000000000\r\n\x1A";
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        assert_eq!(emu.load_hex(hex.as_bytes()).unwrap(), 0x100);
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert_eq!(emu.console().output_string(), "Hi");
+        assert!(emu.load_hex(b":0100000000\r\n").is_err());
+    }
+
+    #[test]
+    fn test_load_prl() {
+        // LD C,2; LD E,'H'; CALL 5; LD A,(0111H); LD E,A; CALL 5; JP 0; DB 'i'
+        let code = [
+            0x0E, 0x02, 0x1E, b'H', 0xCD, 0x05, 0x00, 0x3A, 0x11, 0x01, 0x5F, 0xCD, 0x05, 0x00,
+            0xC3, 0x00, 0x00, b'i',
+        ];
+        let mut prl = vec![0; 0x100];
+        prl[1] = code.len() as u8;
+        prl.extend_from_slice(&code);
+        prl.extend_from_slice(&[0b0000_0000, 0b0100_0000, 0b0000_0000]);
+
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.load_prl(&prl, 0x8000).unwrap();
+        assert_eq!(emu.memory()[0x8009], 0x80);
+
+        let result = emu.run_from(0x8000).unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert_eq!(emu.console().output_string(), "Hi");
+    }
+
+    #[test]
+    fn test_memory_map() {
+        // LD HL,(6); LD (0120H),HL; LD C,2; LD E,'!'; CALL 5; CALL 0BA0CH (CONOUT); HALT
+        let program = [
+            0x2A, 0x06, 0x00, 0x22, 0x20, 0x01, 0x0E, 0x02, 0x1E, b'!', 0xCD, 0x05, 0x00, 0x0E,
+            b'?', 0xCD, 0x0C, 0xBA, 0x76,
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.set_memory_map(MemoryMap::for_size(48).unwrap());
+        emu.load_com(&program);
+        emu.start(addr::TPA);
+        assert_eq!(emu.registers().sp, 0xABFE);
+
+        let result = emu.resume().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(&emu.memory()[0x120..0x122], [0x06, 0xAC]);
+        assert_eq!(emu.console().output_string(), "!?");
+    }
+
+    #[test]
+    fn test_bios_image() {
+        // Copy CONOUT's vector from the table found via 0001H and call the
+        // copy, then ask the BDOS for the DPB
+        let program = [
+            0x2A, 0x01, 0x00, // LD HL,(0001)
+            0x11, 0x09, 0x00, // LD DE,9
+            0x19, // ADD HL,DE
+            0x11, 0x00, 0x02, // LD DE,0200
+            0x01, 0x03, 0x00, // LD BC,3
+            0xED, 0xB0, // LDIR
+            0x0E, b'X', // LD C,'X'
+            0xCD, 0x00, 0x02, // CALL 0200
+            0x0E, 0x1F, // LD C,31
+            0xCD, 0x05, 0x00, // CALL 5
+            0x22, 0x10, 0x02, // LD (0210),HL
+            0x76, // HALT
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.load_com(&program);
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(emu.console().output_string(), "X");
+        assert_eq!(emu.memory()[0x200], 0xC3);
+        let dpb = u16::from_le_bytes([emu.memory()[0x210], emu.memory()[0x211]]) as usize;
+        assert_eq!(&emu.memory()[dpb..dpb + 15], DPB.to_bytes());
+    }
+
+    #[test]
+    fn test_trace_events() {
+        use crate::trace::TraceLog;
+
+        // LD C,15; LD DE,5CH; CALL 5; LD C,20; LD DE,5CH; CALL 5; JP 0
+        let program = [
+            0x0E, 15, 0x11, 0x5C, 0x00, 0xCD, 0x05, 0x00, // open
+            0x0E, 20, 0x11, 0x5C, 0x00, 0xCD, 0x05, 0x00, // read
+            0xC3, 0x00, 0x00,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        let mut drive = MemoryDriveFS::new();
+        drive.add_file_str("DATA.TXT", "hello");
+        emu.mount(0, drive);
+        emu.load_com(&program);
+        {
+            let mut fcb = Fcb::new(&mut emu.memory[addr::FCB1 as usize..]);
+            fcb.parse_filename("DATA.TXT");
+        }
+
+        let log = TraceLog::new();
+        emu.set_trace_sink(Box::new(log.clone()));
+        emu.run().unwrap();
+
+        let events = log.events();
+        assert_eq!(events.len(), 5);
+        assert!(matches!(
+            &events[0],
+            TraceEvent::FileOpen { drive: 'A', filename, size: 5, created: false }
+                if filename == "DATA.TXT"
+        ));
+        match &events[1] {
+            TraceEvent::BdosCall {
+                caller,
+                function,
+                name,
+                fcb,
+                a,
+                ..
+            } => {
+                assert_eq!(*caller, 0x0108);
+                assert_eq!(*function, 15);
+                assert_eq!(name.as_deref(), Some("OpenFile"));
+                assert_eq!(fcb.as_deref(), Some("A:DATA.TXT"));
+                assert_eq!(*a, 0);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(matches!(&events[2], TraceEvent::FileRead { record: 0, .. }));
+        assert!(matches!(
+            &events[3],
+            TraceEvent::BdosCall {
+                function: 20,
+                dma: Some(0x0080),
+                ..
+            }
+        ));
+        assert!(matches!(&events[4], TraceEvent::Exit { pc: 0, .. }));
+    }
+
+    #[test]
+    fn test_profiler() {
+        // CALL 0108H; JP 0; (0108) LD C,2; LD E,'X'; CALL 5; RET
+        let program = [
+            0xCD, 0x08, 0x01, 0xC3, 0x00, 0x00, 0x00, 0x00, // main
+            0x0E, 0x02, 0x1E, b'X', 0xCD, 0x05, 0x00, 0xC9, // sub
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        emu.enable_profiling();
+        emu.run().unwrap();
+
+        let profile = emu.take_profiler().unwrap();
+        assert_eq!(profile.instruction_count(0x0108), 1);
+        assert_eq!(profile.bdos_stats()[&2].calls, 1);
+        // CALL 5 lands on JP BDOS in page zero before the intercept
+        assert_eq!(
+            profile.folded(emu.symbols()),
+            "0100 27\n0100;0108 41\n0100;0108;0005 10\n"
+        );
+        assert!(profile.report(5, emu.symbols()).contains("ConsoleOutput"));
+    }
+
+    #[test]
+    fn test_symbols_kept_when_opening_com() {
+        // LD C,15; LD DE,5CH; CALL 5; HALT
+        let program = [0x0E, 15, 0x11, 0x5C, 0x00, 0xCD, 0x05, 0x00, 0x76];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        let mut drive = MemoryDriveFS::new();
+        drive.add_file_str("PROG.SYM", "0100 START\r\n0108 DONE\r\n");
+        drive.add_file("DATA.COM", vec![0xC9]);
+        drive.add_file_str("DATA.SYM", "0100 OTHER\r\n");
+        emu.mount(0, drive);
+        emu.load_com(&program);
+        assert!(emu.load_symbols_for(0, "PROG.COM"));
+        // Opening a .COM as data, as PIP would, is not a program load
+        Fcb::new(&mut emu.memory[addr::FCB1 as usize..]).parse_filename("DATA.COM");
+
+        let log = crate::trace::TraceLog::new();
+        emu.set_trace_sink(Box::new(log.clone()));
+        emu.run().unwrap();
+
+        assert_eq!(emu.symbols().get("DONE"), Some(0x0108));
+        assert_eq!(emu.symbols().get("OTHER"), None);
+        let text: Vec<String> = log.events().iter().map(|e| e.to_string()).collect();
+        assert!(text
+            .iter()
+            .any(|t| t.starts_with("[BDOS] DONE OpenFile(15)")));
+        assert!(text.iter().any(|t| t.starts_with("[EXIT] Halt at DONE ")));
+    }
+
+    #[test]
+    fn test_set_args_fills_fcbs() {
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+
+        emu.set_args("b:*.asm $pz [x]");
+        let mem = emu.memory();
+        assert_eq!(mem[0x80] as usize, " B:*.ASM $PZ [X]".len());
+        assert_eq!(&mem[0x81..0x91], b" B:*.ASM $PZ [X]");
+        assert_eq!(&mem[0x5C..0x68], b"\x02????????ASM");
+        assert_eq!(&mem[0x6C..0x78], b"\x00$PZ        ");
+
+        emu.set_args("");
+        assert_eq!(emu.memory()[0x80], 0);
+        assert_eq!(&emu.memory()[0x5C..0x68], b"\x00           ");
+    }
+
+    #[test]
+    fn test_select_disk_out_of_range() {
+        // LD C,14; LD E,190; CALL 5; LD C,15; LD DE,5C; CALL 5; HALT
+        let program = [
+            0x0E, 0x0E, 0x1E, 190, 0xCD, 0x05, 0x00, 0x0E, 0x0F, 0x11, 0x5C, 0x00, 0xCD, 0x05,
+            0x00, 0x76,
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(0, MemoryDriveFS::new());
+        emu.load_com(&program);
+        emu.set_args("Z:FOO.TXT");
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(emu.registers().af >> 8, 0xFF);
+        assert_eq!(emu.current_drive(), 0);
+    }
+
+    #[test]
+    fn test_make_then_open() {
+        // LD C,22; LD DE,5C; CALL 5; LD C,15; LD DE,5C; CALL 5; HALT
+        let program = [
+            0x0E, 0x16, 0x11, 0x5C, 0x00, 0xCD, 0x05, 0x00, 0x0E, 0x0F, 0x11, 0x5C, 0x00, 0xCD,
+            0x05, 0x00, 0x76,
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(0, MemoryDriveFS::new());
+        emu.load_com(&program);
+        emu.set_args("NEW.COM");
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(emu.registers().af >> 8, 0x00);
+        assert_eq!(emu.drive(0).unwrap().read_file("NEW.COM"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_submit_feeds_shell() {
+        // Shell: read a command with BDOS 10, print it with BDOS 9, HALT
+        let shell = [
+            0x11, 0x20, 0x01, // LD DE,0120
+            0x0E, 0x0A, // LD C,10
+            0xCD, 0x05, 0x00, // CALL 5
+            0x3A, 0x21, 0x01, // LD A,(0121)
+            0x6F, // LD L,A
+            0x26, 0x00, // LD H,0
+            0x11, 0x22, 0x01, // LD DE,0122
+            0x19, // ADD HL,DE
+            0x36, b'$', // LD (HL),'$'
+            0x0E, 0x09, // LD C,9
+            0xCD, 0x05, 0x00, // CALL 5
+            0x76, // HALT
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(0, MemoryDriveFS::new());
+        emu.memory_mut()[0x120] = 0x40;
+        emu.set_shell(&shell, addr::TPA);
+        emu.submit(&["STAT", "PIP"]).unwrap();
+
+        let result = emu.run_from(0).unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(emu.console().output_string(), "STAT\r\nSTAT");
+        assert_eq!(
+            emu.drive(0).unwrap().read_file("$$$.SUB").unwrap().len(),
+            RECORD_SIZE
+        );
+    }
+
+    #[test]
+    fn test_step_and_registers() {
+        // LD A,42; LD HL,1234H; JP 0
+        let program = [0x3E, 42, 0x21, 0x34, 0x12, 0xC3, 0x00, 0x00];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        emu.start(addr::TPA);
+
+        assert!(emu.step().unwrap().is_none());
+        assert!(emu.step().unwrap().is_none());
+        let regs = emu.registers();
+        assert_eq!(regs.af >> 8, 42);
+        assert_eq!(regs.hl, 0x1234);
+        assert_eq!(regs.pc, 0x0105);
+
+        let mut patched = regs;
+        patched.bc_alt = 0xBEEF;
+        patched.ix = 0x4000;
+        emu.set_registers(&patched);
+        assert_eq!(emu.registers(), patched);
+
+        assert!(emu.step().unwrap().is_none()); // JP 0
+        let exit = emu.step().unwrap().unwrap();
+        assert_eq!(exit.reason, ExitReason::WarmBoot);
+    }
+
+    #[test]
+    fn test_io_ports() {
+        use crate::io::tests::{Access, MockDevice};
+
+        // LD A,55H; OUT (10H),A; IN A,(11H); LD (0200H),A; IN A,(20H);
+        // LD (0201H),A; JP 0
+        let program = [
+            0x3E, 0x55, 0xD3, 0x10, 0xDB, 0x11, 0x32, 0x00, 0x02, 0xDB, 0x20, 0x32, 0x01, 0x02,
+            0xC3, 0x00, 0x00,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        let (device, log) = MockDevice::new(0x99);
+        emu.io_mut().attach(0x10..=0x11, Box::new(device)).unwrap();
+        emu.load_com(&program);
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert_eq!(
+            *log.lock().unwrap(),
+            [Access::Write(0, 0x55), Access::Read(1)]
+        );
+        assert_eq!(emu.memory()[0x200], 0x99);
+        assert_eq!(emu.memory()[0x201], 0xFF);
+    }
+
+    #[test]
+    fn test_timer_interrupt_im1() {
+        // RST 38H handler: INC B; EI; RETI
+        // Main: LD B,0; IM 1; EI; wait: HALT; LD A,B; CP 3; JR NZ,wait; DI; HALT
+        let program = [
+            0x06, 0x00, 0xED, 0x56, 0xFB, 0x76, 0x78, 0xFE, 0x03, 0x20, 0xFA, 0xF3, 0x76,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        emu.memory_mut()[0x38..0x3C].copy_from_slice(&[0x04, 0xFB, 0xED, 0x4D]);
+        emu.set_timer(Some(Timer::new(1000)));
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(result.pc, 0x010C);
+        assert_eq!(emu.registers().bc >> 8, 3);
+        assert!((3000..4000).contains(&result.t_states));
+    }
+
+    #[test]
+    fn test_device_interrupt_im2() {
+        use crate::io::tests::MockDevice;
+
+        // Main: LD A,02H; LD I,A; IM 2; EI; OUT (20H),A; NOP; DI; HALT
+        let program = [
+            0x3E, 0x02, 0xED, 0x47, 0xED, 0x5E, 0xFB, 0xD3, 0x20, 0x00, 0xF3, 0x76,
+        ];
+        // Handler at 0180H: LD A,0AAH; LD (0200H),A; EI; RETI
+        let handler = [0x3E, 0xAA, 0x32, 0x00, 0x02, 0xFB, 0xED, 0x4D];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        let (mut device, _) = MockDevice::new(0);
+        device.irq_vector = Some(0x40);
+        emu.io_mut().attach(0x20..=0x20, Box::new(device)).unwrap();
+        emu.load_com(&program);
+        emu.memory_mut()[0x180..0x188].copy_from_slice(&handler);
+        emu.memory_mut()[0x240..0x242].copy_from_slice(&[0x80, 0x01]);
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::Halt);
+        assert_eq!(emu.memory()[0x200], 0xAA);
+        assert!(!emu.interrupts().is_pending());
+    }
+
+    #[test]
+    fn test_halt_with_interrupts_enabled_and_no_source() {
+        // EI; HALT - nothing can wake it, so it still ends the program
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&[0xFB, 0x76]);
+        assert_eq!(emu.run().unwrap().reason, ExitReason::Halt);
+    }
+
+    #[test]
+    fn test_halt_with_devices_that_never_interrupt() {
+        use crate::io::{DebugPort, Rtc, RtcClock};
+
+        // EI; HALT - a debug port and a clock cannot raise interrupts
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.io_mut()
+            .attach(0x30..=0x30, Box::new(DebugPort::new(std::io::sink())))
+            .unwrap();
+        emu.io_mut()
+            .attach(0x40..=0x4F, Box::new(Rtc::new(RtcClock::virtual_time())))
+            .unwrap();
+        emu.load_com(&[0xFB, 0x76]);
+        assert_eq!(emu.run().unwrap().reason, ExitReason::Halt);
+    }
+
+    #[cfg(feature = "banked")]
+    #[test]
+    fn test_bank_switching() {
+        use crate::bank::MemoryBank;
+
+        // LD A,1; OUT (0FFH),A; LD A,77H; LD (4000H),A; LD (0C000H),A;
+        // XOR A; OUT (0FFH),A; JP 0
+        let program = [
+            0x3E, 0x01, 0xD3, 0xFF, 0x3E, 0x77, 0x32, 0x00, 0x40, 0x32, 0x00, 0xC0, 0xAF, 0xD3,
+            0xFF, 0xC3, 0x00, 0x00,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        // Bank 1 needs the program too, where the OUT returns to
+        let mut bank = MemoryBank::with_defaults(2).unwrap();
+        let memory: &mut [u8; 65536] = emu.memory_mut().try_into().unwrap();
+        for (i, &b) in memory[..0x200].to_vec().iter().enumerate() {
+            bank.write(memory, 1, i as u16, b);
+        }
+        emu.set_memory_bank(Some(bank));
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert_eq!(emu.memory()[0x4000], 0);
+        assert_eq!(emu.memory()[0xC000], 0x77);
+
+        let snapshot = emu.memory_bank().unwrap();
+        assert_eq!(snapshot.current(), 0);
+        assert_eq!(
+            snapshot.read(emu.memory().try_into().unwrap(), 1, 0x4000),
+            0x77
+        );
+        assert!(emu.select_bank(1));
+        assert_eq!(emu.memory()[0x4000], 0x77);
+        assert!(!emu.select_bank(2));
+
+        // Restoring the snapshot brings back bank 0 as it was
+        emu.restore_memory_bank(snapshot);
+        assert_eq!(emu.memory()[0x4000], 0);
+        assert_eq!(emu.memory_bank().unwrap().current(), 0);
+    }
+
+    #[cfg(feature = "banked")]
+    #[test]
+    fn test_bdos_after_bank_switch() {
+        use crate::bank::MemoryBank;
+
+        // LD A,1; OUT (0FFH),A; then in bank 1: LD C,2; LD E,'X'; CALL 5; JP 0
+        let program = [
+            0x3E, 0x01, 0xD3, 0xFF, 0x0E, 0x02, 0x1E, b'X', 0xCD, 0x05, 0x00, 0xC3, 0x00, 0x00,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        // Only the TPA is copied; page zero comes from set_memory_bank
+        let mut bank = MemoryBank::with_defaults(2).unwrap();
+        let memory: &mut [u8; 65536] = emu.memory_mut().try_into().unwrap();
+        for (i, &b) in program.iter().enumerate() {
+            bank.write(memory, 1, 0x100 + i as u16, b);
+        }
+        emu.set_memory_bank(Some(bank));
+
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert_eq!(emu.console().output_string(), "X");
+        assert_eq!(emu.memory_bank().unwrap().current(), 1);
+    }
+
+    #[test]
+    fn test_throttled_run() {
+        // LD BC,2000; loop: DEC BC; LD A,B; OR C; JR NZ,loop; JP 0
+        // About 52000 T-states, 26 ms at 2 MHz
+        let program = [
+            0x01, 0xD0, 0x07, 0x0B, 0x78, 0xB1, 0x20, 0xFB, 0xC3, 0x00, 0x00,
+        ];
+
+        let console = HeadlessConsole::new();
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> = CpmEmulator::new(console);
+        emu.load_com(&program);
+        emu.set_throttle(Some(Throttle::from_mhz(2.0).unwrap()));
+
+        let start = Instant::now();
+        let result = emu.run().unwrap();
+        assert_eq!(result.reason, ExitReason::WarmBoot);
+        assert!(result.t_states > 50_000);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(24));
+    }
 }
@@ -38,6 +38,44 @@ pub enum CpmError {
     #[error("Package error: {0}")]
     Package(String),
 
+    #[error("Integrity check failed: {0}")]
+    Integrity(String),
+
+    #[error("Dependency error: {0}")]
+    Dependency(String),
+
+    #[error("{file} is provided by both {first} and {second}")]
+    FileConflict {
+        file: String,
+        first: String,
+        second: String,
+    },
+
+    #[error("Script step {step} failed: {message}\n--- recent output ---\n{output}")]
+    Script {
+        step: usize,
+        message: String,
+        output: String,
+    },
+
+    #[error("Intel HEX: {0}")]
+    Hex(String),
+
+    #[error("PRL: {0}")]
+    Prl(String),
+
+    #[error("Invalid memory map: {0}")]
+    MemoryMap(String),
+
+    #[error("I/O port {0:02X}H already in use")]
+    PortInUse(u8),
+
+    #[error("Throttle error: {0}")]
+    Throttle(String),
+
+    #[error("Invalid pattern: {0}")]
+    Pattern(#[from] regex::Error),
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -0,0 +1,533 @@
+//! GDB Remote Serial Protocol stub for the Z80 CPU.
+//!
+//! Exposes a `CpmEmulator` to any debugger that speaks the GDB RSP over TCP.
+//! Registers use the layout of GDB's z80 target: AF, BC, DE, HL, SP, PC,
+//! IX, IY, AF', BC', DE', HL', IR (16 bits each, little-endian).
+//!
+//! Supported packets: `?`, `g`/`G`, `p`/`P`, `m`/`M`, `c`, `s`, `Z0`/`z0`,
+//! `Z1`/`z1`, `k`, `D`, `qSupported`, `qAttached`, `QStartNoAckMode`, plus the
+//! `0x03` interrupt byte while the target is running. BDOS exits (warm boot
+//! without a shell, HALT) are reported as `W` stop replies.
+//!
+//! `monitor where`, `monitor sym NAME|ADDR` and `monitor symbols` (`qRcmd`)
+//! report addresses against the emulator's symbol table. With the `banked`
+//! feature, `monitor bank [N]` shows or switches the target's memory bank.
+//! Switching is the same as the program writing N to the select port, so
+//! the program runs on in that bank; memory packets always see the
+//! selected bank.
+//!
+//! Enabled with the `gdb` feature.
+
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::console::CpmConsole;
+use crate::emulator::{CpmEmulator, Registers};
+use crate::error::{CpmError, CpmResult};
+use crate::fs::DriveFS;
+use crate::{CpmExitInfo, ExitReason};
+
+/// Number of registers in the z80 target description.
+const NUM_REGS: usize = 13;
+
+/// Instructions executed between checks for an interrupt byte.
+const POLL_INTERVAL: usize = 4096;
+
+/// SIGINT, reported after a `0x03` interrupt.
+const SIGINT: u8 = 2;
+/// SIGTRAP, reported after a step or breakpoint.
+const SIGTRAP: u8 = 5;
+
+/// How a debugging session ended.
+#[derive(Debug, Clone)]
+pub enum SessionEnd {
+    /// The program exited; the debugger was sent a `W` reply.
+    Exited(CpmExitInfo),
+    /// The debugger detached; the program can be resumed.
+    Detached,
+    /// The debugger killed the program.
+    Killed,
+}
+
+/// GDB RSP stub bound to a single debugger connection.
+pub struct GdbStub {
+    stream: TcpStream,
+    breakpoints: HashSet<u16>,
+    no_ack: bool,
+    /// Bytes received but not yet parsed.
+    pending: Vec<u8>,
+}
+
+impl GdbStub {
+    /// Listen on `addr` and wait for a debugger to connect.
+    pub fn listen<A: ToSocketAddrs>(addr: A) -> CpmResult<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Self::accept(&listener)
+    }
+
+    /// Accept one debugger connection from an existing listener.
+    pub fn accept(listener: &TcpListener) -> CpmResult<Self> {
+        let (stream, _) = listener.accept()?;
+        Ok(Self::new(stream))
+    }
+
+    /// Create a stub on an already-connected stream.
+    pub fn new(stream: TcpStream) -> Self {
+        let _ = stream.set_nodelay(true);
+        Self {
+            stream,
+            breakpoints: HashSet::new(),
+            no_ack: false,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Serve debugger requests until the program exits or the debugger
+    /// detaches or kills it. The emulator must already be started.
+    pub fn serve<C: CpmConsole, D: DriveFS>(
+        &mut self,
+        emu: &mut CpmEmulator<C, D>,
+    ) -> CpmResult<SessionEnd> {
+        loop {
+            let Some(packet) = self.read_packet()? else {
+                // Connection closed: treat as detach
+                return Ok(SessionEnd::Detached);
+            };
+
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            match self.handle_packet(emu, &packet)? {
+                Some(end) => return Ok(end),
+                None => continue,
+            }
+        }
+    }
+
+    /// Handle one packet. Returns Some when the session ends.
+    fn handle_packet<C: CpmConsole, D: DriveFS>(
+        &mut self,
+        emu: &mut CpmEmulator<C, D>,
+        packet: &str,
+    ) -> CpmResult<Option<SessionEnd>> {
+        let cmd = packet.get(..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+
+        match cmd {
+            "?" => self.send_signal(SIGTRAP)?,
+            "g" => {
+                let regs = reg_values(&emu.registers());
+                let hex: String = regs.iter().map(|&v| hex_u16_le(v)).collect();
+                self.send_packet(&hex)?;
+            }
+            "G" => match parse_hex_bytes(args) {
+                Some(bytes) if bytes.len() >= NUM_REGS * 2 => {
+                    let mut regs = emu.registers();
+                    for n in 0..NUM_REGS {
+                        let v = u16::from_le_bytes([bytes[n * 2], bytes[n * 2 + 1]]);
+                        set_reg_value(&mut regs, n, v);
+                    }
+                    emu.set_registers(&regs);
+                    self.send_packet("OK")?;
+                }
+                _ => self.send_packet("E01")?,
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < NUM_REGS => {
+                    let v = reg_values(&emu.registers())[n];
+                    self.send_packet(&hex_u16_le(v))?;
+                }
+                _ => self.send_packet("E01")?,
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, v)| {
+                    let n = usize::from_str_radix(n, 16).ok()?;
+                    let bytes = parse_hex_bytes(v)?;
+                    (n < NUM_REGS && bytes.len() >= 2)
+                        .then(|| (n, u16::from_le_bytes([bytes[0], bytes[1]])))
+                });
+                match parsed {
+                    Some((n, v)) => {
+                        let mut regs = emu.registers();
+                        set_reg_value(&mut regs, n, v);
+                        emu.set_registers(&regs);
+                        self.send_packet("OK")?;
+                    }
+                    None => self.send_packet("E01")?,
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let mem = emu.memory();
+                    let hex: String = (0..len)
+                        .map(|i| format!("{:02x}", mem[addr.wrapping_add(i as u16) as usize]))
+                        .collect();
+                    self.send_packet(&hex)?;
+                }
+                None => self.send_packet("E01")?,
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_addr_len(range)?;
+                    let bytes = parse_hex_bytes(data)?;
+                    (bytes.len() == len).then_some((addr, bytes))
+                });
+                match parsed {
+                    Some((addr, bytes)) => {
+                        let mem = emu.memory_mut();
+                        for (i, b) in bytes.into_iter().enumerate() {
+                            mem[addr.wrapping_add(i as u16) as usize] = b;
+                        }
+                        self.send_packet("OK")?;
+                    }
+                    None => self.send_packet("E01")?,
+                }
+            }
+            "c" => {
+                self.set_resume_address(emu, args);
+                return self.run(emu, false);
+            }
+            "s" => {
+                self.set_resume_address(emu, args);
+                return self.run(emu, true);
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next();
+                let addr = parts.next().and_then(|a| u16::from_str_radix(a, 16).ok());
+                match (kind, addr) {
+                    (Some("0") | Some("1"), Some(addr)) => {
+                        if cmd == "Z" {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+                        self.send_packet("OK")?;
+                    }
+                    // Watchpoints are not supported
+                    _ => self.send_packet("")?,
+                }
+            }
+            "k" => return Ok(Some(SessionEnd::Killed)),
+            "D" => {
+                self.send_packet("OK")?;
+                return Ok(Some(SessionEnd::Detached));
+            }
+            "H" => self.send_packet("OK")?,
+            _ if packet.starts_with("qSupported") => {
+                self.send_packet("PacketSize=4000;QStartNoAckMode+")?
+            }
+            _ if packet == "qAttached" => self.send_packet("1")?,
+            _ if packet.starts_with("qRcmd,") => match parse_hex_bytes(&packet[6..]) {
+                Some(bytes) => {
+                    let output = monitor_command(emu, &String::from_utf8_lossy(&bytes));
+                    let hex: String = output.bytes().map(|b| format!("{:02x}", b)).collect();
+                    self.send_packet(&hex)?;
+                }
+                None => self.send_packet("E01")?,
+            },
+            _ if packet == "QStartNoAckMode" => {
+                self.send_packet("OK")?;
+                self.no_ack = true;
+            }
+            _ => self.send_packet("")?,
+        }
+
+        Ok(None)
+    }
+
+    /// Apply the optional resume address of `c`/`s`.
+    fn set_resume_address<C: CpmConsole, D: DriveFS>(
+        &self,
+        emu: &mut CpmEmulator<C, D>,
+        args: &str,
+    ) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            let mut regs = emu.registers();
+            regs.pc = addr;
+            emu.set_registers(&regs);
+        }
+    }
+
+    /// Execute until a breakpoint, interrupt or exit (or one step).
+    fn run<C: CpmConsole, D: DriveFS>(
+        &mut self,
+        emu: &mut CpmEmulator<C, D>,
+        single_step: bool,
+    ) -> CpmResult<Option<SessionEnd>> {
+        let mut count = 0usize;
+        loop {
+            if let Some(info) = emu.step()? {
+                let code = match info.reason {
+                    ExitReason::WarmBoot => 0,
+                    ExitReason::Halt => 1,
+                    _ => 2,
+                };
+                self.send_packet(&format!("W{:02x}", code))?;
+                return Ok(Some(SessionEnd::Exited(info)));
+            }
+
+            if single_step || self.breakpoints.contains(&emu.pc()) {
+                self.send_signal(SIGTRAP)?;
+                return Ok(None);
+            }
+
+            count += 1;
+            if count.is_multiple_of(POLL_INTERVAL) && self.poll_interrupt()? {
+                self.send_signal(SIGINT)?;
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Non-blocking check for the `0x03` interrupt byte.
+    fn poll_interrupt(&mut self) -> CpmResult<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 64];
+        let result = self.stream.read(&mut buf);
+        self.stream.set_nonblocking(false)?;
+
+        match result {
+            Ok(n) => {
+                let data = &buf[..n];
+                if let Some(pos) = data.iter().position(|&b| b == 0x03) {
+                    self.pending.extend_from_slice(&data[pos + 1..]);
+                    Ok(true)
+                } else {
+                    self.pending.extend_from_slice(data);
+                    Ok(false)
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn send_signal(&mut self, signal: u8) -> CpmResult<()> {
+        self.send_packet(&format!("S{:02x}", signal))
+    }
+
+    /// Send a `$data#cs` packet, waiting for the `+` ack unless disabled.
+    fn send_packet(&mut self, data: &str) -> CpmResult<()> {
+        let checksum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        let frame = format!("${}#{:02x}", data, checksum);
+
+        loop {
+            self.stream.write_all(frame.as_bytes())?;
+            self.stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'+') => return Ok(()),
+                Some(b'-') => continue,
+                Some(other) => {
+                    // Not an ack; keep it for the packet reader
+                    self.pending.insert(0, other);
+                    return Ok(());
+                }
+                None => return Err(CpmError::Io(ErrorKind::ConnectionAborted.into())),
+            }
+        }
+    }
+
+    /// Read one packet payload. Returns None when the connection closes.
+    fn read_packet(&mut self) -> CpmResult<Option<Vec<u8>>> {
+        loop {
+            // Skip to the start of a packet
+            match self.read_byte()? {
+                Some(b'$') => {}
+                Some(0x03) => return Ok(Some(b"?".to_vec())),
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+
+            let (Some(hi), Some(lo)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+            let expected = parse_hex_bytes(&format!("{}{}", hi as char, lo as char));
+            let checksum = data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+            let valid = expected.is_some_and(|v| v.first() == Some(&checksum));
+
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> CpmResult<Option<u8>> {
+        if !self.pending.is_empty() {
+            return Ok(Some(self.pending.remove(0)));
+        }
+        let mut b = [0u8; 1];
+        match self.stream.read(&mut b)? {
+            0 => Ok(None),
+            _ => Ok(Some(b[0])),
+        }
+    }
+}
+
+/// Run a `monitor` command and return its output.
+fn monitor_command<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+    command: &str,
+) -> String {
+    let mut words = command.split_whitespace();
+    let (first, second) = (words.next(), words.next());
+    #[cfg(feature = "banked")]
+    if first == Some("bank") {
+        return bank_command(emu, second);
+    }
+    let symbols = emu.symbols();
+    match (first, second) {
+        (Some("where"), None) => {
+            let pc = emu.pc();
+            format!("PC = {:04X} ({})\n", pc, symbols.format_addr(pc))
+        }
+        (Some("sym"), Some(arg)) => {
+            if let Some(addr) = symbols.get(arg) {
+                format!("{} = {:04X}\n", arg.to_uppercase(), addr)
+            } else if let Ok(addr) = u16::from_str_radix(arg.trim_start_matches("0x"), 16) {
+                format!("{:04X} = {}\n", addr, symbols.format_addr(addr))
+            } else {
+                format!("No symbol {}\n", arg)
+            }
+        }
+        (Some("symbols"), None) => symbols
+            .iter()
+            .map(|(name, addr)| format!("{:04X} {}\n", addr, name))
+            .collect(),
+        _ => MONITOR_HELP.to_string(),
+    }
+}
+
+/// Reply to an unknown `monitor` command.
+#[cfg(feature = "banked")]
+const MONITOR_HELP: &str = "Commands: where, sym NAME|ADDR, symbols, bank [N]\n";
+#[cfg(not(feature = "banked"))]
+const MONITOR_HELP: &str = "Commands: where, sym NAME|ADDR, symbols\n";
+
+/// `monitor bank [N]`: report the memory bank, or switch the CPU to bank N
+/// as an OUT to the select port would.
+#[cfg(feature = "banked")]
+fn bank_command<C: CpmConsole, D: DriveFS>(
+    emu: &mut CpmEmulator<C, D>,
+    arg: Option<&str>,
+) -> String {
+    if let Some(n) = arg {
+        match n.parse() {
+            Ok(n) if emu.select_bank(n) => {}
+            _ => return format!("No bank {}\n", n),
+        }
+    }
+    match emu.memory_bank() {
+        Some(bank) => format!(
+            "Bank {} of {}, common from {:04X}\n",
+            bank.current(),
+            bank.count(),
+            bank.common_base()
+        ),
+        None => "Memory is not banked\n".to_string(),
+    }
+}
+
+/// Registers in z80 target order.
+fn reg_values(r: &Registers) -> [u16; NUM_REGS] {
+    [
+        r.af,
+        r.bc,
+        r.de,
+        r.hl,
+        r.sp,
+        r.pc,
+        r.ix,
+        r.iy,
+        r.af_alt,
+        r.bc_alt,
+        r.de_alt,
+        r.hl_alt,
+        u16::from_be_bytes([r.i, r.r]),
+    ]
+}
+
+fn set_reg_value(r: &mut Registers, n: usize, v: u16) {
+    match n {
+        0 => r.af = v,
+        1 => r.bc = v,
+        2 => r.de = v,
+        3 => r.hl = v,
+        4 => r.sp = v,
+        5 => r.pc = v,
+        6 => r.ix = v,
+        7 => r.iy = v,
+        8 => r.af_alt = v,
+        9 => r.bc_alt = v,
+        10 => r.de_alt = v,
+        11 => r.hl_alt = v,
+        _ => [r.i, r.r] = v.to_be_bytes(),
+    }
+}
+
+fn hex_u16_le(v: u16) -> String {
+    let [lo, hi] = v.to_le_bytes();
+    format!("{:02x}{:02x}", lo, hi)
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u16, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((addr, len.min(0x10000)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_encoding() {
+        let regs = Registers {
+            af: 0x1234,
+            i: 0xAB,
+            r: 0xCD,
+            ..Default::default()
+        };
+        let values = reg_values(&regs);
+        assert_eq!(hex_u16_le(values[0]), "3412");
+        assert_eq!(values[12], 0xABCD);
+
+        let mut back = Registers::default();
+        for (n, &v) in values.iter().enumerate() {
+            set_reg_value(&mut back, n, v);
+        }
+        assert_eq!(back, regs);
+    }
+
+    #[test]
+    fn test_parse_helpers() {
+        assert_eq!(parse_hex_bytes("00ff10"), Some(vec![0x00, 0xFF, 0x10]));
+        assert_eq!(parse_hex_bytes("0"), None);
+        assert_eq!(parse_addr_len("100,4"), Some((0x100, 4)));
+    }
+}
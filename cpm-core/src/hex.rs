@@ -0,0 +1,295 @@
+//! Intel HEX images.
+//!
+//! ASM and most CP/M assemblers write `.HEX` files that `LOAD.COM` turns
+//! into `.COM` files. `HexImage` does the same natively: it parses records
+//! with checksum verification, keeps gaps between segments, and writes
+//! files `LOAD.COM` accepts.
+//!
+//! A zero-length record ends the file, as in the CP/M tools. Its address
+//! field, when nonzero, is the start address; start-address records
+//! (types 03 and 05) are understood as well.
+
+use std::fmt::Write as _;
+
+use crate::bdos::addr;
+use crate::error::{CpmError, CpmResult};
+
+const DATA: u8 = 0x00;
+const EOF: u8 = 0x01;
+const EXT_SEGMENT: u8 = 0x02;
+const START_SEGMENT: u8 = 0x03;
+const EXT_LINEAR: u8 = 0x04;
+const START_LINEAR: u8 = 0x05;
+
+/// Data bytes per record written by `to_hex`.
+pub const RECORD_LEN: usize = 16;
+
+/// Memory contents described by an Intel HEX file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HexImage {
+    /// Contiguous runs of bytes, sorted by address and not overlapping.
+    pub segments: Vec<(u16, Vec<u8>)>,
+    /// Entry point, when the file gives one.
+    pub start: Option<u16>,
+}
+
+impl HexImage {
+    /// Image of `data` loaded at `address`.
+    pub fn from_binary(address: u16, data: &[u8]) -> Self {
+        let segments = if data.is_empty() {
+            Vec::new()
+        } else {
+            vec![(address, data.to_vec())]
+        };
+        Self {
+            segments,
+            start: None,
+        }
+    }
+
+    /// Parse HEX text. Blank lines and anything after ^Z are ignored.
+    pub fn parse(data: &[u8]) -> CpmResult<Self> {
+        let text = String::from_utf8_lossy(data);
+        let text = text.split('\x1A').next().unwrap_or_default();
+        let mut image = Self::default();
+        let mut bytes: Vec<(u16, u8)> = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let number = idx + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let bad = |message: &str| CpmError::Hex(format!("line {}: {}", number, message));
+            let (len, address, kind, payload) = parse_record(line).map_err(|m| bad(&m))?;
+
+            match kind {
+                DATA if len == 0 => {
+                    if address != 0 {
+                        image.start = Some(address);
+                    }
+                    break;
+                }
+                DATA => {
+                    if address as usize + payload.len() > 0x10000 {
+                        return Err(bad("record runs past 64K"));
+                    }
+                    bytes.extend(
+                        payload
+                            .iter()
+                            .enumerate()
+                            .map(|(i, &b)| (address + i as u16, b)),
+                    );
+                }
+                EOF => {
+                    if address != 0 {
+                        image.start = Some(address);
+                    }
+                    break;
+                }
+                EXT_SEGMENT | EXT_LINEAR => {
+                    if payload.iter().any(|&b| b != 0) {
+                        return Err(bad("address above 64K"));
+                    }
+                }
+                START_SEGMENT | START_LINEAR if payload.len() == 4 => {
+                    image.start = Some(u16::from_be_bytes([payload[2], payload[3]]));
+                }
+                START_SEGMENT | START_LINEAR => return Err(bad("bad start address record")),
+                _ => return Err(bad(&format!("unknown record type {:02X}", kind))),
+            }
+        }
+
+        // Later records win, as they would when loading into memory
+        bytes.sort_by_key(|&(address, _)| address);
+        bytes.dedup_by(|later, earlier| {
+            if later.0 == earlier.0 {
+                earlier.1 = later.1;
+                true
+            } else {
+                false
+            }
+        });
+        for (address, byte) in bytes {
+            match image.segments.last_mut() {
+                Some((start, data)) if *start as usize + data.len() == address as usize => {
+                    data.push(byte)
+                }
+                _ => image.segments.push((address, vec![byte])),
+            }
+        }
+        Ok(image)
+    }
+
+    /// Lowest address with data.
+    pub fn low_address(&self) -> Option<u16> {
+        self.segments.first().map(|(address, _)| *address)
+    }
+
+    /// One past the highest address with data.
+    pub fn end_address(&self) -> Option<u32> {
+        self.segments
+            .last()
+            .map(|(address, data)| *address as u32 + data.len() as u32)
+    }
+
+    /// Flat image from `base` to the end of the data, gaps filled with
+    /// zeros.
+    pub fn to_binary(&self, base: u16) -> CpmResult<Vec<u8>> {
+        if self.low_address().is_some_and(|low| low < base) {
+            return Err(CpmError::Hex(format!("data below {:04X}H", base)));
+        }
+        let end = self.end_address().unwrap_or(base as u32);
+        let mut out = vec![0; (end - base as u32) as usize];
+        for (address, data) in &self.segments {
+            let offset = (address - base) as usize;
+            out[offset..offset + data.len()].copy_from_slice(data);
+        }
+        Ok(out)
+    }
+
+    /// `.COM` file: the image from the TPA on.
+    pub fn to_com(&self) -> CpmResult<Vec<u8>> {
+        self.to_binary(addr::TPA)
+    }
+
+    /// HEX text with CR LF line ends. The start address goes in the
+    /// address field of the end record, where `LOAD.COM` ignores it.
+    pub fn to_hex(&self) -> String {
+        let mut out = String::new();
+        for (address, data) in &self.segments {
+            for (i, chunk) in data.chunks(RECORD_LEN).enumerate() {
+                let address = address.wrapping_add((i * RECORD_LEN) as u16);
+                write_record(&mut out, address, DATA, chunk);
+            }
+        }
+        write_record(&mut out, self.start.unwrap_or(0), EOF, &[]);
+        out
+    }
+}
+
+/// Split a record into length, address, type and data.
+fn parse_record(line: &str) -> Result<(usize, u16, u8, Vec<u8>), String> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or_else(|| "missing ':'".to_string())?;
+    if !digits.is_ascii() {
+        return Err("invalid hex digit".to_string());
+    }
+    if digits.len() % 2 != 0 || digits.len() < 10 {
+        return Err("truncated record".to_string());
+    }
+    let bytes = (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "invalid hex digit".to_string())?;
+
+    let len = bytes[0] as usize;
+    if bytes.len() != len + 5 {
+        return Err(format!(
+            "length {} does not match {} data bytes",
+            len,
+            bytes.len() - 5
+        ));
+    }
+    let sum = bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+    if sum != 0 {
+        let expected = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0u8, |acc, &b| acc.wrapping_add(b))
+            .wrapping_neg();
+        return Err(format!(
+            "checksum {:02X}, expected {:02X}",
+            bytes[bytes.len() - 1],
+            expected
+        ));
+    }
+    let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+    Ok((len, address, bytes[3], bytes[4..4 + len].to_vec()))
+}
+
+fn write_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let [hi, lo] = address.to_be_bytes();
+    let header = [data.len() as u8, hi, lo, kind];
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |acc, &b| acc.wrapping_add(b));
+    out.push(':');
+    for b in header.iter().chain(data) {
+        let _ = write!(out, "{:02X}", b);
+    }
+    let _ = write!(out, "{:02X}\r\n", sum.wrapping_neg());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ASM output for a short program, ending in a zero-length record.
+    const ASM_HEX: &str = ":100100000E09110801CD0500C948656C6C6F2124EA\r\n\
+                           :0000000000\r\n\x1A\x1A";
+
+    #[test]
+    fn test_parse_asm_output() {
+        let image = HexImage::parse(ASM_HEX.as_bytes()).unwrap();
+        assert_eq!(image.low_address(), Some(0x100));
+        assert_eq!(image.end_address(), Some(0x110));
+        assert_eq!(image.start, None);
+        let com = image.to_com().unwrap();
+        assert_eq!(&com[..3], [0x0E, 0x09, 0x11]);
+        assert_eq!(&com[9..15], b"Hello!");
+    }
+
+    #[test]
+    fn test_gaps_and_start() {
+        let hex = ":02010000C3FF3B\n:020110001234A7\n:0400000500000100F6\n:00000001FF\n";
+        let image = HexImage::parse(hex.as_bytes()).unwrap();
+        assert_eq!(
+            image.segments,
+            [(0x100, vec![0xC3, 0xFF]), (0x110, vec![0x12, 0x34])]
+        );
+        assert_eq!(image.start, Some(0x100));
+
+        let com = image.to_com().unwrap();
+        assert_eq!(com.len(), 0x12);
+        assert_eq!(&com[2..0x10], [0; 14]);
+        assert_eq!(&com[0x10..], [0x12, 0x34]);
+        assert!(image.to_binary(0x200).is_err());
+    }
+
+    #[test]
+    fn test_bad_records() {
+        assert!(HexImage::parse(b":0000000000\n")
+            .unwrap()
+            .segments
+            .is_empty());
+
+        for (hex, message) in [
+            (":00000000", "truncated record"),
+            (":02010000C3FF3C", "checksum 3C, expected 3B"),
+            (":03010000C3FF3B", "length 3 does not match 2 data bytes"),
+            ("02010000C3FF3B", "missing ':'"),
+            (":0201G000C3FF3B", "invalid hex digit"),
+            (":020000040001F9", "address above 64K"),
+        ] {
+            match HexImage::parse(hex.as_bytes()) {
+                Err(CpmError::Hex(m)) => assert_eq!(m, format!("line 1: {}", message)),
+                other => panic!("{}: got {:?}", hex, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data: Vec<u8> = (0..40).collect();
+        let mut image = HexImage::from_binary(0x100, &data);
+        image.start = Some(0x100);
+        let hex = image.to_hex();
+
+        assert_eq!(hex.lines().count(), 4);
+        assert!(hex.ends_with(":00010001FE\r\n"));
+        assert_eq!(HexImage::parse(hex.as_bytes()).unwrap(), image);
+    }
+}
@@ -0,0 +1,185 @@
+//! Package integrity: content digests and signatures.
+//!
+//! A manifest may give the SHA-256 of each file (`FileEntry::sha256`) and
+//! a digest of the whole package (`PackageManifest::sha256`, see
+//! `package_digest`); `load_packages` checks both when present. A package
+//! can also carry a detached Ed25519 signature over the ZIP, in a
+//! `.sig` file next to it holding the signature in hex, checked against a
+//! trusted-keys file by `load_signed_package`.
+//!
+//! A trusted-keys file has one public key per line, in hex, optionally
+//! followed by a name; blank lines and `#` comments are ignored.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+use crate::error::{CpmError, CpmResult};
+use crate::package::{load_package, LoadedPackage};
+use crate::receipt::sha256_hex;
+
+/// Digest of a package's files: SHA-256 over `NAME\nDIGEST\n` for each
+/// file in name order, so renaming, adding or removing a file changes it.
+pub fn package_digest(files: &HashMap<String, Vec<u8>>) -> String {
+    let mut names: Vec<&String> = files.keys().collect();
+    names.sort();
+    let mut listing = String::new();
+    for name in names {
+        listing.push_str(name);
+        listing.push('\n');
+        listing.push_str(&sha256_hex(&files[name]));
+        listing.push('\n');
+    }
+    sha256_hex(listing.as_bytes())
+}
+
+/// Fail unless `data` has the SHA-256 `expected` (hex, any case).
+pub(crate) fn check_digest(what: &str, data: &[u8], expected: &str) -> CpmResult<()> {
+    if sha256_hex(data).eq_ignore_ascii_case(expected.trim()) {
+        Ok(())
+    } else {
+        Err(CpmError::Integrity(format!(
+            "{} does not match its digest",
+            what
+        )))
+    }
+}
+
+/// Public keys whose signatures are accepted.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedKeys {
+    keys: Vec<(String, VerifyingKey)>,
+}
+
+impl TrustedKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a trusted-keys file.
+    pub fn parse(text: &str) -> CpmResult<Self> {
+        let mut keys = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (hex, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let key = parse_hex::<32>(hex)
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                .ok_or_else(|| {
+                    CpmError::Package(format!("trusted keys line {}: invalid key", number + 1))
+                })?;
+            let name = match name.trim() {
+                "" => hex[..16].to_string(),
+                name => name.to_string(),
+            };
+            keys.keys.push((name, key));
+        }
+        Ok(keys)
+    }
+
+    pub fn load(path: &Path) -> CpmResult<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Trust `key` under `name`.
+    pub fn add(&mut self, name: &str, key: VerifyingKey) {
+        self.keys.push((name.to_string(), key));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Name of the key that made `signature` (hex) over `data`.
+    pub fn verify(&self, data: &[u8], signature: &str) -> CpmResult<&str> {
+        let signature = parse_hex::<64>(signature.trim())
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or_else(|| CpmError::Integrity("malformed signature".to_string()))?;
+        self.keys
+            .iter()
+            .find(|(_, key)| key.verify(data, &signature).is_ok())
+            .map(|(name, _)| name.as_str())
+            .ok_or_else(|| {
+                CpmError::Integrity("signature does not match any trusted key".to_string())
+            })
+    }
+}
+
+/// Where the detached signature of `zip_path` is kept: `NAME.zip.sig`.
+pub fn signature_path(zip_path: &Path) -> PathBuf {
+    let mut name = zip_path.as_os_str().to_owned();
+    name.push(".sig");
+    PathBuf::from(name)
+}
+
+/// Load a package whose signature must verify against `keys`. Digests
+/// in the manifest are checked as usual.
+pub fn load_signed_package(path: &Path, keys: &TrustedKeys) -> CpmResult<LoadedPackage> {
+    let zip = std::fs::read(path)?;
+    let sig_path = signature_path(path);
+    let signature = std::fs::read_to_string(&sig_path)
+        .map_err(|_| CpmError::Integrity(format!("{} is not signed", path.display())))?;
+    keys.verify(&zip, &signature)?;
+    load_package(std::io::Cursor::new(zip))
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_package_digest() {
+        let mut files = HashMap::new();
+        files.insert("A.COM".to_string(), b"a".to_vec());
+        files.insert("B.TXT".to_string(), b"b".to_vec());
+        let digest = package_digest(&files);
+
+        files.insert("C.TXT".to_string(), Vec::new());
+        assert_ne!(package_digest(&files), digest);
+        files.remove("C.TXT");
+        assert_eq!(package_digest(&files), digest);
+    }
+
+    #[test]
+    fn test_trusted_keys() {
+        let signer = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[9; 32]);
+        let text = format!(
+            "# release keys\n\n{} release\n",
+            hex(signer.verifying_key().as_bytes())
+        );
+        let keys = TrustedKeys::parse(&text).unwrap();
+
+        let data = b"package";
+        let good = hex(&signer.sign(data).to_bytes());
+        assert_eq!(keys.verify(data, &good).unwrap(), "release");
+        assert!(matches!(
+            keys.verify(b"packagf", &good),
+            Err(CpmError::Integrity(_))
+        ));
+        let untrusted = hex(&other.sign(data).to_bytes());
+        assert!(keys.verify(data, &untrusted).is_err());
+        assert!(keys.verify(data, "00").is_err());
+
+        assert!(TrustedKeys::parse("abcd\n").is_err());
+    }
+}
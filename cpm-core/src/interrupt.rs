@@ -0,0 +1,146 @@
+//! Maskable interrupt sources.
+//!
+//! The `InterruptController` collects requests from a periodic timer
+//! running on the T-state clock and from I/O devices, and hands them to
+//! the CPU one at a time when it accepts interrupts. Each request carries
+//! the byte a device would put on the data bus: the opcode to execute in
+//! IM 0, ignored in IM 1 (always RST 38H), the low byte of the vector
+//! table address in IM 2.
+
+use std::collections::VecDeque;
+
+/// Data bus byte used when none is given: RST 38H in IM 0.
+pub const DEFAULT_VECTOR: u8 = 0xFF;
+
+/// Periodic interrupt source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timer {
+    /// T-states between ticks.
+    pub period: u64,
+    /// Data bus byte for each tick.
+    pub vector: u8,
+}
+
+impl Timer {
+    /// Tick every `period` T-states with the default vector.
+    pub fn new(period: u64) -> Self {
+        Self {
+            period: period.max(1),
+            vector: DEFAULT_VECTOR,
+        }
+    }
+
+    /// Use `vector` for the ticks.
+    pub fn with_vector(mut self, vector: u8) -> Self {
+        self.vector = vector;
+        self
+    }
+}
+
+/// Pending interrupt requests.
+#[derive(Debug, Default)]
+pub struct InterruptController {
+    timer: Option<Timer>,
+    /// T-state of the next tick, set on the first poll after the timer is.
+    next_tick: Option<u64>,
+    pending: VecDeque<u8>,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start, replace or (with `None`) stop the periodic timer. The first
+    /// tick comes one period after the next poll.
+    pub fn set_timer(&mut self, timer: Option<Timer>) {
+        self.timer = timer;
+        self.next_tick = None;
+    }
+
+    pub fn timer(&self) -> Option<Timer> {
+        self.timer
+    }
+
+    /// Queue a request with the given data bus byte.
+    pub fn raise(&mut self, vector: u8) {
+        self.pending.push_back(vector);
+    }
+
+    /// Queue a timer tick if one is due at `t_states`. Ticks missed while
+    /// interrupts were disabled collapse into one, as a level-triggered
+    /// line would.
+    pub fn poll(&mut self, t_states: u64) {
+        let Some(timer) = self.timer else {
+            return;
+        };
+        let next = *self.next_tick.get_or_insert(t_states + timer.period);
+        if t_states >= next {
+            if !self.pending.contains(&timer.vector) {
+                self.pending.push_back(timer.vector);
+            }
+            let missed = (t_states - next) / timer.period;
+            self.next_tick = Some(next + (missed + 1) * timer.period);
+        }
+    }
+
+    /// T-state of the next timer tick, if the timer is running.
+    pub fn next_tick(&self) -> Option<u64> {
+        self.next_tick
+    }
+
+    /// The request the CPU would accept next.
+    pub fn peek(&self) -> Option<u8> {
+        self.pending.front().copied()
+    }
+
+    /// Remove the request the CPU accepted.
+    pub fn acknowledge(&mut self) -> Option<u8> {
+        self.pending.pop_front()
+    }
+
+    /// True if a request is waiting.
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Drop pending requests and restart the timer period.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.next_tick = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timer_ticks() {
+        let mut ic = InterruptController::new();
+        ic.set_timer(Some(Timer::new(1000).with_vector(0x10)));
+        ic.poll(50);
+        assert_eq!(ic.next_tick(), Some(1050));
+        ic.poll(1049);
+        assert!(!ic.is_pending());
+        ic.poll(1050);
+        assert_eq!(ic.peek(), Some(0x10));
+
+        // Three periods late: one request, next tick stays on the grid
+        ic.poll(4100);
+        assert_eq!(ic.acknowledge(), Some(0x10));
+        assert!(!ic.is_pending());
+        assert_eq!(ic.next_tick(), Some(5050));
+    }
+
+    #[test]
+    fn test_device_requests_queue() {
+        let mut ic = InterruptController::new();
+        ic.raise(0x20);
+        ic.raise(0x22);
+        ic.poll(1_000_000);
+        assert_eq!(ic.acknowledge(), Some(0x20));
+        assert_eq!(ic.acknowledge(), Some(0x22));
+        assert_eq!(ic.acknowledge(), None);
+    }
+}
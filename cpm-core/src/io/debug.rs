@@ -0,0 +1,38 @@
+//! Debug output port.
+
+use std::io::Write;
+
+use super::IoDevice;
+
+/// "printf port": every byte written goes to a host stream unchanged, so
+/// test programs can log without going through the BDOS. Reads return
+/// 0FFH.
+pub struct DebugPort {
+    output: Box<dyn Write + Send>,
+}
+
+impl DebugPort {
+    pub fn new(output: impl Write + Send + 'static) -> Self {
+        Self {
+            output: Box::new(output),
+        }
+    }
+
+    /// Port writing to the host's stderr.
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+}
+
+impl IoDevice for DebugPort {
+    fn read(&mut self, _offset: u8, _t_states: u64) -> u8 {
+        0xFF
+    }
+
+    fn write(&mut self, _offset: u8, value: u8, _t_states: u64) {
+        let _ = self.output.write_all(&[value]);
+        if value == b'\n' {
+            let _ = self.output.flush();
+        }
+    }
+}
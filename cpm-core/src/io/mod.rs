@@ -0,0 +1,228 @@
+//! Z80 I/O port devices.
+//!
+//! Programs that talk to hardware use `IN` and `OUT`. An `IoBus` maps
+//! port ranges (the low byte of the port address) to `IoDevice`s;
+//! unmapped ports read 0FFH and ignore writes. Built-in devices:
+//! - `SerialPort`: 8251 or Z80-SIO style UART bound to a byte stream
+//! - `Rtc`: real-time clock with BCD registers
+//! - `DebugPort`: bytes written go straight to a host stream
+
+mod debug;
+mod rtc;
+mod serial;
+
+pub use debug::DebugPort;
+pub use rtc::{Rtc, RtcClock};
+pub use serial::{SerialKind, SerialPort};
+
+use std::ops::RangeInclusive;
+
+use crate::error::{CpmError, CpmResult};
+
+/// A peripheral on the I/O bus.
+///
+/// `offset` is the port relative to the first port the device was
+/// attached at; `t_states` is the CPU time of the access.
+pub trait IoDevice: Send {
+    /// Read a byte from a port.
+    fn read(&mut self, offset: u8, t_states: u64) -> u8;
+
+    /// Write a byte to a port.
+    fn write(&mut self, offset: u8, value: u8, t_states: u64);
+
+    /// Polled before each instruction: the data bus byte of a new
+    /// interrupt request, if the device raises one at `t_states`.
+    fn interrupt(&mut self, _t_states: u64) -> Option<u8> {
+        None
+    }
+
+    /// True if `interrupt` may ever return a request, so a HALT with
+    /// interrupts enabled can be woken by this device.
+    fn can_interrupt(&self) -> bool {
+        false
+    }
+}
+
+/// Port-mapped device registry.
+pub struct IoBus {
+    devices: Vec<(u8, Box<dyn IoDevice>)>,
+    /// Index into `devices` for each port.
+    ports: [Option<u8>; 256],
+}
+
+impl Default for IoBus {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            ports: [None; 256],
+        }
+    }
+}
+
+impl IoBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map a range of ports to a device.
+    pub fn attach(
+        &mut self,
+        ports: RangeInclusive<u8>,
+        device: Box<dyn IoDevice>,
+    ) -> CpmResult<()> {
+        if let Some(port) = ports.clone().find(|&p| self.ports[p as usize].is_some()) {
+            return Err(CpmError::PortInUse(port));
+        }
+        let index = self.devices.len() as u8;
+        self.devices.push((*ports.start(), device));
+        for port in ports {
+            self.ports[port as usize] = Some(index);
+        }
+        Ok(())
+    }
+
+    /// True if no devices are attached.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// True if any attached device can raise interrupts.
+    pub fn can_interrupt(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|(_, device)| device.can_interrupt())
+    }
+
+    /// Collect new interrupt requests from all devices, in attach order.
+    pub fn poll_interrupts(&mut self, t_states: u64, mut raise: impl FnMut(u8)) {
+        for (_, device) in &mut self.devices {
+            if let Some(vector) = device.interrupt(t_states) {
+                raise(vector);
+            }
+        }
+    }
+
+    /// True if any device is mapped at `port`.
+    pub fn is_mapped(&self, port: u8) -> bool {
+        self.ports[port as usize].is_some()
+    }
+
+    /// Read from the device at the low byte of `port`.
+    pub fn read(&mut self, port: u16, t_states: u64) -> u8 {
+        let port = port as u8;
+        match self.ports[port as usize] {
+            Some(index) => {
+                let (base, device) = &mut self.devices[index as usize];
+                device.read(port - *base, t_states)
+            }
+            None => 0xFF,
+        }
+    }
+
+    /// Write to the device at the low byte of `port`.
+    pub fn write(&mut self, port: u16, value: u8, t_states: u64) {
+        let port = port as u8;
+        if let Some(index) = self.ports[port as usize] {
+            let (base, device) = &mut self.devices[index as usize];
+            device.write(port - *base, value, t_states);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// One port access seen by a `MockDevice`.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Access {
+        Read(u8),
+        Write(u8, u8),
+    }
+
+    /// Device that logs accesses and reads back a fixed value. Writing to
+    /// offset 0 while `irq_vector` is set raises an interrupt on the next
+    /// poll.
+    pub struct MockDevice {
+        pub value: u8,
+        pub log: Arc<Mutex<Vec<Access>>>,
+        pub irq_vector: Option<u8>,
+        irq: bool,
+    }
+
+    impl MockDevice {
+        pub fn new(value: u8) -> (Self, Arc<Mutex<Vec<Access>>>) {
+            let log = Arc::new(Mutex::new(Vec::new()));
+            (
+                Self {
+                    value,
+                    log: log.clone(),
+                    irq_vector: None,
+                    irq: false,
+                },
+                log,
+            )
+        }
+    }
+
+    impl IoDevice for MockDevice {
+        fn read(&mut self, offset: u8, _t_states: u64) -> u8 {
+            self.log.lock().unwrap().push(Access::Read(offset));
+            self.value
+        }
+
+        fn write(&mut self, offset: u8, value: u8, _t_states: u64) {
+            self.log.lock().unwrap().push(Access::Write(offset, value));
+            self.irq |= offset == 0;
+        }
+
+        fn interrupt(&mut self, _t_states: u64) -> Option<u8> {
+            if std::mem::take(&mut self.irq) {
+                self.irq_vector
+            } else {
+                None
+            }
+        }
+
+        fn can_interrupt(&self) -> bool {
+            self.irq_vector.is_some()
+        }
+    }
+
+    #[test]
+    fn test_port_mapping() {
+        let mut bus = IoBus::new();
+        let (device, log) = MockDevice::new(0x42);
+        bus.attach(0x10..=0x11, Box::new(device)).unwrap();
+
+        assert_eq!(bus.read(0x11, 0), 0x42);
+        assert_eq!(bus.read(0xFF12, 0), 0xFF);
+        bus.write(0x3410, 7, 0);
+        assert_eq!(*log.lock().unwrap(), [Access::Read(1), Access::Write(0, 7)]);
+
+        let (other, _) = MockDevice::new(0);
+        assert!(matches!(
+            bus.attach(0x0F..=0x10, Box::new(other)),
+            Err(CpmError::PortInUse(0x10))
+        ));
+        assert!(bus.is_mapped(0x11) && !bus.is_mapped(0x0F));
+    }
+
+    #[test]
+    fn test_device_interrupts() {
+        let mut bus = IoBus::new();
+        let (mut device, _) = MockDevice::new(0);
+        device.irq_vector = Some(0x40);
+        assert!(!bus.can_interrupt());
+        bus.attach(0x20..=0x20, Box::new(device)).unwrap();
+        assert!(bus.can_interrupt());
+
+        let mut raised = Vec::new();
+        bus.poll_interrupts(0, |v| raised.push(v));
+        bus.write(0x20, 1, 0);
+        bus.poll_interrupts(10, |v| raised.push(v));
+        bus.poll_interrupts(20, |v| raised.push(v));
+        assert_eq!(raised, [0x40]);
+    }
+}
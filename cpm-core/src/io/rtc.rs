@@ -0,0 +1,139 @@
+//! Real-time clock with BCD registers.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::IoDevice;
+
+/// Where the clock gets the time from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RtcClock {
+    /// Host wall clock (UTC).
+    #[default]
+    System,
+    /// `epoch_secs` (Unix time) plus elapsed CPU time at `hz`, so runs are
+    /// reproducible.
+    FromTStates { epoch_secs: u64, hz: u64 },
+}
+
+impl RtcClock {
+    /// 1978-01-01 00:00:00 UTC, day 1 of CP/M 3 dates.
+    pub const CPM_EPOCH: u64 = 252_460_800;
+
+    /// Time from the T-state counter of a 4 MHz CPU started at the CP/M
+    /// epoch, as used in deterministic mode.
+    pub fn virtual_time() -> Self {
+        Self::FromTStates {
+            epoch_secs: Self::CPM_EPOCH,
+            hz: 4_000_000,
+        }
+    }
+
+    /// Unix time at `t_states`.
+    pub fn unix_time(&self, t_states: u64) -> u64 {
+        match *self {
+            Self::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            Self::FromTStates { epoch_secs, hz } => epoch_secs + t_states / hz.max(1),
+        }
+    }
+}
+
+/// Clock chip with a register select at offset 0 and the selected
+/// register at offset 1. Registers are BCD: 0 seconds, 1 minutes,
+/// 2 hours, 3 day, 4 month, 5 year (two digits). Others read 0FFH.
+pub struct Rtc {
+    clock: RtcClock,
+    register: u8,
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Self { clock, register: 0 }
+    }
+
+    /// Unix time at `t_states`.
+    pub fn unix_time(&self, t_states: u64) -> u64 {
+        self.clock.unix_time(t_states)
+    }
+}
+
+/// Two-digit BCD.
+fn bcd(value: u64) -> u8 {
+    (((value / 10 % 10) << 4) | (value % 10)) as u8
+}
+
+/// Year, month and day of a count of days since 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    // Howard Hinnant's algorithm, with eras of 400 years from 0000-03-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097) as u64;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe as i64 + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+impl IoDevice for Rtc {
+    fn read(&mut self, offset: u8, t_states: u64) -> u8 {
+        if offset == 0 {
+            return self.register;
+        }
+        let secs = self.unix_time(t_states);
+        let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+        match self.register {
+            0 => bcd(secs % 60),
+            1 => bcd(secs / 60 % 60),
+            2 => bcd(secs / 3600 % 24),
+            3 => bcd(day),
+            4 => bcd(month),
+            5 => bcd(year as u64),
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, offset: u8, value: u8, _t_states: u64) {
+        if offset == 0 {
+            self.register = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_registers_from_t_states() {
+        // 1999-12-31 23:59:58 UTC, 4 MHz
+        let mut rtc = Rtc::new(RtcClock::FromTStates {
+            epoch_secs: 946_684_798,
+            hz: 4_000_000,
+        });
+        let read = |rtc: &mut Rtc, register, t| {
+            rtc.write(0, register, t);
+            rtc.read(1, t)
+        };
+        assert_eq!(read(&mut rtc, 0, 0), 0x58);
+        assert_eq!(read(&mut rtc, 2, 0), 0x23);
+        assert_eq!(read(&mut rtc, 5, 0), 0x99);
+        // two seconds later it is the new year
+        let t = 8_000_000;
+        assert_eq!(read(&mut rtc, 0, t), 0x00);
+        assert_eq!(read(&mut rtc, 3, t), 0x01);
+        assert_eq!(read(&mut rtc, 4, t), 0x01);
+        assert_eq!(read(&mut rtc, 5, t), 0x00);
+        assert_eq!(read(&mut rtc, 9, t), 0xFF);
+    }
+}
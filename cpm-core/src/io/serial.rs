@@ -0,0 +1,138 @@
+//! UART bound to a byte stream.
+
+use std::io::{Read, Write};
+
+use super::IoDevice;
+
+/// Status register layout to emulate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialKind {
+    /// Intel 8251: bit 0 TxRDY, bit 1 RxRDY, bit 2 TxEMPTY.
+    I8251,
+    /// Z80-SIO read register 0: bit 0 Rx available, bit 2 Tx empty.
+    Sio,
+}
+
+/// Serial port with the data register at offset 0 and status/control at
+/// offset 1. The transmitter is always ready; received bytes come from
+/// the input stream, which is read when a program polls the status, so it
+/// should not block (a file, a buffer or a pipe that is kept full).
+pub struct SerialPort {
+    kind: SerialKind,
+    input: Box<dyn Read + Send>,
+    output: Box<dyn Write + Send>,
+    /// Byte read ahead from `input` for the status register.
+    pending: Option<u8>,
+    /// SIO register selected by the last control write.
+    register: u8,
+}
+
+impl SerialPort {
+    pub fn new(
+        kind: SerialKind,
+        input: impl Read + Send + 'static,
+        output: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            kind,
+            input: Box::new(input),
+            output: Box::new(output),
+            pending: None,
+            register: 0,
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        if self.pending.is_none() {
+            let mut byte = [0];
+            if let Ok(1) = self.input.read(&mut byte) {
+                self.pending = Some(byte[0]);
+            }
+        }
+        self.pending.is_some()
+    }
+
+    fn status(&mut self) -> u8 {
+        let rx = self.poll();
+        match self.kind {
+            SerialKind::I8251 => 0b101 | if rx { 0b010 } else { 0 },
+            SerialKind::Sio if self.register != 0 => 0,
+            SerialKind::Sio => 0b100 | rx as u8,
+        }
+    }
+}
+
+impl IoDevice for SerialPort {
+    fn read(&mut self, offset: u8, _t_states: u64) -> u8 {
+        match offset {
+            0 => {
+                self.poll();
+                self.pending.take().unwrap_or(0)
+            }
+            _ => {
+                let status = self.status();
+                self.register = 0;
+                status
+            }
+        }
+    }
+
+    fn write(&mut self, offset: u8, value: u8, _t_states: u64) {
+        match offset {
+            0 => {
+                let _ = self.output.write_all(&[value]);
+                let _ = self.output.flush();
+            }
+            // 8251 mode/command words need no action; the SIO selects a
+            // register for the next control read
+            _ if self.kind == SerialKind::Sio => self.register = value & 0x07,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+
+    /// Output stream the test can inspect after the port owns it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_8251() {
+        let out = SharedBuffer::default();
+        let mut port = SerialPort::new(SerialKind::I8251, Cursor::new(b"OK".to_vec()), out.clone());
+
+        port.write(1, 0x37, 0); // command word
+        assert_eq!(port.read(1, 0), 0b111);
+        assert_eq!(port.read(0, 0), b'O');
+        assert_eq!(port.read(0, 0), b'K');
+        assert_eq!(port.read(1, 0), 0b101);
+        port.write(0, b'!', 0);
+        assert_eq!(*out.0.lock().unwrap(), b"!");
+    }
+
+    #[test]
+    fn test_sio() {
+        let mut port = SerialPort::new(SerialKind::Sio, Cursor::new(b"A".to_vec()), Vec::new());
+        assert_eq!(port.read(1, 0), 0b101);
+        port.write(1, 0x01, 0); // select RR1
+        assert_eq!(port.read(1, 0), 0);
+        assert_eq!(port.read(0, 0), b'A');
+        assert_eq!(port.read(1, 0), 0b100);
+    }
+}
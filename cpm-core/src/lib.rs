@@ -2,8 +2,29 @@
 //!
 //! This crate provides the core components for emulating CP/M 2.2:
 //! - BDOS (Basic Disk Operating System) syscall handling
+//! - BDOS/BIOS code images with trap-backed jump tables
 //! - Virtual filesystem with overlay support
 //! - Console I/O abstraction
+//! - Configurable memory map (TPA size, BDOS/CBIOS placement)
+//! - Built-in console command processor (CCP)
+//! - Package action executor
+//! - Expect-style scripted console
+//! - Program runner with file-change reporting
+//! - Assembler/compiler toolchains and diagnostics
+//! - Z80/8080 disassembler
+//! - Structured syscall tracing
+//! - Execution profiling
+//! - Symbol tables from .SYM/.PRN files
+//! - Intel HEX reading and writing
+//! - Page-relocatable (.PRL/.SPR) executables
+//! - Z80 I/O port devices
+//! - Timer and device interrupts
+//! - Banked memory (`banked` feature)
+//! - Speed throttling to a target CPU clock
+//! - Deterministic mode with T-state based time
+//! - Reproducible build receipts
+//! - Package digests and Ed25519 signatures
+//! - Package dependencies and file conflict detection
 //!
 //! # Architecture
 //!
@@ -13,22 +34,66 @@
 //! - `CpmConsole` trait: Character I/O abstraction
 //! - `CpmEmulator`: Integrates Z80 CPU with BDOS handling
 
+pub mod action;
+#[cfg(feature = "banked")]
+pub mod bank;
 pub mod bdos;
+pub mod bios;
+pub mod ccp;
 pub mod console;
+pub mod diagnostic;
+pub mod disasm;
 pub mod emulator;
 pub mod error;
 pub mod fs;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+pub mod hex;
+pub mod integrity;
+pub mod interrupt;
+pub mod io;
+pub mod memory;
 pub mod package;
+pub mod prl;
+pub mod profile;
+pub mod receipt;
+pub mod resolve;
+pub mod runner;
+pub mod script;
+pub mod symbols;
+pub mod throttle;
+pub mod toolchain;
+pub mod trace;
 pub mod workspace;
 
+pub use action::{find_action, run_action, ActionResult};
+#[cfg(feature = "banked")]
+pub use bank::MemoryBank;
+pub use ccp::{Ccp, FileSpec};
 pub use console::{CpmConsole, HeadlessConsole};
-pub use emulator::CpmEmulator;
+pub use diagnostic::{Diagnostic, Severity};
+pub use emulator::{CpmEmulator, Registers};
 pub use error::{CpmError, CpmResult};
 pub use fs::{to_8_3, DriveFS, MemoryDriveFS, OverlayDriveFS};
+pub use hex::HexImage;
+pub use integrity::{load_signed_package, TrustedKeys};
+pub use interrupt::{InterruptController, Timer};
+pub use io::{IoBus, IoDevice};
+pub use memory::MemoryMap;
 pub use package::{
-    load_package, load_package_from_path, load_packages, LoadedPackage, PackageAction,
-    PackageDriveFS, PackageManifest,
+    load_package, load_package_from_path, load_packages, Dependency, FileConflict, LoadedPackage,
+    PackageAction, PackageDriveFS, PackageManifest,
 };
+pub use prl::PrlImage;
+pub use profile::Profiler;
+pub use receipt::{FileDigest, Receipt, RunConfig};
+pub use resolve::{PackageResolver, Version, VersionReq};
+pub use runner::{CpmRunner, RunOptions, RunResult};
+pub use script::{run_script, ScriptStep, ScriptedConsole};
+pub use symbols::SymbolTable;
+pub use throttle::Throttle;
+pub use toolchain::{CompileResult, ManifestToolchain, Source, Toolchain};
+pub use trace::{JsonSink, TextSink, TraceEvent, TraceLog, TraceSink};
 pub use workspace::{DriveConfig, FileChangeEvent, ShellInfo, Workspace};
 
 /// Reason for program exit.
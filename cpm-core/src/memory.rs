@@ -0,0 +1,150 @@
+//! Memory layout of the emulated system.
+//!
+//! A real CP/M 2.2 is generated for a memory size with MOVCPM: the CCP,
+//! BDOS and BIOS move up together and the TPA ends where the BDOS begins.
+//! `MemoryMap` describes that layout. The default keeps the emulator's
+//! own layout with the BDOS at 0FE00H and a 62K TPA.
+//!
+//! Calls are intercepted at trap addresses: the BDOS entry and the
+//! address its `JP` leads to, and each CBIOS jump table entry and the trap
+//! byte it jumps to. See `bios` for the code images around them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::bdos::addr;
+use crate::error::{CpmError, CpmResult};
+
+/// Entries in the CBIOS jump table.
+pub const CBIOS_ENTRIES: u16 = 17;
+
+/// Offset of the BDOS trap from the entry point, past the entry `JP` and
+/// the four error vectors as in DRI's BDOS.
+pub const BDOS_TRAP_OFFSET: u16 = 11;
+
+/// Addresses of the system components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryMap {
+    /// Where the CCP loads.
+    pub ccp: u16,
+    /// BDOS entry point, the target of the jump at 0005H.
+    pub bdos: u16,
+    /// Start of the CBIOS jump table.
+    pub cbios: u16,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        Self {
+            ccp: addr::CCP,
+            bdos: addr::BDOS_ENTRY,
+            cbios: addr::CBIOS,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// Layout of a CP/M 2.2 generated for `kb` K of memory (20-64), as
+    /// MOVCPM places it: CCP at 3400H plus the bias, BDOS entry 806H and
+    /// BIOS 1600H above the CCP.
+    pub fn for_size(kb: u32) -> CpmResult<Self> {
+        if !(20..=64).contains(&kb) {
+            return Err(CpmError::MemoryMap(format!("{}K is outside 20K-64K", kb)));
+        }
+        let ccp = (0x3400 + (kb - 20) * 1024) as u16;
+        Ok(Self {
+            ccp,
+            bdos: ccp + 0x806,
+            cbios: ccp + 0x1600,
+        })
+    }
+
+    /// Parse a memory size such as `48k`, `56K` or `64`.
+    pub fn parse(s: &str) -> CpmResult<Self> {
+        let digits = s.trim().trim_end_matches(['k', 'K']);
+        let kb = digits
+            .parse()
+            .map_err(|_| CpmError::MemoryMap(format!("invalid memory size: {}", s)))?;
+        Self::for_size(kb)
+    }
+
+    /// First byte above the TPA: the page the BDOS entry is in.
+    pub fn tpa_end(&self) -> u16 {
+        self.bdos & 0xFF00
+    }
+
+    /// Bytes available to programs from 0100H, overwriting the CCP.
+    pub fn tpa_size(&self) -> u16 {
+        self.tpa_end() - addr::TPA
+    }
+
+    /// Initial stack pointer for programs and shells.
+    pub fn stack(&self) -> u16 {
+        self.tpa_end() - 2
+    }
+
+    /// Where the `JP` at the BDOS entry leads.
+    pub fn bdos_trap(&self) -> u16 {
+        self.bdos + BDOS_TRAP_OFFSET
+    }
+
+    /// True at the BDOS entry point or its trap.
+    pub fn is_bdos(&self, pc: u16) -> bool {
+        pc == self.bdos || pc == self.bdos_trap()
+    }
+
+    /// Trap byte the CBIOS jump table entry for `function` leads to.
+    pub fn cbios_trap(&self, function: u8) -> u16 {
+        self.cbios + CBIOS_ENTRIES * 3 + function as u16
+    }
+
+    /// CBIOS function number for a jump table entry or trap address.
+    pub fn cbios_function(&self, pc: u16) -> Option<u8> {
+        let offset = pc.checked_sub(self.cbios)?;
+        if offset < CBIOS_ENTRIES * 3 {
+            Some((offset / 3) as u8)
+        } else if offset < CBIOS_ENTRIES * 4 {
+            Some((offset - CBIOS_ENTRIES * 3) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// First byte after the CBIOS jump table and traps.
+    pub fn cbios_end(&self) -> u32 {
+        self.cbios as u32 + CBIOS_ENTRIES as u32 * 4
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_size() {
+        let map = MemoryMap::for_size(64).unwrap();
+        assert_eq!((map.ccp, map.bdos, map.cbios), (0xE400, 0xEC06, 0xFA00));
+
+        let map = MemoryMap::parse("48k").unwrap();
+        assert_eq!((map.ccp, map.bdos, map.cbios), (0xA400, 0xAC06, 0xBA00));
+        assert_eq!(map.tpa_size(), 0xAB00);
+        assert_eq!(map.stack(), 0xABFE);
+
+        assert_eq!(MemoryMap::parse(" 56K").unwrap().ccp, 0xC400);
+        assert!(MemoryMap::parse("65k").is_err());
+        assert!(MemoryMap::parse("lots").is_err());
+    }
+
+    #[test]
+    fn test_default_layout() {
+        let map = MemoryMap::default();
+        assert_eq!(map.tpa_end(), addr::BDOS);
+        assert_eq!(map.tpa_size(), 0xFD00);
+        assert_eq!(map.stack(), 0xFDFE);
+        assert!(map.is_bdos(0xFE06) && map.is_bdos(0xFE11));
+        assert_eq!(map.cbios_function(0xFF03), Some(1));
+        assert_eq!(map.cbios_function(0xFF30), Some(16));
+        assert_eq!(map.cbios_function(map.cbios_trap(1)), Some(1));
+        assert_eq!(map.cbios_function(0xFF44), None);
+        assert_eq!(map.cbios_function(0xFE06), None);
+    }
+}
@@ -2,6 +2,8 @@
 //!
 //! Packages are ZIP files containing CP/M files and an optional `manifest.mf` JSON file.
 //! The manifest describes the package metadata, files, and actions.
+//! Digests in the manifest are checked as the package loads; see
+//! `integrity`.
 
 use std::collections::{HashMap, HashSet};
 use std::io::{Read, Seek};
@@ -11,6 +13,7 @@ use zip::ZipArchive;
 
 use crate::error::{CpmError, CpmResult};
 use crate::fs::{to_8_3, DriveFS};
+use crate::integrity::{check_digest, package_digest};
 
 /// Action defined in a package manifest.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +35,18 @@ pub struct PackageAction {
     pub package: Option<String>,
 }
 
+impl PackageAction {
+    /// Expand the `submit` template (see `expand_submit_template`) into
+    /// batch lines, one per CR-separated command.
+    pub fn submit_lines(&self, base_name: &str, drive: char) -> Vec<String> {
+        expand_submit_template(self, base_name, Some(drive))
+            .split(['\r', '\n'])
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
+
 /// Interactive script step for menu-driven tools.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InteractiveStep {
@@ -53,6 +68,23 @@ pub struct FileEntry {
     #[serde(rename = "type")]
     #[serde(default)]
     pub file_type: Option<String>,
+    /// SHA-256 of the file (hex), checked at load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+/// Another package a package needs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dependency {
+    /// Package id.
+    pub id: String,
+    /// Version constraint such as `>=2.2` or `>=1.0, <2`; any version if
+    /// absent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Files used from the package, which it must provide.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
 }
 
 /// Package manifest schema.
@@ -74,6 +106,21 @@ pub struct PackageManifest {
     pub meta: Option<serde_json::Value>,
     #[serde(default)]
     pub actions: Vec<PackageAction>,
+    /// Packages that must be loaded before this one.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<Dependency>,
+    /// `package_digest` of every file in the ZIP but the manifest,
+    /// checked at load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl PackageManifest {
+    /// The id other packages refer to this one by: `id`, or the name if
+    /// there is none.
+    pub fn package_id(&self) -> &str {
+        self.id.as_deref().unwrap_or(&self.name)
+    }
 }
 
 /// Loaded package with files and actions.
@@ -84,6 +131,26 @@ pub struct LoadedPackage {
     pub actions: Vec<PackageAction>,
 }
 
+/// A file provided by two packages with different contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileConflict {
+    pub file: String,
+    /// Package the file came from first.
+    pub first: String,
+    /// Package that provides it again.
+    pub second: String,
+}
+
+impl std::fmt::Display for FileConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} from {} overrides {}",
+            self.file, self.second, self.first
+        )
+    }
+}
+
 /// Load packages from ZIP data.
 /// Supports manifest.mf as single object or array of objects.
 /// Returns multiple packages if the manifest is an array.
@@ -136,13 +203,20 @@ pub fn load_packages<R: Read + Seek>(reader: R) -> CpmResult<Vec<LoadedPackage>>
                     required: None,
                     load_address: None,
                     file_type: None,
+                    sha256: None,
                 })
                 .collect(),
             meta: None,
             actions: Vec::new(),
+            dependencies: Vec::new(),
+            sha256: None,
         });
     }
 
+    for manifest in &manifests {
+        verify_manifest_digests(manifest, &all_files)?;
+    }
+
     // Create a LoadedPackage for each manifest
     let mut packages: Vec<LoadedPackage> = Vec::new();
     let mut assigned_files: HashSet<String> = HashSet::new();
@@ -163,7 +237,7 @@ pub fn load_packages<R: Read + Seek>(reader: R) -> CpmResult<Vec<LoadedPackage>>
         // Collect actions for this package
         for action in &manifest.actions {
             let mut action = action.clone();
-            action.package = Some(manifest.id.clone().unwrap_or_else(|| manifest.name.clone()));
+            action.package = Some(manifest.package_id().to_string());
             actions.push(action);
         }
 
@@ -200,6 +274,8 @@ pub fn load_package<R: Read + Seek>(reader: R) -> CpmResult<LoadedPackage> {
                 files: Vec::new(),
                 meta: None,
                 actions: Vec::new(),
+                dependencies: Vec::new(),
+                sha256: None,
             },
             files: HashMap::new(),
             actions: Vec::new(),
@@ -229,6 +305,31 @@ pub fn load_package_from_path(path: &std::path::Path) -> CpmResult<LoadedPackage
     load_package(std::io::BufReader::new(file))
 }
 
+/// Check the digests a manifest gives against the files of its ZIP.
+fn verify_manifest_digests(
+    manifest: &PackageManifest,
+    files: &HashMap<String, Vec<u8>>,
+) -> CpmResult<()> {
+    if let Some(expected) = &manifest.sha256 {
+        if !package_digest(files).eq_ignore_ascii_case(expected.trim()) {
+            return Err(CpmError::Integrity(format!(
+                "package {} does not match its digest",
+                manifest.name
+            )));
+        }
+    }
+    for entry in &manifest.files {
+        let Some(expected) = &entry.sha256 else {
+            continue;
+        };
+        let data = files
+            .get(&to_8_3(&entry.src))
+            .ok_or_else(|| CpmError::Integrity(format!("{} is missing", entry.src)))?;
+        check_digest(&entry.src, data, expected)?;
+    }
+    Ok(())
+}
+
 /// Normalize manifest data to array format.
 fn normalize_manifest_data(data: serde_json::Value) -> Vec<PackageManifest> {
     if let Ok(arr) = serde_json::from_value::<Vec<PackageManifest>>(data.clone()) {
@@ -261,11 +362,7 @@ pub fn expand_submit_template(
     base_name: &str,
     drive: Option<char>,
 ) -> String {
-    let template = action
-        .submit
-        .as_ref()
-        .map(|s| s.as_str())
-        .unwrap_or_else(|| "{command} {name}\r");
+    let template = action.submit.as_deref().unwrap_or("{command} {name}\r");
 
     let mut result = template
         .replace("{command}", &action.command)
@@ -279,13 +376,16 @@ pub fn expand_submit_template(
 }
 
 /// Read-only filesystem backed by loaded packages.
-/// Multiple packages are merged (later packages override earlier ones).
+/// Multiple packages are merged: with `add_package` later packages
+/// override earlier ones and the clashes are kept in `conflicts`;
+/// `try_add_package` refuses them instead.
 #[derive(Debug, Clone)]
 pub struct PackageDriveFS {
     files: HashMap<String, Vec<u8>>,
     file_origins: HashMap<String, String>,
     packages: Vec<LoadedPackage>,
     all_actions: Vec<PackageAction>,
+    conflicts: Vec<FileConflict>,
 }
 
 impl PackageDriveFS {
@@ -296,6 +396,7 @@ impl PackageDriveFS {
             file_origins: HashMap::new(),
             packages: Vec::new(),
             all_actions: Vec::new(),
+            conflicts: Vec::new(),
         }
     }
 
@@ -310,6 +411,8 @@ impl PackageDriveFS {
 
     /// Add a package (files are merged, later overrides earlier).
     pub fn add_package(&mut self, pkg: LoadedPackage) {
+        let mut conflicts = self.conflicts_with(&pkg);
+        self.conflicts.append(&mut conflicts);
         let pkg_name = pkg.manifest.name.clone();
         for (name, data) in &pkg.files {
             let fname = to_8_3(name);
@@ -323,6 +426,44 @@ impl PackageDriveFS {
         self.packages.push(pkg);
     }
 
+    /// Add a package unless it would replace a file from another package
+    /// with different contents.
+    pub fn try_add_package(&mut self, pkg: LoadedPackage) -> CpmResult<()> {
+        if let Some(conflict) = self.conflicts_with(&pkg).into_iter().next() {
+            return Err(CpmError::FileConflict {
+                file: conflict.file,
+                first: conflict.first,
+                second: conflict.second,
+            });
+        }
+        self.add_package(pkg);
+        Ok(())
+    }
+
+    /// Files overridden by a later package, in the order they were added.
+    pub fn conflicts(&self) -> &[FileConflict] {
+        &self.conflicts
+    }
+
+    /// Files of `pkg` that would replace different ones, by name.
+    fn conflicts_with(&self, pkg: &LoadedPackage) -> Vec<FileConflict> {
+        let mut conflicts: Vec<FileConflict> = pkg
+            .files
+            .iter()
+            .filter_map(|(name, data)| {
+                let fname = to_8_3(name);
+                let existing = self.files.get(&fname)?;
+                (existing != data).then(|| FileConflict {
+                    first: self.file_origins[&fname].clone(),
+                    second: pkg.manifest.name.clone(),
+                    file: fname,
+                })
+            })
+            .collect();
+        conflicts.sort_by(|a, b| a.file.cmp(&b.file));
+        conflicts
+    }
+
     /// Remove a package by name.
     pub fn remove_package(&mut self, name: &str) -> bool {
         let idx = self.packages.iter().position(|p| p.manifest.name == name);
@@ -332,6 +473,7 @@ impl PackageDriveFS {
             self.files.clear();
             self.file_origins.clear();
             self.all_actions.clear();
+            self.conflicts.clear();
             let packages = std::mem::take(&mut self.packages);
             for pkg in packages {
                 self.add_package(pkg);
@@ -462,6 +604,49 @@ mod tests {
         buf
     }
 
+    fn zip_of(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        use std::io::Write;
+        let mut buf = Vec::new();
+        let mut zip = zip::ZipWriter::new(Cursor::new(&mut buf));
+        for (name, data) in entries {
+            zip.start_file::<_, ()>(*name, Default::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_manifest_digests() {
+        let hello: &[u8] = b"\xC3\x00\x00";
+        let mut files = HashMap::new();
+        files.insert("HELLO.COM".to_string(), hello.to_vec());
+        let manifest = format!(
+            r#"{{ "name": "Signed", "sha256": "{}",
+                 "files": [ {{ "src": "HELLO.COM", "sha256": "{}" }} ] }}"#,
+            package_digest(&files),
+            crate::receipt::sha256_hex(hello)
+        );
+
+        let zip = zip_of(&[("manifest.mf", manifest.as_bytes()), ("HELLO.COM", hello)]);
+        assert!(load_package(Cursor::new(zip)).is_ok());
+
+        let altered = zip_of(&[("manifest.mf", manifest.as_bytes()), ("HELLO.COM", b"\xC9")]);
+        assert!(matches!(
+            load_package(Cursor::new(altered)),
+            Err(CpmError::Integrity(_))
+        ));
+        let added = zip_of(&[
+            ("manifest.mf", manifest.as_bytes()),
+            ("HELLO.COM", hello),
+            ("EXTRA.COM", b"\xC9"),
+        ]);
+        assert!(matches!(
+            load_package(Cursor::new(added)),
+            Err(CpmError::Integrity(msg)) if msg.contains("package Signed")
+        ));
+    }
+
     #[test]
     fn test_load_package() {
         let zip_data = create_test_zip();
@@ -495,6 +680,36 @@ mod tests {
         assert!(files.contains(&"MANIFEST.MF".to_string()));
     }
 
+    #[test]
+    fn test_file_conflicts() {
+        let pkg = |name: &str, data: &[u8]| {
+            let mut pkg = load_package(Cursor::new(create_test_zip())).unwrap();
+            pkg.manifest.name = name.to_string();
+            pkg.files.insert("LOAD.COM".to_string(), data.to_vec());
+            pkg
+        };
+        let mut fs = PackageDriveFS::new();
+        fs.add_package(pkg("First", b"\xC9"));
+        // Same contents are not a conflict
+        fs.try_add_package(pkg("Same", b"\xC9")).unwrap();
+        assert!(matches!(
+            fs.try_add_package(pkg("Other", b"\x00")),
+            Err(CpmError::FileConflict { file, first, second })
+                if file == "LOAD.COM" && first == "Same" && second == "Other"
+        ));
+        assert_eq!(fs.read_file("LOAD.COM"), Some(vec![0xC9]));
+
+        fs.add_package(pkg("Other", b"\x00"));
+        assert_eq!(fs.read_file("LOAD.COM"), Some(vec![0x00]));
+        assert_eq!(
+            fs.conflicts()
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>(),
+            ["LOAD.COM from Other overrides Same"]
+        );
+    }
+
     #[test]
     fn test_action_matches_file() {
         let action = PackageAction {
@@ -513,6 +728,25 @@ mod tests {
         assert!(!action_matches_file(&action, "test.txt"));
     }
 
+    #[test]
+    fn test_submit_lines() {
+        let action = PackageAction {
+            id: "asm".to_string(),
+            name: "ASM".to_string(),
+            command: "ASM".to_string(),
+            patterns: vec!["*.ASM".to_string()],
+            output_exts: vec!["COM".to_string()],
+            submit: Some("{drive}:\rA:ASM {drive}:{name}\rA:LOAD {name}\r".to_string()),
+            interactive_script: None,
+            package: None,
+        };
+
+        assert_eq!(
+            action.submit_lines("HELLO", 'B'),
+            vec!["B:", "A:ASM B:HELLO", "A:LOAD HELLO"]
+        );
+    }
+
     #[test]
     fn test_expand_submit_template() {
         let action = PackageAction {
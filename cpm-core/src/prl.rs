@@ -0,0 +1,270 @@
+//! Page-relocatable executables (.PRL and .SPR).
+//!
+//! A PRL file is a 256-byte header, the code linked at 0100H and a
+//! relocation bitmap with one bit per code byte, most significant bit
+//! first. A set bit marks the high byte of an address; loading at another
+//! page adds the page difference to it. SPR files (MP/M system PRLs) are
+//! the same but linked at 0000H.
+//!
+//! Header layout: bytes 1-2 code size, bytes 4-5 extra memory needed
+//! after the code (data/BSS), both little-endian.
+
+use crate::bdos::addr;
+use crate::error::{CpmError, CpmResult};
+use crate::memory::MemoryMap;
+
+/// Size of the PRL header.
+pub const HEADER_SIZE: usize = 0x100;
+
+/// A parsed PRL or SPR file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrlImage {
+    /// Code as linked.
+    pub code: Vec<u8>,
+    /// One bit per code byte.
+    pub bitmap: Vec<u8>,
+    /// Memory needed after the code.
+    pub bss_size: u16,
+    /// Address the code was linked at (0100H for PRL, 0000H for SPR).
+    pub origin: u16,
+}
+
+impl PrlImage {
+    /// Parse a .PRL file.
+    pub fn parse(data: &[u8]) -> CpmResult<Self> {
+        Self::parse_at(data, addr::TPA)
+    }
+
+    /// Parse a .SPR file.
+    pub fn parse_spr(data: &[u8]) -> CpmResult<Self> {
+        Self::parse_at(data, 0)
+    }
+
+    /// Parse by file name: .SPR files are linked at 0, others at 0100H.
+    pub fn parse_file(filename: &str, data: &[u8]) -> CpmResult<Self> {
+        if filename.to_uppercase().ends_with(".SPR") {
+            Self::parse_spr(data)
+        } else {
+            Self::parse(data)
+        }
+    }
+
+    fn parse_at(data: &[u8], origin: u16) -> CpmResult<Self> {
+        if data.len() < HEADER_SIZE {
+            return Err(CpmError::Prl("file shorter than header".to_string()));
+        }
+        let code_size = u16::from_le_bytes([data[1], data[2]]) as usize;
+        let bss_size = u16::from_le_bytes([data[4], data[5]]);
+        let bitmap_size = code_size.div_ceil(8);
+        let code_end = HEADER_SIZE + code_size;
+        let Some(bitmap) = data.get(code_end..code_end + bitmap_size) else {
+            return Err(CpmError::Prl(format!(
+                "code size {:04X}H needs {} bytes, file has {}",
+                code_size,
+                code_end + bitmap_size,
+                data.len()
+            )));
+        };
+        Ok(Self {
+            code: data[HEADER_SIZE..code_end].to_vec(),
+            bitmap: bitmap.to_vec(),
+            bss_size,
+            origin,
+        })
+    }
+
+    /// Code plus extra memory.
+    pub fn memory_size(&self) -> u32 {
+        self.code.len() as u32 + self.bss_size as u32
+    }
+
+    /// True if the code byte at `offset` is relocated.
+    pub fn is_relocated(&self, offset: usize) -> bool {
+        self.bitmap
+            .get(offset / 8)
+            .is_some_and(|b| b & (0x80 >> (offset % 8)) != 0)
+    }
+
+    /// Highest page-aligned address the image fits below `limit` at, or
+    /// `None` if it does not fit above the origin page.
+    pub fn top_address(&self, limit: u16) -> Option<u16> {
+        let base = (limit as u32).checked_sub(self.memory_size())? & !0xFF;
+        (base >= (self.origin & 0xFF00) as u32).then_some(base as u16)
+    }
+
+    /// Code relocated to run at `address`, which must be page-aligned.
+    pub fn relocate(&self, address: u16) -> CpmResult<Vec<u8>> {
+        if address & 0xFF != 0 {
+            return Err(CpmError::Prl(format!("{:04X}H is not on a page", address)));
+        }
+        if address as u32 + self.memory_size() > 0x10000 {
+            return Err(CpmError::Prl(format!("does not fit at {:04X}H", address)));
+        }
+        let delta = (address.wrapping_sub(self.origin & 0xFF00) >> 8) as u8;
+        Ok(self
+            .code
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| {
+                if self.is_relocated(i) {
+                    b.wrapping_add(delta)
+                } else {
+                    b
+                }
+            })
+            .collect())
+    }
+}
+
+/// True for names the loader relocates.
+pub fn is_relocatable(filename: &str) -> bool {
+    let upper = filename.to_uppercase();
+    upper.ends_with(".PRL") || upper.ends_with(".SPR")
+}
+
+/// Image to put in memory for a file named in a manifest, and where.
+/// PRL/SPR files are relocated to `load_address`, or to the top of the
+/// TPA of `map` when none is given; other files load as they are.
+pub fn load_image(
+    filename: &str,
+    data: &[u8],
+    load_address: Option<u16>,
+    map: &MemoryMap,
+) -> CpmResult<(u16, Vec<u8>)> {
+    if !is_relocatable(filename) {
+        return Ok((load_address.unwrap_or(addr::TPA), data.to_vec()));
+    }
+    let image = PrlImage::parse_file(filename, data)?;
+    let address = match load_address {
+        Some(address) => address,
+        None => image
+            .top_address(map.tpa_end())
+            .ok_or_else(|| CpmError::Prl(format!("{} does not fit in the TPA", filename)))?,
+    };
+    Ok((address, image.relocate(address)?))
+}
+
+/// Image and address for a shell named in a manifest. The manifest's
+/// address assumes the default memory map; a PRL shell that would not fit
+/// below the BDOS of `map` there is relocated to the CCP address instead,
+/// and any other shell that would overlap the BDOS or BIOS is refused.
+pub fn load_shell(
+    filename: &str,
+    data: &[u8],
+    load_address: Option<u16>,
+    map: &MemoryMap,
+) -> CpmResult<(u16, Vec<u8>)> {
+    let fits = |address: u16, size: u32| address as u32 + size <= map.tpa_end() as u32;
+    if is_relocatable(filename) {
+        let size = PrlImage::parse_file(filename, data)?.memory_size();
+        if let Some(address) = load_address.filter(|&a| !fits(a, size)) {
+            if !fits(map.ccp, size) {
+                return Err(CpmError::MemoryMap(format!(
+                    "{} does not fit at {:04X}H or {:04X}H",
+                    filename, address, map.ccp
+                )));
+            }
+            return load_image(filename, data, Some(map.ccp), map);
+        }
+        return load_image(filename, data, load_address, map);
+    }
+    let (address, image) = load_image(filename, data, load_address, map)?;
+    if !fits(address, image.len() as u32) {
+        return Err(CpmError::MemoryMap(format!(
+            "{} at {:04X}H overlaps the BDOS at {:04X}H",
+            filename,
+            address,
+            map.tpa_end()
+        )));
+    }
+    Ok((address, image))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// LXI H,0106H; JMP 0100H; DB 01H - the last byte looks like a page
+    /// but is not flagged.
+    fn prl() -> Vec<u8> {
+        let mut data = vec![0; HEADER_SIZE];
+        data[1] = 7;
+        data[4] = 0x40;
+        data.extend_from_slice(&[0x21, 0x06, 0x01, 0xC3, 0x00, 0x01, 0x01]);
+        data.push(0b0010_0100);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let image = PrlImage::parse(&prl()).unwrap();
+        assert_eq!(image.code.len(), 7);
+        assert_eq!(image.bss_size, 0x40);
+        assert_eq!(image.memory_size(), 0x47);
+        assert!(image.is_relocated(2) && image.is_relocated(5));
+        assert!(!image.is_relocated(6));
+
+        assert!(PrlImage::parse(&prl()[..HEADER_SIZE + 7]).is_err());
+        assert!(PrlImage::parse(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_relocate() {
+        let image = PrlImage::parse(&prl()).unwrap();
+        assert_eq!(image.relocate(0x100).unwrap(), image.code);
+        assert_eq!(
+            image.relocate(0xDC00).unwrap(),
+            [0x21, 0x06, 0xDC, 0xC3, 0x00, 0xDC, 0x01]
+        );
+        assert!(image.relocate(0xDC80).is_err());
+        assert_eq!(image.top_address(0xDC00), Some(0xDB00));
+        assert_eq!(image.top_address(0x140), None);
+
+        let spr = PrlImage::parse_file("RESBDOS.SPR", &prl()).unwrap();
+        assert_eq!(spr.relocate(0x300).unwrap()[2], 0x04);
+    }
+
+    #[test]
+    fn test_load_image() {
+        let map = MemoryMap::default();
+        let (address, code) = load_image("SHELL.PRL", &prl(), None, &map).unwrap();
+        assert_eq!(address, 0xFD00);
+        assert_eq!(code[2], 0xFD);
+        let map_48k = MemoryMap::for_size(48).unwrap();
+        assert_eq!(
+            load_image("SHELL.PRL", &prl(), None, &map_48k).unwrap().0,
+            0xAB00
+        );
+        assert_eq!(
+            load_image("SHELL.PRL", &prl(), Some(0xDC00), &map)
+                .unwrap()
+                .0,
+            0xDC00
+        );
+        assert_eq!(
+            load_image("CCP.COM", b"\xC9", Some(0xDC00), &map).unwrap(),
+            (0xDC00, vec![0xC9])
+        );
+    }
+
+    #[test]
+    fn test_load_shell() {
+        let map = MemoryMap::default();
+        let map_48k = MemoryMap::for_size(48).unwrap();
+        assert_eq!(
+            load_shell("CCP.PRL", &prl(), Some(0xDC00), &map).unwrap().0,
+            0xDC00
+        );
+        // Moved to the CCP of a smaller system
+        let (address, code) = load_shell("CCP.PRL", &prl(), Some(0xDC00), &map_48k).unwrap();
+        assert_eq!(address, map_48k.ccp);
+        assert_eq!(code[2], (map_48k.ccp >> 8) as u8);
+
+        assert!(load_shell("CCP.COM", &[0; 0x800], Some(0xDC00), &map).is_ok());
+        assert!(matches!(
+            load_shell("CCP.COM", &[0; 0x800], Some(0xDC00), &map_48k),
+            Err(CpmError::MemoryMap(_))
+        ));
+        assert!(load_shell("CCP.COM", &[0; 0x800], None, &map_48k).is_ok());
+    }
+}
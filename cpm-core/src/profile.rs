@@ -0,0 +1,365 @@
+//! Execution profiler.
+//!
+//! Counts instructions and T-states per PC, times each BDOS function on the
+//! host clock, tallies records read and written per file, and builds a call
+//! tree from CALL/RST/RET so the run can be rendered as a text report or as
+//! folded stacks for flamegraph tools.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::bdos::BdosFunction;
+use crate::symbols::SymbolTable;
+use crate::trace::TraceEvent;
+
+/// Calls deeper than this are not tracked (runaway recursion or stack tricks).
+const MAX_DEPTH: usize = 256;
+
+/// Statistics for one BDOS function.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BdosStats {
+    pub calls: u64,
+    /// Host time spent handling the calls.
+    pub time: Duration,
+}
+
+/// Record counts for one file (`D:NAME.EXT`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileStats {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// Node in the call tree.
+struct Frame {
+    parent: usize,
+    address: u16,
+    depth: usize,
+    /// T-states spent in this frame, excluding callees.
+    t_states: u64,
+}
+
+/// Execution profile collected by the emulator.
+pub struct Profiler {
+    instructions: Vec<u64>,
+    t_states: Vec<u64>,
+    bdos: BTreeMap<u8, BdosStats>,
+    files: BTreeMap<String, FileStats>,
+    /// Call tree; frame 0 is an unlabelled root above each entry point.
+    frames: Vec<Frame>,
+    children: HashMap<(usize, u16), usize>,
+    current: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            instructions: vec![0; 65536],
+            t_states: vec![0; 65536],
+            bdos: BTreeMap::new(),
+            files: BTreeMap::new(),
+            frames: vec![Frame {
+                parent: 0,
+                address: 0,
+                depth: 0,
+                t_states: 0,
+            }],
+            children: HashMap::new(),
+            current: 0,
+        }
+    }
+
+    /// Start a new call stack rooted at an entry point (program start or
+    /// shell reload).
+    pub fn enter_root(&mut self, address: u16) {
+        self.current = self.child(0, address);
+    }
+
+    /// Record one executed instruction. `opcode` holds the first two bytes
+    /// at `pc` before execution; `next_pc` is the PC afterwards.
+    pub fn record_instruction(&mut self, pc: u16, opcode: [u8; 2], t_states: u64, next_pc: u16) {
+        self.instructions[pc as usize] += 1;
+        self.t_states[pc as usize] += t_states;
+        self.frames[self.current].t_states += t_states;
+
+        match opcode[0] {
+            // CALL nn
+            0xCD => self.record_call(next_pc),
+            // CALL cc,nn (taken if we did not fall through)
+            op if op & 0xC7 == 0xC4 && next_pc != pc.wrapping_add(3) => self.record_call(next_pc),
+            // RST n
+            op if op & 0xC7 == 0xC7 => self.record_call(next_pc),
+            // RET
+            0xC9 => self.record_return(),
+            // RET cc
+            op if op & 0xC7 == 0xC0 && next_pc != pc.wrapping_add(1) => self.record_return(),
+            // RETN / RETI
+            0xED if opcode[1] & 0xC7 == 0x45 => self.record_return(),
+            _ => {}
+        }
+    }
+
+    /// Enter a subroutine.
+    pub fn record_call(&mut self, target: u16) {
+        if self.frames[self.current].depth < MAX_DEPTH {
+            self.current = self.child(self.current, target);
+        }
+    }
+
+    /// Leave the current subroutine (also used for intercepted BDOS/CBIOS
+    /// returns).
+    pub fn record_return(&mut self) {
+        if self.frames[self.current].depth > 1 {
+            self.current = self.frames[self.current].parent;
+        }
+    }
+
+    /// Record a completed BDOS call.
+    pub fn record_bdos(&mut self, function: u8, time: Duration) {
+        let stats = self.bdos.entry(function).or_default();
+        stats.calls += 1;
+        stats.time += time;
+    }
+
+    /// Update file statistics from a trace event.
+    pub fn record_event(&mut self, event: &TraceEvent) {
+        match event {
+            TraceEvent::FileRead {
+                drive, filename, ..
+            } => {
+                self.file_entry(*drive, filename).reads += 1;
+            }
+            TraceEvent::FileWrite {
+                drive, filename, ..
+            } => {
+                self.file_entry(*drive, filename).writes += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Number of times the instruction at `pc` executed.
+    pub fn instruction_count(&self, pc: u16) -> u64 {
+        self.instructions[pc as usize]
+    }
+
+    /// Instruction counts for each 256-byte page.
+    pub fn page_counts(&self) -> [u64; 256] {
+        let mut pages = [0u64; 256];
+        for (pc, &count) in self.instructions.iter().enumerate() {
+            pages[pc >> 8] += count;
+        }
+        pages
+    }
+
+    /// Total instructions executed.
+    pub fn total_instructions(&self) -> u64 {
+        self.instructions.iter().sum()
+    }
+
+    /// Total T-states spent executing instructions.
+    pub fn total_t_states(&self) -> u64 {
+        self.t_states.iter().sum()
+    }
+
+    /// Per-function BDOS statistics, keyed by function number.
+    pub fn bdos_stats(&self) -> &BTreeMap<u8, BdosStats> {
+        &self.bdos
+    }
+
+    /// Per-file record statistics, keyed by `D:NAME.EXT`.
+    pub fn file_stats(&self) -> &BTreeMap<String, FileStats> {
+        &self.files
+    }
+
+    /// Human-readable report listing the `top` hottest addresses, labelled
+    /// with `symbols` where known.
+    pub fn report(&self, top: usize, symbols: &SymbolTable) -> String {
+        let total = self.total_instructions();
+        let pct = |n: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                n as f64 * 100.0 / total as f64
+            }
+        };
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "Instructions: {}  T-states: {}",
+            total,
+            self.total_t_states()
+        );
+
+        let mut hot: Vec<(usize, u64)> = self
+            .instructions
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\nHot spots:");
+        let _ = writeln!(out, "  PC         Count  T-states       %  Symbol");
+        for &(pc, count) in hot.iter().take(top) {
+            let symbol = match symbols.lookup(pc as u16) {
+                Some(_) => symbols.format_addr(pc as u16),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                out,
+                "  {:04X} {:>11} {:>9} {:>6.2}%  {}",
+                pc,
+                count,
+                self.t_states[pc],
+                pct(count),
+                symbol
+            );
+        }
+
+        let mut pages: Vec<(usize, u64)> = self
+            .page_counts()
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        pages.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let _ = writeln!(out, "\nPages:");
+        for (page, count) in pages {
+            let _ = writeln!(out, "  {:02X}xx {:>11} {:>6.2}%", page, count, pct(count));
+        }
+
+        if !self.bdos.is_empty() {
+            let _ = writeln!(out, "\nBDOS functions:");
+            let _ = writeln!(out, "  Fn  Name                    Calls   Time (ms)");
+            for (&func, stats) in &self.bdos {
+                let name = BdosFunction::try_from(func)
+                    .map(|f| format!("{:?}", f))
+                    .unwrap_or_else(|_| "Unknown".to_string());
+                let _ = writeln!(
+                    out,
+                    "  {:>2}  {:<20} {:>8} {:>11.3}",
+                    func,
+                    name,
+                    stats.calls,
+                    stats.time.as_secs_f64() * 1000.0
+                );
+            }
+        }
+
+        if !self.files.is_empty() {
+            let _ = writeln!(out, "\nFiles:");
+            let _ = writeln!(out, "  File              Reads   Writes");
+            for (name, stats) in &self.files {
+                let _ = writeln!(out, "  {:<14} {:>8} {:>8}", name, stats.reads, stats.writes);
+            }
+        }
+
+        out
+    }
+
+    /// Folded stacks (`root;caller;callee T-states` per line), as consumed
+    /// by flamegraph tools. Frames are named from `symbols` where known.
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines = Vec::new();
+        for (idx, frame) in self.frames.iter().enumerate().skip(1) {
+            if frame.t_states == 0 {
+                continue;
+            }
+            let mut path = Vec::with_capacity(frame.depth);
+            let mut node = idx;
+            while node != 0 {
+                path.push(symbols.format_addr(self.frames[node].address));
+                node = self.frames[node].parent;
+            }
+            path.reverse();
+            lines.push(format!("{} {}", path.join(";"), frame.t_states));
+        }
+        lines.sort();
+
+        let mut out = lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Find or create the child frame of `parent` for `address`.
+    fn child(&mut self, parent: usize, address: u16) -> usize {
+        if let Some(&idx) = self.children.get(&(parent, address)) {
+            return idx;
+        }
+        let idx = self.frames.len();
+        self.frames.push(Frame {
+            parent,
+            address,
+            depth: self.frames[parent].depth + 1,
+            t_states: 0,
+        });
+        self.children.insert((parent, address), idx);
+        idx
+    }
+
+    fn file_entry(&mut self, drive: char, filename: &str) -> &mut FileStats {
+        self.files
+            .entry(format!("{}:{}", drive, filename))
+            .or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_call_tree() {
+        let mut p = Profiler::new();
+        p.enter_root(0x0100);
+        p.record_instruction(0x0100, [0xCD, 0x00], 17, 0x0200); // CALL 0200
+        p.record_instruction(0x0200, [0x00, 0x00], 4, 0x0201); // NOP
+        p.record_instruction(0x0201, [0xC9, 0x00], 10, 0x0103); // RET
+        p.record_instruction(0x0103, [0xC4, 0x00], 10, 0x0106); // CALL NZ not taken
+        p.record_instruction(0x0106, [0xC9, 0x00], 10, 0x0000); // RET at root
+
+        assert_eq!(p.folded(&SymbolTable::new()), "0100 37\n0100;0200 14\n");
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert("MAIN", 0x0100);
+        symbols.insert("PRINT", 0x0200);
+        assert_eq!(p.folded(&symbols), "MAIN 37\nMAIN;PRINT 14\n");
+        assert!(p.report(5, &symbols).contains("PRINT+1"));
+        assert_eq!(p.total_instructions(), 5);
+        assert_eq!(p.total_t_states(), 51);
+        assert_eq!(p.page_counts()[1], 3);
+        assert_eq!(p.page_counts()[2], 2);
+    }
+
+    #[test]
+    fn test_bdos_and_file_stats() {
+        let mut p = Profiler::new();
+        p.record_bdos(20, Duration::from_millis(2));
+        p.record_bdos(20, Duration::from_millis(3));
+        p.record_event(&TraceEvent::FileRead {
+            drive: 'A',
+            filename: "FOO.TXT".to_string(),
+            record: 0,
+        });
+
+        assert_eq!(p.bdos_stats()[&20].calls, 2);
+        assert_eq!(p.bdos_stats()[&20].time, Duration::from_millis(5));
+        assert_eq!(p.file_stats()["A:FOO.TXT"].reads, 1);
+
+        let report = p.report(10, &SymbolTable::new());
+        assert!(report.contains("ReadSequential"));
+        assert!(report.contains("A:FOO.TXT"));
+    }
+}
@@ -0,0 +1,279 @@
+//! Reproducible build receipts.
+//!
+//! A `Receipt` records what went into a run and what came out of it as
+//! SHA-256 digests: the package ZIPs, every file opened through the BDOS,
+//! the command and console input, the emulator version and configuration,
+//! and every file written that still exists at the end. Running the same
+//! inputs again in deterministic mode must give the same outputs, which
+//! `Receipt::mismatches` checks.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::console::CpmConsole;
+use crate::emulator::CpmEmulator;
+use crate::error::CpmResult;
+use crate::fs::DriveFS;
+use crate::memory::MemoryMap;
+
+/// Receipt layout version.
+pub const FORMAT: u32 = 1;
+
+/// Lower-case hex SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// A named file and its digest. CP/M files are named `D:NAME.EXT`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct FileDigest {
+    pub name: String,
+    pub sha256: String,
+}
+
+impl FileDigest {
+    pub fn of(name: impl Into<String>, data: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            sha256: sha256_hex(data),
+        }
+    }
+}
+
+/// `D:NAME.EXT` for a file on `drive` (0 = A:).
+fn drive_path(drive: u8, name: &str) -> String {
+    format!("{}:{}", (b'A' + drive) as char, name)
+}
+
+/// Files opened and written through the BDOS, kept by the emulator while
+/// recording is on.
+#[derive(Debug, Clone, Default)]
+pub struct FileLog {
+    /// Contents at each open; a file reopened unchanged is listed once.
+    reads: BTreeSet<FileDigest>,
+    /// Drive and name of each file closed with changes, created or
+    /// renamed to.
+    written: BTreeSet<(u8, String)>,
+}
+
+impl FileLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_read(&mut self, drive: u8, name: &str, data: &[u8]) {
+        self.reads
+            .insert(FileDigest::of(drive_path(drive, name), data));
+    }
+
+    pub(crate) fn record_write(&mut self, drive: u8, name: &str) {
+        self.written.insert((drive, name.to_string()));
+    }
+
+    /// Digests of the files opened, by name.
+    pub fn reads(&self) -> impl Iterator<Item = &FileDigest> {
+        self.reads.iter()
+    }
+
+    /// Drive and name of the files written, in order.
+    pub fn written(&self) -> impl Iterator<Item = (u8, &str)> {
+        self.written.iter().map(|(d, name)| (*d, name.as_str()))
+    }
+}
+
+/// Emulator settings that affect the outcome of a run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunConfig {
+    pub memory_map: MemoryMap,
+    pub deterministic: bool,
+    /// Timer period in T-states, if the timer was running.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timer: Option<u64>,
+    /// Run budget in T-states; 0 for none.
+    pub max_t_states: u64,
+    /// Action id, for runs of a package action.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<String>,
+    /// Host file the action was run on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Other host files copied in beside the source.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_files: Vec<String>,
+}
+
+impl RunConfig {
+    /// Settings of `emu` with a run budget of `max_t_states`.
+    pub fn of<C: CpmConsole, D: DriveFS>(emu: &CpmEmulator<C, D>, max_t_states: u64) -> Self {
+        Self {
+            memory_map: emu.memory_map(),
+            deterministic: emu.is_deterministic(),
+            timer: emu.interrupts().timer().map(|t| t.period),
+            max_t_states,
+            action: None,
+            source: None,
+            extra_files: Vec::new(),
+        }
+    }
+}
+
+/// Inputs and outputs of one run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub format: u32,
+    /// Emulator name and version.
+    pub emulator: String,
+    pub config: RunConfig,
+    /// Command line, or the commands typed at the CCP, one per line.
+    pub command: String,
+    /// Digest of the console input queued or scripted for the run.
+    pub input_sha256: String,
+    /// Package ZIPs, by the name they were loaded under.
+    pub packages: Vec<FileDigest>,
+    /// Files opened through the BDOS.
+    pub inputs: Vec<FileDigest>,
+    /// Files written, with their contents at the end of the run.
+    pub outputs: Vec<FileDigest>,
+}
+
+impl Receipt {
+    pub fn new(config: RunConfig, command: &str, input: &[u8]) -> Self {
+        Self {
+            format: FORMAT,
+            emulator: format!("cpm-core {}", env!("CARGO_PKG_VERSION")),
+            config,
+            command: command.to_string(),
+            input_sha256: sha256_hex(input),
+            packages: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+
+    /// Record a package ZIP.
+    pub fn add_package(&mut self, name: &str, zip: &[u8]) {
+        self.packages.push(FileDigest::of(name, zip));
+    }
+
+    /// Take the inputs and outputs from the file log of `emu`. Outputs are
+    /// hashed as they are on the drives now; files written and later
+    /// deleted are left out.
+    pub fn record_files<C: CpmConsole, D: DriveFS>(&mut self, emu: &CpmEmulator<C, D>) {
+        let Some(log) = emu.file_log() else {
+            return;
+        };
+        self.inputs = log.reads().cloned().collect();
+        self.outputs = log
+            .written()
+            .filter_map(|(drive, name)| {
+                let data = emu.drive(drive)?.read_file(name)?;
+                Some(FileDigest::of(drive_path(drive, name), &data))
+            })
+            .collect();
+    }
+
+    pub fn to_json(&self) -> CpmResult<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> CpmResult<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// How a re-run differs from this receipt: one line per package,
+    /// input or output whose digest changed, appeared or went missing.
+    /// Empty when the runs match.
+    pub fn mismatches(&self, rerun: &Receipt) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.emulator != rerun.emulator {
+            problems.push(format!(
+                "emulator: {} now {}",
+                self.emulator, rerun.emulator
+            ));
+        }
+        if self.config != rerun.config {
+            problems.push("configuration differs".to_string());
+        }
+        if self.command != rerun.command {
+            problems.push("command differs".to_string());
+        }
+        if self.input_sha256 != rerun.input_sha256 {
+            problems.push("console input differs".to_string());
+        }
+        compare("package", &self.packages, &rerun.packages, &mut problems);
+        compare("input", &self.inputs, &rerun.inputs, &mut problems);
+        compare("output", &self.outputs, &rerun.outputs, &mut problems);
+        problems
+    }
+}
+
+/// Describe the differences between two digest lists.
+fn compare(kind: &str, old: &[FileDigest], new: &[FileDigest], problems: &mut Vec<String>) {
+    for file in old {
+        match new.iter().find(|f| f.name == file.name) {
+            None => problems.push(format!("{} {} missing", kind, file.name)),
+            Some(f) if f.sha256 != file.sha256 => {
+                problems.push(format!("{} {} changed", kind, file.name))
+            }
+            Some(_) => {}
+        }
+    }
+    for file in new {
+        if !old.iter().any(|f| f.name == file.name) {
+            problems.push(format!("{} {} is new", kind, file.name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_json_round_trip_and_mismatches() {
+        let config = RunConfig {
+            memory_map: MemoryMap::default(),
+            deterministic: true,
+            timer: None,
+            max_t_states: 1000,
+            action: Some("asm".to_string()),
+            source: Some("hello.asm".to_string()),
+            extra_files: Vec::new(),
+        };
+        let mut receipt = Receipt::new(config, "ASM HELLO", b"");
+        receipt.add_package("asm.zip", b"zip");
+        receipt.outputs.push(FileDigest::of("B:HELLO.HEX", b"hex"));
+        receipt.outputs.push(FileDigest::of("B:HELLO.PRN", b"prn"));
+
+        let json = receipt.to_json().unwrap();
+        let parsed = Receipt::from_json(&json).unwrap();
+        assert_eq!(parsed, receipt);
+        assert!(receipt.mismatches(&parsed).is_empty());
+
+        let mut rerun = parsed;
+        rerun.outputs[0] = FileDigest::of("B:HELLO.HEX", b"other");
+        rerun.outputs.pop();
+        rerun.outputs.push(FileDigest::of("B:HELLO.SYM", b"sym"));
+        assert_eq!(
+            receipt.mismatches(&rerun),
+            [
+                "output B:HELLO.HEX changed",
+                "output B:HELLO.PRN missing",
+                "output B:HELLO.SYM is new",
+            ]
+        );
+    }
+}
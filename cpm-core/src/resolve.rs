@@ -0,0 +1,422 @@
+//! Package dependencies and version constraints.
+//!
+//! A manifest lists the packages it needs under `dependencies`, each by
+//! id with an optional version constraint and the files it uses:
+//!
+//! ```json
+//! "dependencies": [
+//!     { "id": "cpm22", "version": ">=2.2", "files": ["LOAD.COM"] }
+//! ]
+//! ```
+//!
+//! `PackageResolver` picks from the available packages, such as a
+//! directory of ZIPs, the newest version of each dependency that meets
+//! the constraint of the first package to ask for it, and orders the
+//! result so every package comes after those it depends on. There is no
+//! backtracking: a later constraint the chosen version does not meet is
+//! reported as a conflict.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::error::{CpmError, CpmResult};
+use crate::fs::to_8_3;
+use crate::integrity::{load_signed_package, TrustedKeys};
+use crate::package::{load_package_from_path, Dependency, LoadedPackage};
+
+/// Dotted numeric version such as `2.2` or `1.0.3`; a leading `v` is
+/// allowed. Missing components count as zero, so `2.2` equals `2.2.0`.
+#[derive(Debug, Clone)]
+pub struct Version(Vec<u64>);
+
+impl Version {
+    pub fn parse(s: &str) -> CpmResult<Self> {
+        let s = s.trim();
+        let digits = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        digits
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u64>>>()
+            .map(Self)
+            .ok_or_else(|| CpmError::Dependency(format!("invalid version: {}", s)))
+    }
+
+    fn component(&self, i: usize) -> u64 {
+        self.0.get(i).copied().unwrap_or(0)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (0..self.0.len().max(other.0.len()))
+            .map(|i| self.component(i).cmp(&other.component(i)))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+/// Comma-separated comparisons (`=`, `>`, `>=`, `<`, `<=`) that must all
+/// hold, e.g. `>=2.2, <3`. A bare version means `=`; `*` or nothing
+/// matches any version.
+#[derive(Debug, Clone)]
+pub struct VersionReq(Vec<(Ordering, bool, Version)>);
+
+impl VersionReq {
+    pub fn parse(s: &str) -> CpmResult<Self> {
+        let mut comparisons = Vec::new();
+        for part in s.split(',').map(str::trim) {
+            if part.is_empty() || part == "*" {
+                continue;
+            }
+            // (ordering of the version against the bound, or-equal)
+            let (ordering, or_equal, rest) = if let Some(rest) = part.strip_prefix(">=") {
+                (Ordering::Greater, true, rest)
+            } else if let Some(rest) = part.strip_prefix("<=") {
+                (Ordering::Less, true, rest)
+            } else if let Some(rest) = part.strip_prefix('>') {
+                (Ordering::Greater, false, rest)
+            } else if let Some(rest) = part.strip_prefix('<') {
+                (Ordering::Less, false, rest)
+            } else {
+                let rest = part.strip_prefix("==").or(part.strip_prefix('='));
+                (Ordering::Equal, true, rest.unwrap_or(part))
+            };
+            comparisons.push((ordering, or_equal, Version::parse(rest)?));
+        }
+        Ok(Self(comparisons))
+    }
+
+    /// True if the requirement accepts every version.
+    pub fn is_any(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.0.iter().all(|(ordering, or_equal, bound)| {
+            let actual = version.cmp(bound);
+            actual == *ordering || (*or_equal && actual.is_eq())
+        })
+    }
+}
+
+/// Chooses and orders packages to satisfy dependencies.
+#[derive(Debug, Clone, Default)]
+pub struct PackageResolver {
+    available: Vec<LoadedPackage>,
+}
+
+impl PackageResolver {
+    /// Resolve against `available` packages.
+    pub fn new(available: Vec<LoadedPackage>) -> Self {
+        Self { available }
+    }
+
+    /// Resolve against the `.zip` packages in `dir`. With `keys`, each
+    /// must be signed by one of them.
+    pub fn from_dir(dir: &Path, keys: Option<&TrustedKeys>) -> CpmResult<Self> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+            })
+            .collect();
+        paths.sort();
+        let mut available = Vec::new();
+        for path in paths {
+            available.push(match keys {
+                Some(keys) => load_signed_package(&path, keys)?,
+                None => load_package_from_path(&path)?,
+            });
+        }
+        Ok(Self::new(available))
+    }
+
+    pub fn available(&self) -> &[LoadedPackage] {
+        &self.available
+    }
+
+    /// `roots` and everything they need, each package after its
+    /// dependencies and otherwise in the order given. A root satisfies
+    /// dependencies on its own id; every root is kept, even if several
+    /// share an id.
+    pub fn resolve(&self, roots: Vec<LoadedPackage>) -> CpmResult<Vec<LoadedPackage>> {
+        let mut resolution = Resolution::default();
+        for pkg in roots {
+            let id = pkg.manifest.package_id().to_string();
+            resolution.selected.insert(id, resolution.packages.len());
+            resolution.packages.push(Some(pkg));
+        }
+        for index in 0..resolution.packages.len() {
+            self.visit(index, &mut resolution)?;
+        }
+        let Resolution {
+            mut packages,
+            order,
+            ..
+        } = resolution;
+        Ok(order.iter().filter_map(|&i| packages[i].take()).collect())
+    }
+
+    /// Depth-first: resolve the dependencies of package `index`, then
+    /// append it to the order.
+    fn visit(&self, index: usize, resolution: &mut Resolution) -> CpmResult<()> {
+        if resolution.done.contains(&index) {
+            return Ok(());
+        }
+        let manifest = &resolution.package(index).manifest;
+        let id = manifest.package_id().to_string();
+        let dependencies = manifest.dependencies.clone();
+        if !resolution.visiting.insert(index) {
+            return Err(CpmError::Dependency(format!(
+                "dependency cycle through {}",
+                id
+            )));
+        }
+        for dep in &dependencies {
+            let req = VersionReq::parse(dep.version.as_deref().unwrap_or(""))?;
+            let provider = match resolution.selected.get(&dep.id) {
+                Some(&provider) => {
+                    let pkg = resolution.package(provider);
+                    if !req.is_any() && !req.matches(&version_of(pkg)?) {
+                        return Err(unmet(&id, dep, Some(pkg)));
+                    }
+                    provider
+                }
+                None => {
+                    let pkg = match self.newest(&dep.id, &req) {
+                        Some(pkg) => pkg,
+                        None => return Err(self.unavailable(&id, dep)),
+                    };
+                    resolution
+                        .selected
+                        .insert(dep.id.clone(), resolution.packages.len());
+                    resolution.packages.push(Some(pkg.clone()));
+                    resolution.packages.len() - 1
+                }
+            };
+            let files = &resolution.package(provider).files;
+            if let Some(file) = dep.files.iter().find(|f| !files.contains_key(&to_8_3(f))) {
+                return Err(CpmError::Dependency(format!(
+                    "{} needs {} from {}, which does not provide it",
+                    id, file, dep.id
+                )));
+            }
+            self.visit(provider, resolution)?;
+        }
+        resolution.visiting.remove(&index);
+        resolution.done.insert(index);
+        resolution.order.push(index);
+        Ok(())
+    }
+
+    /// Newest available version of `id` that meets `req`. Without a
+    /// constraint, a package whose version cannot be read will do.
+    fn newest(&self, id: &str, req: &VersionReq) -> Option<&LoadedPackage> {
+        let mut candidates = self
+            .available
+            .iter()
+            .filter(|pkg| pkg.manifest.package_id() == id);
+        let newest = candidates
+            .clone()
+            .filter_map(|pkg| version_of(pkg).ok().map(|v| (v, pkg)))
+            .filter(|(v, _)| req.matches(v))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, pkg)| pkg);
+        match newest {
+            None if req.is_any() => candidates.next(),
+            newest => newest,
+        }
+    }
+
+    /// Why no available package meets `dep`: an unreadable version if one
+    /// was in the way, otherwise the version found or its absence.
+    fn unavailable(&self, id: &str, dep: &Dependency) -> CpmError {
+        let mut candidates = self
+            .available
+            .iter()
+            .filter(|p| p.manifest.package_id() == dep.id);
+        if let Some(Err(e)) = candidates.clone().map(version_of).find(Result::is_err) {
+            return e;
+        }
+        unmet(id, dep, candidates.next())
+    }
+}
+
+/// Packages selected so far and the progress of the search. Packages
+/// are referred to by index; `selected` gives the one each id resolves to.
+#[derive(Default)]
+struct Resolution {
+    packages: Vec<Option<LoadedPackage>>,
+    selected: HashMap<String, usize>,
+    visiting: HashSet<usize>,
+    done: HashSet<usize>,
+    order: Vec<usize>,
+}
+
+impl Resolution {
+    fn package(&self, index: usize) -> &LoadedPackage {
+        self.packages[index]
+            .as_ref()
+            .expect("package not yet taken")
+    }
+}
+
+/// Version of a package; a package without one counts as `0`.
+fn version_of(pkg: &LoadedPackage) -> CpmResult<Version> {
+    let version = pkg.manifest.version.as_deref().unwrap_or("0");
+    Version::parse(version).map_err(|_| {
+        CpmError::Dependency(format!(
+            "{} has an invalid version: {}",
+            pkg.manifest.package_id(),
+            version
+        ))
+    })
+}
+
+fn unmet(id: &str, dep: &Dependency, found: Option<&LoadedPackage>) -> CpmError {
+    let wanted = match &dep.version {
+        Some(req) => format!("{} {}", dep.id, req),
+        None => dep.id.clone(),
+    };
+    CpmError::Dependency(match found {
+        Some(pkg) => format!(
+            "{} requires {}, but found version {}",
+            id,
+            wanted,
+            pkg.manifest.version.as_deref().unwrap_or("(none)")
+        ),
+        None => format!("{} requires {}, which is not available", id, wanted),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::PackageManifest;
+
+    fn package(id: &str, version: &str, deps: &[(&str, &str)], files: &[&str]) -> LoadedPackage {
+        let manifest = serde_json::json!({
+            "id": id,
+            "name": id,
+            "version": version,
+            "dependencies": deps
+                .iter()
+                .map(|(id, req)| serde_json::json!({ "id": id, "version": req }))
+                .collect::<Vec<_>>(),
+        });
+        LoadedPackage {
+            manifest: serde_json::from_value::<PackageManifest>(manifest).unwrap(),
+            files: files.iter().map(|f| (f.to_string(), Vec::new())).collect(),
+            actions: Vec::new(),
+        }
+    }
+
+    fn ids(packages: &[LoadedPackage]) -> Vec<String> {
+        packages
+            .iter()
+            .map(|p| {
+                format!(
+                    "{} {}",
+                    p.manifest.package_id(),
+                    p.manifest.version.as_deref().unwrap()
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_version_req() {
+        let req = VersionReq::parse(">=2.2, <3").unwrap();
+        assert!(req.matches(&Version::parse("2.2").unwrap()));
+        assert!(req.matches(&Version::parse("v2.10").unwrap()));
+        assert!(!req.matches(&Version::parse("2.1.9").unwrap()));
+        assert!(!req.matches(&Version::parse("3.0").unwrap()));
+        assert!(VersionReq::parse("2.2")
+            .unwrap()
+            .matches(&Version::parse("2.2.0").unwrap()));
+        assert!(VersionReq::parse("*")
+            .unwrap()
+            .matches(&Version::parse("0").unwrap()));
+        assert!(VersionReq::parse(">=two").is_err());
+    }
+
+    #[test]
+    fn test_resolve_orders_dependencies() {
+        let resolver = PackageResolver::new(vec![
+            package("cpm22", "2.0", &[], &["LOAD.COM"]),
+            package("cpm22", "2.2", &[], &["LOAD.COM"]),
+            package("cpm22", "3.0", &[], &["LOAD.COM"]),
+            package("libs", "1.0", &[("cpm22", "<3")], &["LIB.COM"]),
+        ]);
+        let bdsc = package("bds-c", "1.6", &[("libs", ""), ("cpm22", ">=2.2")], &[]);
+
+        let resolved = resolver.resolve(vec![bdsc]).unwrap();
+        assert_eq!(ids(&resolved), ["cpm22 2.2", "libs 1.0", "bds-c 1.6"]);
+
+        // Roots keep their order unless a dependency moves ahead
+        let extra = package("extra", "1", &[], &[]);
+        let tool = package("tool", "1", &[("extra", "")], &[]);
+        let plain = package("plain", "1", &[], &[]);
+        let resolved = resolver
+            .resolve(vec![plain.clone(), tool, plain, extra])
+            .unwrap();
+        assert_eq!(ids(&resolved), ["plain 1", "extra 1", "tool 1", "plain 1"]);
+    }
+
+    #[test]
+    fn test_resolve_errors() {
+        let resolver = PackageResolver::new(vec![package("cpm22", "2.0", &[], &["PIP.COM"])]);
+        let needs = |req: &str| package("tool", "1", &[("cpm22", req)], &[]);
+
+        let err = resolver.resolve(vec![needs(">=2.2")]).unwrap_err();
+        assert!(err.to_string().contains("found version 2.0"), "{}", err);
+        let missing = package("tool", "1", &[("zsdos", "")], &[]);
+        assert!(matches!(
+            resolver.resolve(vec![missing]),
+            Err(CpmError::Dependency(msg)) if msg.contains("not available")
+        ));
+
+        let mut load = needs("");
+        load.manifest.dependencies[0].files = vec!["load.com".to_string()];
+        assert!(resolver.resolve(vec![load]).is_err());
+
+        // A version that cannot be read meets no constraint but is
+        // accepted without one
+        let resolver = PackageResolver::new(vec![package("cpm22", "2.2a", &[], &[])]);
+        assert!(matches!(
+            resolver.resolve(vec![needs(">=2.2")]),
+            Err(CpmError::Dependency(msg)) if msg.contains("invalid version: 2.2a")
+        ));
+        assert_eq!(
+            ids(&resolver.resolve(vec![needs("")]).unwrap()),
+            ["cpm22 2.2a", "tool 1"]
+        );
+        let odd = package("cpm22", "2.2a", &[], &[]);
+        assert!(resolver.resolve(vec![odd.clone(), needs("")]).is_ok());
+        assert!(resolver.resolve(vec![odd, needs("2.2")]).is_err());
+
+        let a = package("a", "1", &[("b", "")], &[]);
+        let b = package("b", "1", &[("a", "")], &[]);
+        assert!(matches!(
+            PackageResolver::default().resolve(vec![a, b]),
+            Err(CpmError::Dependency(msg)) if msg.contains("cycle")
+        ));
+    }
+}
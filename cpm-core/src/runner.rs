@@ -0,0 +1,394 @@
+//! Program runner for tests and automation.
+//!
+//! `CpmRunner` owns a source drive (A:) and a tools drive (B:), both
+//! overlays over package contents. `run` loads a program, feeds it queued
+//! input, and reports which files it created, changed or deleted by
+//! diffing the overlays before and after the run. Each result carries a
+//! `Receipt` of the run's inputs and outputs.
+
+use std::collections::{BTreeSet, HashMap};
+
+pub use crate::action::DEFAULT_MAX_T_STATES;
+use crate::bdos::addr;
+use crate::console::HeadlessConsole;
+use crate::emulator::CpmEmulator;
+use crate::error::{CpmError, CpmResult};
+use crate::fs::{DriveFS, OverlayDriveFS};
+use crate::package::{load_package, LoadedPackage, PackageDriveFS};
+use crate::receipt::{FileDigest, Receipt, RunConfig};
+use crate::{CpmExitInfo, ExitReason};
+
+/// Drive holding source code and build outputs.
+pub const SOURCE_DRIVE: u8 = 0;
+/// Drive holding tools from packages.
+pub const TOOLS_DRIVE: u8 = 1;
+
+type RunnerDrive = OverlayDriveFS<PackageDriveFS>;
+
+/// Options for `CpmRunner::run`.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// Command tail (upper-cased, parsed into FCB1/FCB2).
+    pub args: String,
+    /// Console input queued before the run.
+    pub input: Vec<u8>,
+    /// Give up after this many T-states.
+    pub max_t_states: u64,
+    /// Current drive during the run; defaults to the program's drive.
+    pub working_drive: Option<u8>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            args: String::new(),
+            input: Vec::new(),
+            max_t_states: DEFAULT_MAX_T_STATES,
+            working_drive: None,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Options with a command tail.
+    pub fn args(args: &str) -> Self {
+        Self {
+            args: args.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Queue input lines, each terminated with CR.
+    pub fn with_lines<S: AsRef<str>>(mut self, lines: &[S]) -> Self {
+        for line in lines {
+            self.input.extend_from_slice(line.as_ref().as_bytes());
+            self.input.push(b'\r');
+        }
+        self
+    }
+}
+
+/// Outcome of a run. File names are `D:NAME.EXT`.
+#[derive(Debug, Clone)]
+pub struct RunResult {
+    pub output: String,
+    pub exit_info: CpmExitInfo,
+    pub new_files: Vec<String>,
+    pub modified_files: Vec<String>,
+    pub deleted_files: Vec<String>,
+    pub timed_out: bool,
+    /// Digests of the packages, files read and files written.
+    pub receipt: Receipt,
+}
+
+/// Overlay state of one drive.
+struct DriveSnapshot {
+    files: BTreeSet<String>,
+    overlay: HashMap<String, Vec<u8>>,
+}
+
+impl DriveSnapshot {
+    fn of(fs: &RunnerDrive) -> Self {
+        Self {
+            files: fs.list_files().into_iter().collect(),
+            overlay: fs.modified_files().clone(),
+        }
+    }
+}
+
+/// Runs programs against a source drive and a tools drive.
+pub struct CpmRunner {
+    emu: CpmEmulator<HeadlessConsole, RunnerDrive>,
+    /// Package ZIPs added, for receipts.
+    packages: Vec<FileDigest>,
+}
+
+impl Default for CpmRunner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CpmRunner {
+    pub fn new() -> Self {
+        let mut emu = CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(SOURCE_DRIVE, OverlayDriveFS::new(PackageDriveFS::new()));
+        emu.mount(TOOLS_DRIVE, OverlayDriveFS::new(PackageDriveFS::new()));
+        Self {
+            emu,
+            packages: Vec::new(),
+        }
+    }
+
+    /// Add a package's files to the tools drive.
+    pub fn add_package(&mut self, pkg: LoadedPackage) {
+        self.tools_mut().base_mut().add_package(pkg);
+    }
+
+    /// Load a package ZIP onto the tools drive, recording its digest
+    /// under `name` for receipts.
+    pub fn add_package_zip(&mut self, name: &str, zip: &[u8]) -> CpmResult<()> {
+        let pkg = load_package(std::io::Cursor::new(zip))?;
+        self.packages.push(FileDigest::of(name, zip));
+        self.add_package(pkg);
+        Ok(())
+    }
+
+    /// Add a file to the tools drive.
+    pub fn add_tool(&mut self, name: &str, data: &[u8]) -> CpmResult<()> {
+        self.tools_mut().write_file(name, data)
+    }
+
+    /// Add a file to the source drive.
+    pub fn add_source(&mut self, name: &str, data: &[u8]) -> CpmResult<()> {
+        self.drive_mut(SOURCE_DRIVE)?.write_file(name, data)
+    }
+
+    /// Read a file from a drive.
+    pub fn file(&self, drive: u8, name: &str) -> Option<Vec<u8>> {
+        self.emu.drive(drive)?.read_file(name)
+    }
+
+    /// Mount another drive (C: and up) for the runs.
+    pub fn mount(&mut self, drive: u8, fs: RunnerDrive) {
+        self.emu.mount(drive, fs);
+    }
+
+    /// The underlying emulator, e.g. to enable tracing.
+    pub fn emulator_mut(&mut self) -> &mut CpmEmulator<HeadlessConsole, RunnerDrive> {
+        &mut self.emu
+    }
+
+    /// Run `program` (`NAME`, `NAME.COM` or `D:NAME`). Without a drive
+    /// prefix every mounted drive is searched, A: first.
+    pub fn run(&mut self, program: &str, options: &RunOptions) -> CpmResult<RunResult> {
+        let (drive, com_name) = self.find_program(program)?;
+        let binary = self
+            .file(drive, &com_name)
+            .ok_or_else(|| CpmError::FileNotFound(com_name.clone()))?;
+
+        let before: Vec<(u8, DriveSnapshot)> = self.snapshots();
+
+        *self.emu.console_mut() = HeadlessConsole::with_input(&options.input);
+        self.emu
+            .select_drive(options.working_drive.unwrap_or(drive));
+        self.emu.load_com(&binary);
+        self.emu.load_symbols_for(drive, &com_name);
+        self.emu.set_args(&options.args);
+        self.emu.record_files(true);
+        self.emu.start(addr::TPA);
+
+        let deadline = self.emu.t_states().saturating_add(options.max_t_states);
+        let (exit_info, timed_out) = loop {
+            if let Some(info) = self.emu.step()? {
+                break (info, false);
+            }
+            if self.emu.t_states() >= deadline {
+                let info = CpmExitInfo {
+                    reason: ExitReason::Error("Timeout".to_string()),
+                    t_states: self.emu.t_states(),
+                    pc: self.emu.pc(),
+                };
+                break (info, true);
+            }
+        };
+
+        let command = format!("{}:{} {}", (b'A' + drive) as char, com_name, options.args);
+        let mut receipt = Receipt::new(
+            RunConfig::of(&self.emu, options.max_t_states),
+            command.trim_end(),
+            &options.input,
+        );
+        receipt.packages = self.packages.clone();
+        receipt.record_files(&self.emu);
+        self.emu.record_files(false);
+
+        let mut result = RunResult {
+            output: self.emu.console().output_string(),
+            exit_info,
+            new_files: Vec::new(),
+            modified_files: Vec::new(),
+            deleted_files: Vec::new(),
+            timed_out,
+            receipt,
+        };
+        for (drive, old) in before {
+            let Some(fs) = self.emu.drive(drive) else {
+                continue;
+            };
+            let new = DriveSnapshot::of(fs);
+            let path = |name: &String| format!("{}:{}", (b'A' + drive) as char, name);
+            for name in &new.files {
+                if !old.files.contains(name) {
+                    result.new_files.push(path(name));
+                } else if new.overlay.get(name) != old.overlay.get(name) {
+                    result.modified_files.push(path(name));
+                }
+            }
+            for name in old.files.difference(&new.files) {
+                result.deleted_files.push(path(name));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Drive and `.COM` name for a program reference.
+    fn find_program(&self, program: &str) -> CpmResult<(u8, String)> {
+        let upper = program.to_uppercase();
+        let (drive, name) = match upper.as_bytes() {
+            [d @ b'A'..=b'P', b':', ..] => (Some(d - b'A'), &upper[2..]),
+            _ => (None, upper.as_str()),
+        };
+        let com_name = if name.ends_with(".COM") {
+            name.to_string()
+        } else {
+            format!("{}.COM", name)
+        };
+
+        let found = match drive {
+            Some(d) => self
+                .emu
+                .drive(d)
+                .is_some_and(|fs| fs.exists(&com_name))
+                .then_some(d),
+            None => (0..16).find(|&d| self.emu.drive(d).is_some_and(|fs| fs.exists(&com_name))),
+        };
+        match found {
+            Some(d) => Ok((d, com_name)),
+            None => Err(CpmError::FileNotFound(match drive {
+                Some(d) => format!("{}:{}", (b'A' + d) as char, com_name),
+                None => com_name,
+            })),
+        }
+    }
+
+    fn snapshots(&self) -> Vec<(u8, DriveSnapshot)> {
+        (0..16)
+            .filter_map(|d| self.emu.drive(d).map(|fs| (d, DriveSnapshot::of(fs))))
+            .collect()
+    }
+
+    fn tools_mut(&mut self) -> &mut RunnerDrive {
+        self.emu
+            .drive_mut(TOOLS_DRIVE)
+            .expect("tools drive mounted")
+    }
+
+    fn drive_mut(&mut self, drive: u8) -> CpmResult<&mut RunnerDrive> {
+        self.emu
+            .drive_mut(drive)
+            .ok_or(CpmError::DriveNotMounted((b'A' + drive) as char))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Delete the FCB1 file and create the FCB2 one.
+    const MOVE_COM: [u8; 38] = [
+        0x11, 0x5C, 0x00, // 0100: LD DE,005C
+        0x0E, 0x13, // LD C,19 (delete)
+        0xCD, 0x05, 0x00, // CALL 5
+        0x21, 0x6C, 0x00, // LD HL,006C
+        0x11, 0x5C, 0x00, // LD DE,005C
+        0x01, 0x10, 0x00, // LD BC,16
+        0xED, 0xB0, // LDIR
+        0x11, 0x5C, 0x00, // LD DE,005C
+        0x0E, 0x16, // LD C,22 (make)
+        0xCD, 0x05, 0x00, // CALL 5
+        0x11, 0x5C, 0x00, // LD DE,005C
+        0x0E, 0x10, // LD C,16 (close)
+        0xCD, 0x05, 0x00, // CALL 5
+        0xC3, 0x00, 0x00, // JP 0
+    ];
+
+    #[test]
+    fn test_file_changes() {
+        let mut runner = CpmRunner::new();
+        runner.add_tool("MOVE.COM", &MOVE_COM).unwrap();
+        runner.add_source("OLD.TXT", b"old").unwrap();
+        runner.add_source("KEEP.TXT", b"keep").unwrap();
+
+        let result = runner
+            .run("move", &RunOptions::args("A:OLD.TXT A:NEW.TXT"))
+            .unwrap();
+
+        assert_eq!(result.exit_info.reason, ExitReason::WarmBoot);
+        assert!(!result.timed_out);
+        assert_eq!(result.new_files, ["A:NEW.TXT"]);
+        assert_eq!(result.deleted_files, ["A:OLD.TXT"]);
+        assert!(result.modified_files.is_empty());
+        assert_eq!(runner.file(SOURCE_DRIVE, "NEW.TXT"), Some(Vec::new()));
+        assert_eq!(result.receipt.command, "B:MOVE.COM A:OLD.TXT A:NEW.TXT");
+        assert_eq!(result.receipt.outputs, [FileDigest::of("A:NEW.TXT", b"")]);
+    }
+
+    #[test]
+    fn test_receipt_records_reads() {
+        // Open FCB1, then warm boot
+        let mut runner = CpmRunner::new();
+        runner
+            .add_tool(
+                "PEEK.COM",
+                &[
+                    0x11, 0x5C, 0x00, 0x0E, 0x0F, 0xCD, 0x05, 0x00, 0xC3, 0x00, 0x00,
+                ],
+            )
+            .unwrap();
+        runner.add_source("DATA.TXT", b"data").unwrap();
+        runner.emulator_mut().set_deterministic(true);
+
+        let first = runner.run("peek", &RunOptions::args("A:DATA.TXT")).unwrap();
+        assert_eq!(
+            first.receipt.inputs,
+            [FileDigest::of("A:DATA.TXT", b"data")]
+        );
+        assert!(first.receipt.outputs.is_empty());
+        assert!(first.receipt.config.deterministic);
+
+        let again = runner.run("peek", &RunOptions::args("A:DATA.TXT")).unwrap();
+        assert!(first.receipt.mismatches(&again.receipt).is_empty());
+        runner.add_source("DATA.TXT", b"changed").unwrap();
+        let changed = runner.run("peek", &RunOptions::args("A:DATA.TXT")).unwrap();
+        assert_eq!(
+            first.receipt.mismatches(&changed.receipt),
+            ["input A:DATA.TXT changed"]
+        );
+    }
+
+    #[test]
+    fn test_input_and_timeout() {
+        let mut runner = CpmRunner::new();
+        // Read a key, print it, then spin
+        runner
+            .add_source(
+                "SPIN.COM",
+                &[
+                    0x0E, 0x01, 0xCD, 0x05, 0x00, 0x5F, 0x0E, 0x02, 0xCD, 0x05, 0x00, 0x18, 0xFE,
+                ],
+            )
+            .unwrap();
+        let options = RunOptions {
+            input: b"Z".to_vec(),
+            max_t_states: 10_000,
+            ..RunOptions::default()
+        };
+        let result = runner.run("A:SPIN", &options).unwrap();
+
+        assert!(result.timed_out);
+        assert_eq!(result.output, "Z");
+    }
+
+    #[test]
+    fn test_program_not_found() {
+        let mut runner = CpmRunner::new();
+        runner.add_tool("TOOL.COM", &[0xC7]).unwrap();
+
+        assert!(runner.run("TOOL", &RunOptions::default()).is_ok());
+        assert!(matches!(
+            runner.run("A:TOOL", &RunOptions::default()),
+            Err(CpmError::FileNotFound(name)) if name == "A:TOOL.COM"
+        ));
+    }
+}
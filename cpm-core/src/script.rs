@@ -0,0 +1,418 @@
+//! Expect-style scripted console.
+//!
+//! A `ScriptedConsole` drives menu-driven programs the way a user would:
+//! each `ScriptStep` waits until the console output produced since the
+//! previous match matches a regular expression, then types its reply.
+//! Steps can time out after a number of T-states, or hold their reply back
+//! until a given T-state so input arrives at the same point in every run.
+//! A program asking for input that the script cannot provide is reported
+//! instead of being fed zero bytes. Failures carry the tail of the output
+//! for diagnosis.
+
+use std::collections::VecDeque;
+
+use regex::bytes::Regex;
+
+use crate::console::CpmConsole;
+use crate::emulator::CpmEmulator;
+use crate::error::{CpmError, CpmResult};
+use crate::fs::DriveFS;
+use crate::CpmExitInfo;
+
+/// Bytes of output quoted in a script failure.
+const RECENT_OUTPUT: usize = 512;
+
+/// Wait for `expect` to appear in the output, then send `send`.
+#[derive(Debug, Clone)]
+pub struct ScriptStep {
+    expect: Regex,
+    send: String,
+    timeout: Option<u64>,
+    not_before: Option<u64>,
+}
+
+impl ScriptStep {
+    /// Step matching a regular expression.
+    pub fn new(expect: &str, send: &str) -> CpmResult<Self> {
+        Ok(Self {
+            expect: Regex::new(expect)?,
+            send: send.to_string(),
+            timeout: None,
+            not_before: None,
+        })
+    }
+
+    /// Step matching literal text.
+    pub fn literal(expect: &str, send: &str) -> Self {
+        Self {
+            expect: Regex::new(&regex::escape(expect)).expect("escaped pattern"),
+            send: send.to_string(),
+            timeout: None,
+            not_before: None,
+        }
+    }
+
+    /// Step that sends its input at T-state `t_states`, whatever the
+    /// output.
+    pub fn at(t_states: u64, send: &str) -> Self {
+        Self::literal("", send).not_before(t_states)
+    }
+
+    /// Fail if the pattern has not matched this many T-states after the
+    /// step became current.
+    pub fn with_timeout(mut self, t_states: u64) -> Self {
+        self.timeout = Some(t_states);
+        self
+    }
+
+    /// Hold the reply back until T-state `t_states`, even if the pattern
+    /// has matched.
+    pub fn not_before(mut self, t_states: u64) -> Self {
+        self.not_before = Some(t_states);
+        self
+    }
+
+    /// The expected pattern.
+    pub fn pattern(&self) -> &str {
+        self.expect.as_str()
+    }
+
+    /// What the step waits for, for messages.
+    fn describe(&self) -> String {
+        match self.not_before {
+            Some(t) if self.expect.as_str().is_empty() => format!("T-state {}", t),
+            _ => format!("/{}/", self.pattern()),
+        }
+    }
+}
+
+/// Console that answers prompts from a list of expect/send steps.
+#[derive(Debug, Default)]
+pub struct ScriptedConsole {
+    output: Vec<u8>,
+    input: VecDeque<u8>,
+    steps: Vec<ScriptStep>,
+    next: usize,
+    /// Output offset where the search for the current step starts.
+    mark: usize,
+    /// T-state count when the current step became current.
+    started: Option<u64>,
+    /// Latest T-state count seen.
+    now: u64,
+    default_timeout: Option<u64>,
+    failure: Option<String>,
+}
+
+impl ScriptedConsole {
+    pub fn new(steps: Vec<ScriptStep>) -> Self {
+        Self {
+            steps,
+            ..Self::default()
+        }
+    }
+
+    /// Timeout for steps that don't set their own.
+    pub fn with_default_timeout(mut self, t_states: u64) -> Self {
+        self.default_timeout = Some(t_states);
+        self
+    }
+
+    /// Queue input ahead of the script (type-ahead).
+    pub fn queue_input(&mut self, input: &[u8]) {
+        self.input.extend(input.iter().copied());
+    }
+
+    /// Get all output as bytes.
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Get output as string (lossy UTF-8 conversion).
+    pub fn output_string(&self) -> String {
+        String::from_utf8_lossy(&self.output).into_owned()
+    }
+
+    /// Number of steps matched so far.
+    pub fn steps_completed(&self) -> usize {
+        self.next
+    }
+
+    /// True when every step has matched.
+    pub fn finished(&self) -> bool {
+        self.next >= self.steps.len()
+    }
+
+    /// Match pending steps against new output and check the current step's
+    /// timeout. Call after each emulator step with the current T-state count.
+    pub fn poll(&mut self, t_states: u64) -> CpmResult<()> {
+        self.now = t_states;
+        self.advance();
+        if self.failure.is_none() && !self.finished() {
+            let started = *self.started.get_or_insert(t_states);
+            let step = &self.steps[self.next];
+            if let Some(limit) = step.timeout.or(self.default_timeout) {
+                if t_states - started > limit {
+                    self.failure = Some(format!(
+                        "timed out after {} T-states waiting for {}",
+                        limit,
+                        step.describe()
+                    ));
+                }
+            }
+        }
+        match self.failure.take() {
+            Some(message) => Err(self.error(message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Error for the current step, quoting the recent output.
+    pub fn error(&self, message: String) -> CpmError {
+        let start = self.output.len().saturating_sub(RECENT_OUTPUT);
+        CpmError::Script {
+            step: self.next + 1,
+            message,
+            output: String::from_utf8_lossy(&self.output[start..]).into_owned(),
+        }
+    }
+
+    /// Match as many steps as the output allows, queuing their replies.
+    fn advance(&mut self) {
+        while let Some(step) = self.steps.get(self.next) {
+            if step.not_before.is_some_and(|t| self.now < t) {
+                break;
+            }
+            let Some(found) = step.expect.find(&self.output[self.mark..]) else {
+                break;
+            };
+            self.mark += found.end();
+            self.input.extend(step.send.bytes());
+            self.next += 1;
+            self.started = None;
+        }
+    }
+
+    /// Next input byte, recording a failure when the program blocks on
+    /// input the script cannot provide.
+    fn next_key(&mut self) -> u8 {
+        if self.input.is_empty() {
+            self.advance();
+        }
+        if let Some(key) = self.input.pop_front() {
+            return key;
+        }
+        if self.failure.is_none() {
+            self.failure = Some(match self.steps.get(self.next) {
+                Some(step) => format!("program is waiting for input, {} not seen", step.describe()),
+                None => "program is waiting for input after the script ended".to_string(),
+            });
+        }
+        0x1A
+    }
+}
+
+impl CpmConsole for ScriptedConsole {
+    fn write(&mut self, ch: u8) {
+        self.output.push(ch);
+    }
+
+    fn has_key(&self) -> bool {
+        !self.input.is_empty()
+    }
+
+    fn get_key(&mut self) -> Option<u8> {
+        if self.input.is_empty() {
+            self.advance();
+        }
+        self.input.pop_front()
+    }
+
+    fn wait_for_key(&mut self) -> u8 {
+        self.next_key()
+    }
+
+    fn input_closed(&self) -> bool {
+        self.input.is_empty() && self.finished()
+    }
+
+    fn take_break(&mut self) -> bool {
+        if self.input.front() == Some(&0x03) {
+            self.input.pop_front();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn set_time(&mut self, t_states: u64) {
+        self.now = t_states;
+        self.advance();
+    }
+
+    fn next_input_at(&self) -> Option<u64> {
+        if !self.input.is_empty() {
+            return None;
+        }
+        let step = self.steps.get(self.next)?;
+        step.expect
+            .is_match(&self.output[self.mark..])
+            .then_some(step.not_before)
+            .flatten()
+    }
+}
+
+/// Run the loaded program under its script until it exits. Fails if a step
+/// times out, the program blocks on input the script can't give, it exits
+/// with steps left, or `max_t_states` pass.
+pub fn run_script<D: DriveFS>(
+    emu: &mut CpmEmulator<ScriptedConsole, D>,
+    max_t_states: u64,
+) -> CpmResult<CpmExitInfo> {
+    let deadline = emu.t_states().saturating_add(max_t_states);
+    loop {
+        let exit = emu.step()?;
+        let t_states = emu.t_states();
+        emu.console_mut().poll(t_states)?;
+
+        if let Some(info) = exit {
+            let console = emu.console();
+            if !console.finished() {
+                let step = console.steps[console.next].describe();
+                return Err(console.error(format!(
+                    "program exited ({:?}) before {} was seen",
+                    info.reason, step
+                )));
+            }
+            return Ok(info);
+        }
+        if t_states >= deadline {
+            return Err(emu
+                .console()
+                .error(format!("no exit after {} T-states", max_t_states)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryDriveFS;
+
+    /// Print "Compile?" and echo keys read with BDOS 1 until 'Q'.
+    const MENU_COM: [u8; 35] = [
+        0x11, 0x1A, 0x01, // 0100: LD DE,011A
+        0x0E, 0x09, // LD C,9
+        0xCD, 0x05, 0x00, // CALL 5
+        0x0E, 0x01, // 0108: LD C,1
+        0xCD, 0x05, 0x00, // CALL 5
+        0xF5, // PUSH AF
+        0x5F, // LD E,A
+        0x0E, 0x02, // LD C,2
+        0xCD, 0x05, 0x00, // CALL 5
+        0xF1, // POP AF
+        0xFE, b'Q', // CP 'Q'
+        0x20, 0xEF, // JR NZ,0108
+        0xC7, // RST 0
+        b'C', b'o', b'm', b'p', b'i', b'l', b'e', b'?', b'$', // 011A: "Compile?$"
+    ];
+
+    fn emulator(steps: Vec<ScriptStep>) -> CpmEmulator<ScriptedConsole, MemoryDriveFS> {
+        let mut emu = CpmEmulator::new(ScriptedConsole::new(steps));
+        emu.mount(0, MemoryDriveFS::new());
+        emu.load_com(&MENU_COM);
+        emu.start(0x100);
+        emu
+    }
+
+    #[test]
+    fn test_expect_send() {
+        let steps = vec![
+            ScriptStep::new(r"Comp\w+\?", "CX").unwrap(),
+            ScriptStep::literal("CX", "Q"),
+        ];
+        let mut emu = emulator(steps);
+        let info = run_script(&mut emu, 1_000_000).unwrap();
+
+        assert_eq!(info.reason, crate::ExitReason::WarmBoot);
+        assert_eq!(emu.console().output_string(), "Compile?CXQ");
+        assert_eq!(emu.console().steps_completed(), 2);
+    }
+
+    #[test]
+    fn test_blocked_on_input() {
+        let mut emu = emulator(vec![ScriptStep::literal("Link?", "L")]);
+        let err = run_script(&mut emu, 1_000_000).unwrap_err();
+        let text = err.to_string();
+
+        assert!(text.contains("step 1"), "{}", text);
+        assert!(text.contains("/Link\\?/ not seen"), "{}", text);
+        assert!(text.ends_with("Compile?"), "{}", text);
+    }
+
+    #[test]
+    fn test_step_timeout() {
+        let mut emu = emulator(vec![ScriptStep::literal("never", "").with_timeout(1_000)]);
+        emu.load_com(&[0x18, 0xFE]); // JR $
+        let err = run_script(&mut emu, 1_000_000).unwrap_err();
+
+        assert!(matches!(err, CpmError::Script { step: 1, .. }));
+        assert!(err.to_string().contains("timed out after 1000 T-states"));
+    }
+
+    #[test]
+    fn test_exit_with_steps_left() {
+        let mut emu = emulator(vec![ScriptStep::literal("Compile?", "Q")]);
+        emu.console_mut()
+            .steps
+            .push(ScriptStep::literal("Done", ""));
+        let err = run_script(&mut emu, 1_000_000).unwrap_err();
+
+        assert!(matches!(err, CpmError::Script { step: 2, .. }));
+        assert!(err.to_string().contains("before /Done/"));
+    }
+
+    #[test]
+    fn test_high_bit_output() {
+        // Bytes with bit 7 set, as WordStar prints, are not UTF-8
+        let mut console = ScriptedConsole::new(vec![
+            ScriptStep::literal("A", "1"),
+            ScriptStep::literal("B", "2"),
+        ]);
+        for &ch in b"\xC1\xD4A\x80B" {
+            console.write(ch);
+        }
+        assert_eq!(console.get_key(), Some(b'1'));
+        assert_eq!(console.get_key(), Some(b'2'));
+        assert!(console.finished());
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(matches!(
+            ScriptStep::new("(", ""),
+            Err(CpmError::Pattern(_))
+        ));
+    }
+
+    #[test]
+    fn test_step_at_t_state() {
+        let mut emu = emulator(vec![ScriptStep::at(500_000, "Q")]);
+        let info = run_script(&mut emu, 1_000_000).unwrap();
+
+        // The program blocks in BDOS 1 until the key arrives
+        assert!(info.t_states >= 500_000);
+        assert_eq!(emu.console().output_string(), "Compile?Q");
+    }
+
+    #[test]
+    fn test_not_before_holds_reply() {
+        let steps = vec![ScriptStep::literal("Compile?", "Q").not_before(200_000)];
+        let mut emu = emulator(steps);
+        emu.console_mut().set_time(1_000);
+        assert!(emu.console().input.is_empty());
+        assert_eq!(emu.console().next_input_at(), None);
+
+        let info = run_script(&mut emu, 1_000_000).unwrap();
+        assert!(info.t_states >= 200_000);
+    }
+}
@@ -0,0 +1,357 @@
+//! Symbol tables from assembler output.
+//!
+//! Reads `.SYM` files (`0100 START` pairs, as written by MAC, RMAC, LINK and
+//! SLR tools) and `.PRN` listings from ASM, LASM3, Z80MR, Z1 and ZASM. In a
+//! listing, labels are taken from the source column and the trailing symbol
+//! table (if any) fills in the rest. Addresses are then shown as
+//! `MAINLOOP+3` in traces, disassembly and profiles.
+
+use std::collections::BTreeMap;
+
+/// Largest offset from a symbol that is still shown as `NAME+n`.
+const MAX_OFFSET: u16 = 0x100;
+
+/// Address ↔ name map.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    /// Preferred name at each address (first one defined).
+    by_addr: BTreeMap<u16, String>,
+    by_name: BTreeMap<String, u16>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a symbol file, choosing the format from the extension
+    /// (`.SYM`, otherwise a `.PRN`/`.LST` listing).
+    pub fn parse(filename: &str, data: &[u8]) -> Self {
+        // Stop at the CP/M EOF marker; records after it are garbage
+        let end = data.iter().position(|&b| b == 0x1A).unwrap_or(data.len());
+        let text = String::from_utf8_lossy(&data[..end]);
+        if filename.to_uppercase().ends_with(".SYM") {
+            Self::parse_sym(&text)
+        } else {
+            Self::parse_prn(&text)
+        }
+    }
+
+    /// Parse a `.SYM` file: whitespace-separated `ADDR NAME` pairs.
+    pub fn parse_sym(text: &str) -> Self {
+        let mut table = Self::new();
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let mut i = 0;
+        while i + 1 < tokens.len() {
+            match (parse_hex(tokens[i]), is_name(tokens[i + 1])) {
+                (Some(addr), true) => {
+                    table.insert(tokens[i + 1], addr);
+                    i += 2;
+                }
+                _ => i += 1,
+            }
+        }
+        table
+    }
+
+    /// Parse an assembler listing.
+    pub fn parse_prn(text: &str) -> Self {
+        let lines: Vec<&str> = text.lines().map(|l| l.trim_end_matches('\r')).collect();
+        let mut table = Self::new();
+
+        // Column layout, learned from the first line with address and code
+        let Some((addr_col, source_col)) = lines.iter().find_map(|l| listing_columns(l)) else {
+            return table;
+        };
+
+        // Labels on lines without an address take the next address listed
+        let mut pending: Vec<String> = Vec::new();
+        let mut in_table = false;
+        let mut table_entries = Vec::new();
+
+        for line in &lines {
+            if in_table {
+                table_entries.extend(table_pairs(line));
+                continue;
+            }
+            let Some((value, label)) = listing_line(line, addr_col, source_col) else {
+                if line.to_lowercase().contains("symbol") {
+                    in_table = true;
+                }
+                continue;
+            };
+            if let Some(value) = value {
+                for name in pending.drain(..) {
+                    table.insert(&name, value);
+                }
+            }
+            if let Some(label) = label {
+                match value {
+                    Some(value) => table.insert(&label, value),
+                    None => pending.push(label),
+                }
+            }
+        }
+
+        // The symbol table may hold names truncated to 6 characters; keep
+        // the full listing name when one exists at the same value.
+        for (name, addr) in table_entries {
+            let covered = table
+                .by_name
+                .iter()
+                .any(|(full, &a)| a == addr && full.starts_with(&name));
+            if !covered {
+                table.insert(&name, addr);
+            }
+        }
+        table
+    }
+
+    /// Add a symbol. Names are case-insensitive and stored in upper case.
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        let name = name.to_uppercase();
+        if self.by_name.contains_key(&name) {
+            return;
+        }
+        self.by_addr.entry(addr).or_insert_with(|| name.clone());
+        self.by_name.insert(name, addr);
+    }
+
+    /// Add all symbols from another table.
+    pub fn merge(&mut self, other: &SymbolTable) {
+        for (name, &addr) in &other.by_name {
+            self.insert(name, addr);
+        }
+    }
+
+    /// Address of a symbol.
+    pub fn get(&self, name: &str) -> Option<u16> {
+        self.by_name.get(&name.to_uppercase()).copied()
+    }
+
+    /// Name defined exactly at an address.
+    pub fn name_at(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(|s| s.as_str())
+    }
+
+    /// Nearest symbol at or below an address, with the offset from it.
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u16)> {
+        let (&base, name) = self.by_addr.range(..=addr).next_back()?;
+        let offset = addr - base;
+        (offset < MAX_OFFSET).then_some((name.as_str(), offset))
+    }
+
+    /// Format an address as `NAME`, `NAME+n`, or `XXXX` if no symbol is near.
+    pub fn format_addr(&self, addr: u16) -> String {
+        match self.lookup(addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("{:04X}", addr),
+        }
+    }
+
+    /// Number of symbols.
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    /// True if the table has no symbols.
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    /// Iterate over symbols in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut all: Vec<(&str, u16)> = self
+            .by_name
+            .iter()
+            .map(|(name, &addr)| (name.as_str(), addr))
+            .collect();
+        all.sort_by_key(|&(name, addr)| (addr, name));
+        all.into_iter()
+    }
+}
+
+/// Parse a 4- or 6-digit hex address (6-digit Z1 addresses are truncated).
+fn parse_hex(token: &str) -> Option<u16> {
+    if (token.len() == 4 || token.len() == 6) && token.chars().all(|c| c.is_ascii_hexdigit()) {
+        u32::from_str_radix(token, 16).ok().map(|v| v as u16)
+    } else {
+        None
+    }
+}
+
+/// Whether a token looks like an assembler symbol name.
+fn is_name(token: &str) -> bool {
+    let mut chars = token.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || "_.?@$".contains(c))
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.?@$".contains(c))
+}
+
+/// Split a line into whitespace-separated tokens with their columns.
+fn tokens(line: &str) -> Vec<(usize, &str)> {
+    let mut out = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                out.push((s, &line[s..i]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        out.push((s, &line[s..]));
+    }
+    out
+}
+
+/// Find the address and source columns from a line such as
+/// ` 0100 0E09      START:` or `     3   0100 0E09      START:`.
+/// The source field starts 11 columns after the address.
+fn listing_columns(line: &str) -> Option<(usize, usize)> {
+    let toks = tokens(line);
+    for (i, &(col, tok)) in toks.iter().enumerate() {
+        let Some(&(next_col, next)) = toks.get(i + 1) else {
+            break;
+        };
+        let is_code =
+            next.len() >= 2 && next.len() % 2 == 0 && next.chars().all(|c| c.is_ascii_hexdigit());
+        if parse_hex(tok).is_some() && is_code && next_col == col + tok.len() + 1 {
+            return Some((col, col + tok.len() + 11));
+        }
+        // Only a line number or error flag may precede the address
+        if !(tok.chars().all(|c| c.is_ascii_digit()) || tok.len() == 1) {
+            break;
+        }
+    }
+    None
+}
+
+/// Parse one listing line into its value (address or EQU value) and label.
+/// Returns None for lines that are not part of the listing body.
+fn listing_line(
+    line: &str,
+    addr_col: usize,
+    source_col: usize,
+) -> Option<(Option<u16>, Option<String>)> {
+    let prefix_end = source_col.min(line.len());
+    let prefix = line.get(..prefix_end)?;
+
+    let mut value = None;
+    for (col, tok) in tokens(prefix) {
+        if col < addr_col {
+            // Line number or error flag
+            if !(tok.chars().all(|c| c.is_ascii_digit()) || tok.len() == 1) {
+                return None;
+            }
+        } else if tok == "=" || tok.chars().all(|c| c.is_ascii_hexdigit()) {
+            if value.is_none() {
+                value = Some(parse_hex(tok)?);
+            }
+        } else {
+            return None;
+        }
+    }
+
+    let source = line.get(source_col..).unwrap_or("");
+    let label = source
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .next()
+        .filter(|name| is_name(name))
+        .map(|name| name.to_string());
+    Some((value, label))
+}
+
+/// Parse `NAME ADDR` pairs from a symbol table line.
+fn table_pairs(line: &str) -> Vec<(String, u16)> {
+    let toks: Vec<&str> = line.split_whitespace().collect();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i + 1 < toks.len() {
+        match (is_name(toks[i]), parse_hex(toks[i + 1])) {
+            (true, Some(addr)) => {
+                out.push((toks[i].to_string(), addr));
+                i += 2;
+            }
+            _ => i += 1,
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASM_PRN: &str = concat!(
+        "\r\n",
+        " 0100           \tORG\t100H\r\n",
+        " 0005 =         BDOS\tEQU\t5\r\n",
+        " 0100 0E09      START:\tMVI\tC,9\r\n",
+        " 0102 110B01    \tLXI\tD,MSG\r\n",
+        " 0105 CD0500    \tCALL\tBDOS\r\n",
+        "                MAINLOOP:\r\n",
+        " 0108 C30000    \tJMP\t0\r\n",
+        " 010B 484924    MSG:\tDB\t'HI$'\r\n",
+        " 010E           \tEND\r\n",
+    );
+
+    #[test]
+    fn test_parse_asm_prn() {
+        let table = SymbolTable::parse("TEST.PRN", ASM_PRN.as_bytes());
+        assert_eq!(table.get("START"), Some(0x0100));
+        assert_eq!(table.get("BDOS"), Some(0x0005));
+        assert_eq!(table.get("MAINLOOP"), Some(0x0108));
+        assert_eq!(table.get("msg"), Some(0x010B));
+        assert_eq!(table.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_lasm3_prn() {
+        let text = concat!(
+            "\x0cLASM3 11/01/84  Page  001\r\n\r\n",
+            "     1   0100             \tORG\t100H\r\n",
+            "     3   0100 0E09      START:\tMVI\tC,9\r\n",
+            "     6                  MAINLOOP:\r\n",
+            "     7   0108 C30000    \tJMP\t0\r\n",
+        );
+        let table = SymbolTable::parse_prn(text);
+        assert_eq!(table.get("START"), Some(0x0100));
+        assert_eq!(table.get("MAINLOOP"), Some(0x0108));
+        assert_eq!(table.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_z1_prn_with_table() {
+        let text = concat!(
+            " 000100 0E09      START:\tLD\tC,9\r\n",
+            " 000108           MAINLOOP:\r\n",
+            " 000108 C30000    \tJP\t0\r\n",
+            "\r\n",
+            "ASEG Symbols\r\n",
+            "\r\n",
+            "BDOS   0005 MAINLO 0108 MSG    010B START  0100 \r\n",
+            "\r\n",
+            " 0000 Error(s) Assembly Complete\r\n\x1a garbage",
+        );
+        let table = SymbolTable::parse("TEST.PRN", text.as_bytes());
+        assert_eq!(table.get("MAINLOOP"), Some(0x0108));
+        assert_eq!(table.get("MAINLO"), None);
+        assert_eq!(table.get("MSG"), Some(0x010B));
+        assert_eq!(table.get("BDOS"), Some(0x0005));
+        assert_eq!(table.len(), 4);
+    }
+
+    #[test]
+    fn test_parse_sym_and_format() {
+        let table = SymbolTable::parse("TEST.SYM", b"0100 START   0140 MAINLOOP\r\n0005 BDOS\x1a");
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.format_addr(0x0143), "MAINLOOP+3");
+        assert_eq!(table.format_addr(0x0100), "START");
+        assert_eq!(table.format_addr(0x4000), "4000");
+        assert_eq!(table.name_at(0x0005), Some("BDOS"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Pacing emulation to a target CPU clock.
+//!
+//! The T-state counter says how long the emulated CPU has been running;
+//! `Throttle` sleeps whenever that gets ahead of the host's clock. Time
+//! the emulator spends behind, blocked on console input or in a slow host
+//! call, is not made up afterwards: past `MAX_LAG` the reference point
+//! moves forward, so a program resumes at normal speed instead of running
+//! flat out to catch up.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{CpmError, CpmResult};
+
+/// Lag behind the host clock that is forgiven rather than caught up.
+pub const MAX_LAG: Duration = Duration::from_millis(20);
+
+/// Emulated time between checks of the host clock.
+const SLICE: Duration = Duration::from_millis(1);
+
+/// Keeps the T-state clock in step with the host clock.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    hz: u64,
+    /// Host time and T-states at the reference point.
+    start: Instant,
+    start_t_states: u64,
+    /// T-states at which to check again.
+    next_check: u64,
+}
+
+impl Throttle {
+    /// Run at `hz` T-states per second.
+    pub fn new(hz: u64) -> Self {
+        Self {
+            hz: hz.max(1),
+            start: Instant::now(),
+            start_t_states: 0,
+            next_check: 0,
+        }
+    }
+
+    /// Run at a clock given in MHz, such as 2 or 3.58.
+    pub fn from_mhz(mhz: f64) -> CpmResult<Self> {
+        if !(mhz.is_finite() && mhz > 0.0 && mhz <= 1000.0) {
+            return Err(CpmError::Throttle(format!(
+                "invalid clock speed: {} MHz",
+                mhz
+            )));
+        }
+        Ok(Self::new((mhz * 1e6) as u64))
+    }
+
+    /// Target clock in Hz.
+    pub fn hz(&self) -> u64 {
+        self.hz
+    }
+
+    /// Make `t_states` correspond to now.
+    pub fn resync(&mut self, t_states: u64) {
+        self.start = Instant::now();
+        self.start_t_states = t_states;
+        self.next_check = t_states + self.slice();
+    }
+
+    /// Called as the CPU runs: sleeps if the emulated time at `t_states`
+    /// is ahead of the host clock.
+    pub fn pace(&mut self, t_states: u64) {
+        if t_states < self.next_check {
+            // A clock reset (a new run) also lands here
+            if t_states < self.start_t_states {
+                self.resync(t_states);
+            }
+            return;
+        }
+        let emulated = self.emulated(t_states);
+        let elapsed = self.start.elapsed();
+        if emulated > elapsed {
+            thread::sleep(emulated - elapsed);
+        } else if elapsed - emulated > MAX_LAG {
+            self.resync(t_states);
+            return;
+        }
+        self.next_check = t_states + self.slice();
+    }
+
+    /// Emulated time since the reference point.
+    fn emulated(&self, t_states: u64) -> Duration {
+        let t = t_states - self.start_t_states;
+        Duration::from_secs(t / self.hz)
+            + Duration::from_nanos((t % self.hz) * 1_000_000_000 / self.hz)
+    }
+
+    fn slice(&self) -> u64 {
+        (self.hz / 1000).max(1) * SLICE.as_millis() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_mhz() {
+        assert_eq!(Throttle::from_mhz(4.0).unwrap().hz(), 4_000_000);
+        assert_eq!(Throttle::from_mhz(3.58).unwrap().hz(), 3_580_000);
+        assert!(Throttle::from_mhz(0.0).is_err());
+        assert!(Throttle::from_mhz(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn test_pace_sleeps_when_ahead() {
+        // 20 ms of emulated time at 1 MHz
+        let mut throttle = Throttle::new(1_000_000);
+        throttle.resync(0);
+        let start = Instant::now();
+        let mut t = 0;
+        while t < 20_000 {
+            t += 100;
+            throttle.pace(t);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(19));
+    }
+
+    #[test]
+    fn test_lag_is_forgiven() {
+        let mut throttle = Throttle::new(1_000_000);
+        throttle.resync(0);
+        // Blocked on input: no T-states for longer than MAX_LAG
+        thread::sleep(MAX_LAG + Duration::from_millis(10));
+        throttle.pace(1_000);
+        assert_eq!(throttle.start_t_states, 1_000);
+
+        // Running on from there is paced again rather than free
+        let start = Instant::now();
+        let mut t = 1_000;
+        while t < 11_000 {
+            t += 100;
+            throttle.pace(t);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(9));
+    }
+}
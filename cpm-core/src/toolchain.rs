@@ -0,0 +1,351 @@
+//! Assemblers and compilers behind one interface.
+//!
+//! A `Toolchain` turns a source file into a binary, a listing and a list
+//! of diagnostics. `ManifestToolchain` takes its tools from package
+//! manifests: the language is an action id (`bdsc`, `lasm3`, `turbo3`,
+//! ...), the action's template drives the build, and a per-tool
+//! `ToolProfile` says where the listing is and how to read errors.
+
+use crate::action::{run_action, DEFAULT_MAX_T_STATES};
+use crate::console::HeadlessConsole;
+use crate::diagnostic::{Diagnostic, ErrorParser, ASM_CODES, Z80MR_CODES};
+use crate::emulator::CpmEmulator;
+use crate::error::{CpmError, CpmResult};
+use crate::fs::{DriveFS, OverlayDriveFS};
+use crate::package::{LoadedPackage, PackageAction, PackageDriveFS};
+use crate::CpmExitInfo;
+
+/// Drive the tools are mounted on.
+pub const TOOLS_DRIVE: u8 = 0;
+/// Drive holding the source and outputs. It also sees the tools, so both
+/// `A:TOOL` and `B:LIBRARY` references in templates resolve.
+pub const BUILD_DRIVE: u8 = 1;
+
+/// A source file to build.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// CP/M file name, e.g. `HELLO.C`.
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl Source {
+    /// Source from host text: LF line endings become CR LF and a ^Z EOF
+    /// marker is appended.
+    pub fn text(name: &str, text: &str) -> Self {
+        let mut data = text
+            .replace("\r\n", "\n")
+            .replace('\n', "\r\n")
+            .into_bytes();
+        data.push(0x1A);
+        Self {
+            name: name.to_uppercase(),
+            data,
+        }
+    }
+
+    /// File name without the extension.
+    pub fn base_name(&self) -> &str {
+        self.name
+            .rsplit_once('.')
+            .map_or(&self.name, |(base, _)| base)
+    }
+}
+
+/// Outcome of a build.
+#[derive(Debug, Clone)]
+pub struct CompileResult {
+    /// First output found, in the order of the action's output extensions.
+    pub binary: Option<(String, Vec<u8>)>,
+    pub listing: Option<(String, Vec<u8>)>,
+    pub errors: Vec<Diagnostic>,
+    /// Console output of the whole build.
+    pub output: String,
+    /// Every output file created or changed.
+    pub outputs: Vec<(String, Vec<u8>)>,
+    pub exit_info: CpmExitInfo,
+}
+
+impl CompileResult {
+    /// A binary was produced and no errors were reported.
+    pub fn success(&self) -> bool {
+        self.binary.is_some() && self.errors.is_empty()
+    }
+}
+
+/// Something that can build source files.
+pub trait Toolchain {
+    /// Language ids accepted by `compile`.
+    fn languages(&self) -> Vec<String>;
+
+    /// Build `source` with the tools for `lang`.
+    fn compile(&mut self, source: &Source, lang: &str) -> CpmResult<CompileResult>;
+}
+
+/// Per-tool knowledge the manifests don't carry.
+#[derive(Debug, Clone, Copy)]
+pub struct ToolProfile {
+    /// Action id.
+    pub id: &'static str,
+    /// Listing extensions to look for.
+    pub listing_exts: &'static [&'static str],
+    pub errors: ErrorParser,
+}
+
+/// Profile for tools without an entry in `PROFILES`.
+pub const DEFAULT_PROFILE: ToolProfile = ToolProfile {
+    id: "",
+    listing_exts: &["PRN", "LST"],
+    errors: ErrorParser::Keywords(&["error", "illegal", "undefined"]),
+};
+
+/// Known tools.
+pub const PROFILES: &[ToolProfile] = &[
+    ToolProfile {
+        id: "asm",
+        listing_exts: &["PRN"],
+        errors: ErrorParser::ListingCodes(ASM_CODES),
+    },
+    ToolProfile {
+        id: "lasm3",
+        listing_exts: &["PRN", "LST"],
+        errors: ErrorParser::ListingCodes(ASM_CODES),
+    },
+    ToolProfile {
+        id: "z1",
+        listing_exts: &["PRN", "LST"],
+        errors: ErrorParser::ListingCodes(Z80MR_CODES),
+    },
+    ToolProfile {
+        id: "z80mr",
+        listing_exts: &["PRN", "LST"],
+        errors: ErrorParser::ListingCodes(Z80MR_CODES),
+    },
+    ToolProfile {
+        id: "zasm",
+        listing_exts: &["PRN"],
+        errors: ErrorParser::Keywords(&["error", "illegal", "undefined", "unrecognized"]),
+    },
+    ToolProfile {
+        id: "bdsc",
+        listing_exts: &[],
+        errors: ErrorParser::BdsC,
+    },
+    ToolProfile {
+        id: "turbo3",
+        listing_exts: &[],
+        errors: ErrorParser::Turbo,
+    },
+    ToolProfile {
+        id: "mtplus",
+        listing_exts: &[],
+        errors: ErrorParser::MtPlus,
+    },
+    ToolProfile {
+        id: "cbas2",
+        listing_exts: &[],
+        errors: ErrorParser::Keywords(&["error", "unmatched", "undefined", "illegal", "no file"]),
+    },
+];
+
+/// Profile for an action id.
+pub fn profile(id: &str) -> &'static ToolProfile {
+    PROFILES
+        .iter()
+        .find(|p| p.id.eq_ignore_ascii_case(id))
+        .unwrap_or(&DEFAULT_PROFILE)
+}
+
+/// Toolchain built from package manifests.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestToolchain {
+    tools: PackageDriveFS,
+    /// Execution budget per build.
+    pub max_t_states: Option<u64>,
+}
+
+impl ManifestToolchain {
+    pub fn new(packages: Vec<LoadedPackage>) -> Self {
+        Self {
+            tools: PackageDriveFS::from_packages(packages),
+            max_t_states: None,
+        }
+    }
+
+    /// Action for a language id.
+    pub fn action(&self, lang: &str) -> Option<&PackageAction> {
+        self.tools
+            .get_actions()
+            .iter()
+            .find(|a| a.id.eq_ignore_ascii_case(lang))
+    }
+
+    /// Language id of the first action matching a file name.
+    pub fn language_for(&self, filename: &str) -> Option<String> {
+        crate::action::find_action(self.tools.get_actions(), filename).map(|a| a.id.clone())
+    }
+}
+
+impl Toolchain for ManifestToolchain {
+    fn languages(&self) -> Vec<String> {
+        self.tools
+            .get_actions()
+            .iter()
+            .map(|a| a.id.clone())
+            .collect()
+    }
+
+    fn compile(&mut self, source: &Source, lang: &str) -> CpmResult<CompileResult> {
+        let action = self
+            .action(lang)
+            .ok_or_else(|| CpmError::Package(format!("No tool for language {}", lang)))?
+            .clone();
+        let profile = profile(&action.id);
+
+        let mut build = OverlayDriveFS::new(self.tools.clone());
+        build.write_file(&source.name, &source.data)?;
+        let mut emu = CpmEmulator::new(HeadlessConsole::new());
+        emu.mount(TOOLS_DRIVE, OverlayDriveFS::new(self.tools.clone()));
+        emu.mount(BUILD_DRIVE, build);
+
+        let max_t_states = self.max_t_states.unwrap_or(DEFAULT_MAX_T_STATES);
+        let result = run_action(&mut emu, &action, BUILD_DRIVE, &source.name, max_t_states)?;
+
+        let base = source.base_name();
+        let written = emu.drive(BUILD_DRIVE).map(|fs| fs.modified_files());
+        let listing = profile.listing_exts.iter().find_map(|ext| {
+            let name = format!("{}.{}", base, ext);
+            let data = written?.get(&name)?;
+            Some((name, data.clone()))
+        });
+        let binary = action.output_exts.iter().find_map(|ext| {
+            let name = format!("{}.{}", base, ext.to_uppercase());
+            result.outputs.iter().find(|(n, _)| *n == name).cloned()
+        });
+
+        // Keyword matches are only trusted when the build produced nothing;
+        // summaries like "Undefined Symbols: none" would flag good builds
+        let mut errors = if binary.is_none() || !profile.errors.is_heuristic() {
+            let listing_text = listing
+                .as_ref()
+                .map(|(_, data)| String::from_utf8_lossy(data).into_owned());
+            profile
+                .errors
+                .parse(&source.name, &result.output, listing_text.as_deref())
+        } else {
+            Vec::new()
+        };
+        if result.timed_out {
+            errors.push(Diagnostic::error(format!(
+                "{} did not finish within {} T-states",
+                action.name, max_t_states
+            )));
+        }
+        if binary.is_none() && errors.is_empty() {
+            errors.push(Diagnostic::error(format!(
+                "{} produced no output file",
+                action.name
+            )));
+        }
+
+        Ok(CompileResult {
+            binary,
+            listing,
+            errors,
+            output: result.output,
+            outputs: result.outputs,
+            exit_info: result.exit_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::PackageManifest;
+    use std::collections::HashMap;
+
+    /// Assembler stand-in: creates FCB1 with extension `ext` and prints
+    /// `message`.
+    fn fake_tool(ext: &str, message: &str) -> Vec<u8> {
+        let mut code = vec![
+            0x21, 0x30, 0x01, // 0100: LD HL,0130
+            0x11, 0x65, 0x00, // LD DE,0065 (FCB1 extension)
+            0x01, 0x03, 0x00, // LD BC,3
+            0xED, 0xB0, // LDIR
+            0x11, 0x5C, 0x00, // LD DE,005C
+            0x0E, 0x16, // LD C,22 (make)
+            0xCD, 0x05, 0x00, // CALL 5
+            0x11, 0x5C, 0x00, // LD DE,005C
+            0x0E, 0x10, // LD C,16 (close)
+            0xCD, 0x05, 0x00, // CALL 5
+            0x11, 0x33, 0x01, // LD DE,0133
+            0x0E, 0x09, // LD C,9
+            0xCD, 0x05, 0x00, // CALL 5
+            0xC3, 0x00, 0x00, // JP 0
+        ];
+        code.resize(0x30, 0);
+        code.extend_from_slice(ext.as_bytes()); // 0130
+        code.extend_from_slice(message.as_bytes()); // 0133
+        code.push(b'$');
+        code
+    }
+
+    fn toolchain(ext: &str, message: &str) -> ManifestToolchain {
+        let manifest: PackageManifest = serde_json::from_str(
+            r#"{
+                "name": "Fake",
+                "actions": [{
+                    "id": "fake",
+                    "name": "Fake assembler",
+                    "command": "FAKE",
+                    "patterns": ["*.FAK"],
+                    "outputExts": ["COM", "HEX"],
+                    "submit": "FAKE {drive}:{name}\r"
+                }]
+            }"#,
+        )
+        .unwrap();
+        let pkg = LoadedPackage {
+            actions: manifest.actions.clone(),
+            manifest,
+            files: HashMap::from([("FAKE.COM".to_string(), fake_tool(ext, message))]),
+        };
+        ManifestToolchain::new(vec![pkg])
+    }
+
+    #[test]
+    fn test_source_text() {
+        let source = Source::text("hello.c", "main()\n{\r\n}\n");
+        assert_eq!(source.name, "HELLO.C");
+        assert_eq!(source.base_name(), "HELLO");
+        assert_eq!(source.data, b"main()\r\n{\r\n}\r\n\x1A");
+    }
+
+    #[test]
+    fn test_compile() {
+        let mut tc = toolchain("HEX", "0 ERRORS");
+        assert_eq!(tc.languages(), ["fake"]);
+        assert_eq!(tc.language_for("x.fak").as_deref(), Some("fake"));
+
+        let result = tc
+            .compile(&Source::text("HELLO.FAK", "nop\n"), "fake")
+            .unwrap();
+        assert!(result.success(), "{:?}", result.errors);
+        assert_eq!(result.binary, Some(("HELLO.HEX".to_string(), Vec::new())));
+        assert!(result.listing.is_none());
+        assert!(result.output.contains("0 ERRORS"));
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let mut tc = toolchain("ERR", "UNDEFINED SYMBOL");
+        let result = tc
+            .compile(&Source::text("HELLO.FAK", "nop\n"), "FAKE")
+            .unwrap();
+
+        assert!(!result.success());
+        assert_eq!(result.errors, [Diagnostic::error("UNDEFINED SYMBOL")]);
+        assert!(tc.compile(&Source::text("HELLO.FAK", ""), "cobol").is_err());
+    }
+}
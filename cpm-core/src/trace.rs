@@ -0,0 +1,336 @@
+//! Structured syscall tracing.
+//!
+//! The emulator reports BDOS and CBIOS calls, file activity, warm boots and
+//! exits as typed `TraceEvent`s delivered to a `TraceSink`. Sinks are
+//! provided for human-readable text, JSON lines, and in-memory capture
+//! (for tests and trace diffing).
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// A traced emulator event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// BDOS call with decoded arguments and return registers.
+    BdosCall {
+        t_states: u64,
+        /// Return address of the caller.
+        caller: u16,
+        /// Caller as `SYMBOL+n`, when symbols are loaded.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        caller_symbol: Option<String>,
+        /// Function number (register C).
+        function: u8,
+        /// Function name, if known.
+        name: Option<String>,
+        /// Register E on entry.
+        e: u8,
+        /// Register pair DE on entry.
+        de: u16,
+        /// FCB filename for file functions (e.g. `A:FOO.TXT`).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fcb: Option<String>,
+        /// DMA address for functions that transfer through it.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        dma: Option<u16>,
+        /// Register A on return.
+        a: u8,
+        /// Register pair HL on return.
+        hl: u16,
+    },
+    /// CBIOS jump table call.
+    CbiosCall {
+        t_states: u64,
+        caller: u16,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        caller_symbol: Option<String>,
+        function: u8,
+        name: String,
+    },
+    /// File opened or created.
+    FileOpen {
+        drive: char,
+        filename: String,
+        /// Size in bytes at open time.
+        size: usize,
+        created: bool,
+    },
+    /// File closed.
+    FileClose {
+        drive: char,
+        filename: String,
+        modified: bool,
+    },
+    /// Record read (sequential or random).
+    FileRead {
+        drive: char,
+        filename: String,
+        record: u32,
+    },
+    /// Record written (sequential or random).
+    FileWrite {
+        drive: char,
+        filename: String,
+        record: u32,
+    },
+    /// File deleted.
+    FileDelete { drive: char, filename: String },
+    /// File renamed.
+    FileRename {
+        drive: char,
+        old_name: String,
+        new_name: String,
+    },
+    /// Warm boot with shell reload.
+    WarmBoot { t_states: u64 },
+    /// Program exit.
+    Exit {
+        t_states: u64,
+        pc: u16,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pc_symbol: Option<String>,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::BdosCall {
+                caller,
+                caller_symbol,
+                function,
+                name,
+                de,
+                fcb,
+                dma,
+                a,
+                hl,
+                ..
+            } => {
+                let name = name.as_deref().unwrap_or("Unknown");
+                write!(f, "[BDOS] ")?;
+                write_location(f, *caller, caller_symbol)?;
+                write!(f, " {}({}) DE={:04X}", name, function, de)?;
+                if let Some(fcb) = fcb {
+                    write!(f, " FCB={}", fcb)?;
+                }
+                if let Some(dma) = dma {
+                    write!(f, " DMA={:04X}", dma)?;
+                }
+                write!(f, " -> A={:02X} HL={:04X}", a, hl)
+            }
+            TraceEvent::CbiosCall {
+                caller,
+                caller_symbol,
+                function,
+                name,
+                ..
+            } => {
+                write!(f, "[CBIOS] ")?;
+                write_location(f, *caller, caller_symbol)?;
+                write!(f, " {}({})", name, function)
+            }
+            TraceEvent::FileOpen {
+                drive,
+                filename,
+                size,
+                created,
+            } => {
+                let verb = if *created { "create" } else { "open" };
+                write!(f, "[FILE] {} {}:{} ({} bytes)", verb, drive, filename, size)
+            }
+            TraceEvent::FileClose {
+                drive,
+                filename,
+                modified,
+            } => {
+                let note = if *modified { " (modified)" } else { "" };
+                write!(f, "[FILE] close {}:{}{}", drive, filename, note)
+            }
+            TraceEvent::FileRead {
+                drive,
+                filename,
+                record,
+            } => write!(f, "[FILE] read {}:{} record {}", drive, filename, record),
+            TraceEvent::FileWrite {
+                drive,
+                filename,
+                record,
+            } => write!(f, "[FILE] write {}:{} record {}", drive, filename, record),
+            TraceEvent::FileDelete { drive, filename } => {
+                write!(f, "[FILE] delete {}:{}", drive, filename)
+            }
+            TraceEvent::FileRename {
+                drive,
+                old_name,
+                new_name,
+            } => write!(f, "[FILE] rename {}:{} -> {}", drive, old_name, new_name),
+            TraceEvent::WarmBoot { t_states } => write!(f, "[BOOT] warm boot at {}", t_states),
+            TraceEvent::Exit {
+                t_states,
+                pc,
+                pc_symbol,
+                reason,
+            } => {
+                write!(f, "[EXIT] {} at ", reason)?;
+                write_location(f, *pc, pc_symbol)?;
+                write!(f, " after {} T-states", t_states)
+            }
+        }
+    }
+}
+
+/// Write an address as its symbol if known, otherwise as hex.
+fn write_location(
+    f: &mut std::fmt::Formatter<'_>,
+    addr: u16,
+    symbol: &Option<String>,
+) -> std::fmt::Result {
+    match symbol {
+        Some(symbol) => write!(f, "{}", symbol),
+        None => write!(f, "{:04X}", addr),
+    }
+}
+
+/// Receiver for trace events.
+pub trait TraceSink: Send {
+    /// Handle one event.
+    fn event(&mut self, event: &TraceEvent);
+}
+
+/// Writes one human-readable line per event.
+pub struct TextSink<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> TextSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write + Send> TraceSink for TextSink<W> {
+    fn event(&mut self, event: &TraceEvent) {
+        let _ = writeln!(self.out, "{}", event);
+    }
+}
+
+/// Writes one JSON object per line.
+pub struct JsonSink<W: Write + Send> {
+    out: W,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write + Send> TraceSink for JsonSink<W> {
+    fn event(&mut self, event: &TraceEvent) {
+        if let Ok(json) = serde_json::to_string(event) {
+            let _ = writeln!(self.out, "{}", json);
+        }
+    }
+}
+
+/// In-memory event log. Clone is cheap and shares the same log, so one
+/// handle can be given to the emulator and another kept for inspection.
+#[derive(Clone, Default)]
+pub struct TraceLog {
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl TraceLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copy of all events recorded so far.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.events.lock().map(|e| e.clone()).unwrap_or_default()
+    }
+
+    /// Remove all recorded events.
+    pub fn clear(&self) {
+        if let Ok(mut events) = self.events.lock() {
+            events.clear();
+        }
+    }
+}
+
+impl TraceSink for TraceLog {
+    fn event(&mut self, event: &TraceEvent) {
+        if let Ok(mut events) = self.events.lock() {
+            events.push(event.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_format() {
+        let event = TraceEvent::BdosCall {
+            t_states: 100,
+            caller: 0x0108,
+            caller_symbol: None,
+            function: 15,
+            name: Some("OpenFile".to_string()),
+            e: 0x5C,
+            de: 0x005C,
+            fcb: Some("A:FOO.TXT".to_string()),
+            dma: None,
+            a: 0,
+            hl: 0,
+        };
+
+        let mut out = Vec::new();
+        JsonSink::new(&mut out).event(&event);
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["event"], "bdos_call");
+        assert_eq!(json["name"], "OpenFile");
+        assert_eq!(json["fcb"], "A:FOO.TXT");
+        assert!(json.get("dma").is_none());
+    }
+
+    #[test]
+    fn test_text_format_with_symbol() {
+        let event = TraceEvent::Exit {
+            t_states: 100,
+            pc: 0x0143,
+            pc_symbol: Some("MAINLOOP+3".to_string()),
+            reason: "Halt".to_string(),
+        };
+        assert_eq!(
+            event.to_string(),
+            "[EXIT] Halt at MAINLOOP+3 after 100 T-states"
+        );
+    }
+
+    #[test]
+    fn test_text_format() {
+        let event = TraceEvent::FileRead {
+            drive: 'B',
+            filename: "DATA.DAT".to_string(),
+            record: 3,
+        };
+        assert_eq!(event.to_string(), "[FILE] read B:DATA.DAT record 3");
+    }
+
+    #[test]
+    fn test_trace_log_shared() {
+        let log = TraceLog::new();
+        let mut sink = log.clone();
+        sink.event(&TraceEvent::WarmBoot { t_states: 5 });
+        assert_eq!(log.events(), vec![TraceEvent::WarmBoot { t_states: 5 }]);
+        log.clear();
+        assert!(log.events().is_empty());
+    }
+}
@@ -12,6 +12,7 @@ use std::sync::{Arc, RwLock};
 
 use crate::error::{CpmError, CpmResult};
 use crate::fs::{DriveFS, MemoryDriveFS, OverlayDriveFS};
+use crate::memory::MemoryMap;
 use crate::package::{LoadedPackage, PackageDriveFS};
 
 /// Drive configuration.
@@ -49,6 +50,7 @@ pub enum FileChangeEvent {
 }
 
 /// Shared workspace state (interior of Arc<RwLock<...>>).
+#[derive(Default)]
 struct WorkspaceInner {
     /// Drive filesystems (A=0, B=1, ..., P=15)
     drives: [Option<Box<dyn DriveFS>>; 16],
@@ -58,16 +60,6 @@ struct WorkspaceInner {
     package_cache: HashMap<String, LoadedPackage>,
 }
 
-impl Default for WorkspaceInner {
-    fn default() -> Self {
-        Self {
-            drives: Default::default(),
-            configs: HashMap::new(),
-            package_cache: HashMap::new(),
-        }
-    }
-}
-
 /// CP/M Workspace - shared environment for multiple terminals.
 ///
 /// Workspaces are thread-safe and can be shared across multiple emulator instances.
@@ -233,7 +225,8 @@ impl Workspace {
     /// Find a shell from mounted packages.
     ///
     /// Searches all drives for packages with shell metadata:
-    /// - File entry with type: "shell" and optional loadAddress
+    /// - File entry with type: "shell" and optional loadAddress; .PRL
+    ///   shells are relocated there, or to the top of the TPA
     pub fn find_shell(&self) -> Option<ShellInfo> {
         let inner = self.inner.read().ok()?;
 
@@ -258,11 +251,19 @@ impl Workspace {
                                         .and_then(|s| {
                                             let s = s.trim_start_matches("0x").trim_start_matches("0X");
                                             u16::from_str_radix(s, 16).ok()
-                                        })
-                                        .unwrap_or(0x0100);
+                                        });
+                                    // .PRL shells are relocated to their address
+                                    let Ok((load_address, binary)) = crate::prl::load_shell(
+                                        &filename,
+                                        data,
+                                        load_address,
+                                        &MemoryMap::default(),
+                                    ) else {
+                                        continue;
+                                    };
 
                                     return Some(ShellInfo {
-                                        binary: data.clone(),
+                                        binary,
                                         filename,
                                         drive: letter,
                                         load_address,
@@ -306,7 +307,7 @@ impl Workspace {
 /// Convert drive letter to index (A=0, B=1, ..., P=15).
 fn drive_index(letter: char) -> CpmResult<usize> {
     let upper = letter.to_ascii_uppercase();
-    if upper >= 'A' && upper <= 'P' {
+    if ('A'..='P').contains(&upper) {
         Ok((upper as u8 - b'A') as usize)
     } else {
         Err(CpmError::InvalidDrive(letter))
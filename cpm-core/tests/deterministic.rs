@@ -0,0 +1,75 @@
+//! Deterministic mode: two runs of the same script give the same trace.
+
+use cpm_core::io::{Rtc, RtcClock};
+use cpm_core::{
+    run_script, CpmEmulator, MemoryDriveFS, ScriptStep, ScriptedConsole, Timer, TraceEvent,
+    TraceLog,
+};
+
+/// Counts timer ticks at 0206H, polls BDOS 11 for a key, reads it, then
+/// stores RTC registers 0-5 (seconds to year) at 0200H.
+const CLOCK_COM: [u8; 38] = [
+    0xED, 0x56, // 0100: IM 1
+    0xFB, // EI
+    0x0E, 0x0B, // 0103: LD C,11
+    0xCD, 0x05, 0x00, // CALL 5
+    0xB7, // OR A
+    0x28, 0xF8, // JR Z,0103
+    0x0E, 0x01, // LD C,1
+    0xCD, 0x05, 0x00, // CALL 5
+    0x21, 0x00, 0x02, // LD HL,0200
+    0x06, 0x00, // LD B,0
+    0x78, // 0115: LD A,B
+    0xD3, 0x70, // OUT (70),A
+    0xDB, 0x71, // IN A,(71)
+    0x77, // LD (HL),A
+    0x23, // INC HL
+    0x04, // INC B
+    0x78, // LD A,B
+    0xFE, 0x06, // CP 6
+    0x20, 0xF3, // JR NZ,0115
+    0xF3, // DI
+    0xC3, 0x00, 0x00, // JP 0
+];
+
+/// RST 38H: INC (0206H); EI; RETI
+const TICK: [u8; 13] = [
+    0xF5, 0x3A, 0x06, 0x02, 0x3C, 0x32, 0x06, 0x02, 0xF1, 0xFB, 0xED, 0x4D, 0x00,
+];
+
+fn run_once() -> (Vec<TraceEvent>, Vec<u8>) {
+    let console = ScriptedConsole::new(vec![ScriptStep::at(2_000_000, "X")]);
+    let mut emu: CpmEmulator<ScriptedConsole, MemoryDriveFS> = CpmEmulator::new(console);
+    emu.mount(0, MemoryDriveFS::new());
+    emu.set_deterministic(true);
+    emu.set_timer(Some(Timer::new(40_000)));
+    let rtc = Rtc::new(emu.time_source());
+    emu.io_mut().attach(0x70..=0x71, Box::new(rtc)).unwrap();
+    let log = TraceLog::new();
+    emu.set_trace_sink(Box::new(log.clone()));
+
+    emu.load_com(&CLOCK_COM);
+    emu.memory_mut()[0x38..0x38 + TICK.len()].copy_from_slice(&TICK);
+    emu.start(0x100);
+    run_script(&mut emu, 100_000_000).unwrap();
+
+    (log.events(), emu.memory()[0x200..0x207].to_vec())
+}
+
+#[test]
+fn test_identical_traces() {
+    let (first, first_results) = run_once();
+    let (second, second_results) = run_once();
+
+    assert!(first.len() > 10);
+    assert_eq!(first, second);
+    assert_eq!(first_results, second_results);
+
+    // Half a second at 4 MHz: 1978-01-01 00:00:00, and about 50 ticks
+    assert_eq!(first_results[..6], [0, 0, 0, 0x01, 0x01, 0x78]);
+    assert!((45..=55).contains(&first_results[6]));
+    assert_eq!(
+        RtcClock::virtual_time().unix_time(4_000_000),
+        RtcClock::CPM_EPOCH + 1
+    );
+}
@@ -0,0 +1,143 @@
+//! GDB stub tests using a scripted RSP client over a local TCP socket.
+#![cfg(feature = "gdb")]
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use cpm_core::gdb::{GdbStub, SessionEnd};
+use cpm_core::{CpmEmulator, ExitReason, HeadlessConsole, MemoryDriveFS};
+
+/// Minimal RSP client.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    fn send(&mut self, data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum).unwrap();
+        assert_eq!(self.read_byte(), b'+', "stub did not ack {}", data);
+        self.read_reply()
+    }
+
+    fn read_reply(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut reply = Vec::new();
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        self.read_byte();
+        self.read_byte();
+        self.stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let mut b = [0u8; 1];
+        self.stream.read_exact(&mut b).unwrap();
+        b[0]
+    }
+}
+
+/// Decode register n from a `g` reply.
+fn reg(g: &str, n: usize) -> u16 {
+    let lo = u8::from_str_radix(&g[n * 4..n * 4 + 2], 16).unwrap();
+    let hi = u8::from_str_radix(&g[n * 4 + 2..n * 4 + 4], 16).unwrap();
+    u16::from_le_bytes([lo, hi])
+}
+
+#[test]
+fn test_gdb_session() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = thread::spawn(move || {
+        let program = [
+            0x3E, 0x2A, // 0100: LD A,42
+            0x06, 0x07, // 0102: LD B,7
+            0x0E, 0x02, // 0104: LD C,2
+            0x1E, b'X', // 0106: LD E,'X'
+            0xCD, 0x05, 0x00, // 0108: CALL 5
+            0xC3, 0x00, 0x00, // 010B: JP 0
+        ];
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.load_com(&program);
+        emu.start(0x0100);
+
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        let end = stub.serve(&mut emu).unwrap();
+        (end, emu.console().output_string())
+    });
+
+    let mut client = Client {
+        stream: TcpStream::connect(("127.0.0.1", port)).unwrap(),
+    };
+
+    assert!(client.send("qSupported:swbreak+").contains("PacketSize"));
+    assert_eq!(client.send("?"), "S05");
+
+    let g = client.send("g");
+    assert_eq!(reg(&g, 5), 0x0100, "PC");
+
+    // Breakpoint and continue
+    assert_eq!(client.send("Z0,104,1"), "OK");
+    assert_eq!(client.send("c"), "S05");
+    let g = client.send("g");
+    assert_eq!(reg(&g, 5), 0x0104, "PC at breakpoint");
+    assert_eq!(reg(&g, 0) >> 8, 42, "A");
+    assert_eq!(reg(&g, 1) >> 8, 7, "B");
+
+    // Single step
+    assert_eq!(client.send("s"), "S05");
+    assert_eq!(client.send("p5"), "0601");
+
+    // Memory read and patch the character being printed
+    assert_eq!(client.send("m106,2"), "1e58");
+    assert_eq!(client.send("M107,1:59"), "OK");
+
+    // Run to the BDOS exit
+    assert_eq!(client.send("z0,104,1"), "OK");
+    assert_eq!(client.send("c"), "W00");
+
+    let (end, output) = server.join().unwrap();
+    match end {
+        SessionEnd::Exited(info) => assert_eq!(info.reason, ExitReason::WarmBoot),
+        other => panic!("unexpected session end: {:?}", other),
+    }
+    assert_eq!(output, "Y");
+}
+
+#[test]
+fn test_gdb_interrupt() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = thread::spawn(move || {
+        // 0100: JR 0100 (spin forever)
+        let mut emu: CpmEmulator<HeadlessConsole, MemoryDriveFS> =
+            CpmEmulator::new(HeadlessConsole::new());
+        emu.load_com(&[0x18, 0xFE]);
+        emu.start(0x0100);
+
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        stub.serve(&mut emu).unwrap()
+    });
+
+    let mut client = Client {
+        stream: TcpStream::connect(("127.0.0.1", port)).unwrap(),
+    };
+
+    let checksum = b'c';
+    write!(client.stream, "$c#{:02x}", checksum).unwrap();
+    assert_eq!(client.read_byte(), b'+');
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.read_reply(), "S02");
+
+    assert_eq!(client.send("D"), "OK");
+    assert!(matches!(server.join().unwrap(), SessionEnd::Detached));
+}
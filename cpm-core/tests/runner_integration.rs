@@ -0,0 +1,54 @@
+//! Package tests through `CpmRunner`: assemble, load and run a program.
+
+use cpm_core::runner::{RunOptions, SOURCE_DRIVE};
+use cpm_core::{load_package_from_path, CpmRunner, ExitReason};
+use std::path::PathBuf;
+
+fn get_package_path(name: &str) -> PathBuf {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    PathBuf::from(manifest_dir)
+        .parent()
+        .unwrap()
+        .join("win95-sim/public/cpm")
+        .join(name)
+}
+
+const HELLO_ASM: &str = "\tORG\t100H\r\n\
+\tMVI\tC,9\r\n\
+\tLXI\tD,MSG\r\n\
+\tCALL\t5\r\n\
+\tRET\r\n\
+MSG:\tDB\t'Hello from ASM$'\r\n\
+\tEND\r\n\x1A";
+
+#[test]
+fn test_asm_load_run() {
+    let path = get_package_path("cpm22.zip");
+    if !path.exists() {
+        eprintln!("Skipping test - cpm22.zip not found at {:?}", path);
+        return;
+    }
+
+    let mut runner = CpmRunner::new();
+    runner.add_package(load_package_from_path(&path).expect("Failed to load cpm22.zip"));
+    runner
+        .add_source("HELLO.ASM", HELLO_ASM.as_bytes())
+        .unwrap();
+
+    let options = RunOptions {
+        working_drive: Some(SOURCE_DRIVE),
+        ..RunOptions::args("HELLO")
+    };
+    let result = runner.run("ASM", &options).unwrap();
+    assert!(!result.timed_out, "{}", result.output);
+    assert!(result.new_files.contains(&"A:HELLO.HEX".to_string()));
+    assert!(result.new_files.contains(&"A:HELLO.PRN".to_string()));
+
+    let result = runner.run("LOAD", &options).unwrap();
+    assert_eq!(result.new_files, ["A:HELLO.COM"], "{}", result.output);
+
+    let result = runner.run("A:HELLO", &RunOptions::default()).unwrap();
+    assert_eq!(result.exit_info.reason, ExitReason::WarmBoot);
+    assert!(result.output.contains("Hello from ASM"));
+    assert!(result.new_files.is_empty());
+}